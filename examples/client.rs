@@ -2,7 +2,7 @@ use lds::ldap::{DerefAliases, Message, MessageParams, MsgSearch, SearchScope};
 use std::io::Result;
 
 async fn client_example() -> Result<()> {
-    let connection = lds::client::connect("127.0.0.1:389").await?;
+    let connection = lds::client::connect("ldap://127.0.0.1:389").await?;
     let res = connection.send_request_bind("used", "password").await?;
     println!("response: {:?}", res);
     if res.res == 0 {
@@ -18,7 +18,10 @@ async fn client_example() -> Result<()> {
                     }),
                     size_limit: 0,
                     time_limit: 0,
+                    types_only: false,
+                    attributes: Vec::new(),
                 }),
+                controls: Vec::new(),
             })
             .await;
         println!("response2: {:?}", res2);
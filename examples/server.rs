@@ -26,7 +26,7 @@ impl lds::server::Service for Test1 {
                     let mut resp1 = codec::ldap_write_search_res_entry(
                         id,
                         "n1",
-                        &vec![
+                        &[
                             PartialAttribute {
                                 name: "a1".to_owned(),
                                 values: vec!["aaa".to_owned(), "bbbb".to_owned()],
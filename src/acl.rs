@@ -0,0 +1,470 @@
+use crate::codec;
+use crate::dn::Dn;
+use crate::ldap::{self, Filter, Message, MessageParams};
+use std::io::Result;
+
+// write includes read, auth only lets a password be used to bind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    None,
+    Auth,
+    Read,
+    Write,
+}
+
+// rules without attrs= give anyone but the entry itself at most auth on these
+const PROTECTED_ATTRIBUTES: &[&str] = &["userPassword"];
+
+// compares attribute descriptions without their options, userPassword;binary is userPassword
+fn same_attribute(a: &str, b: &str) -> bool {
+    let base = |s: &str| s.split(';').next().unwrap_or_default().to_owned();
+    base(a).eq_ignore_ascii_case(&base(b))
+}
+
+// the attributes a search filter tests, None for an extensible match on every attribute
+fn filter_attributes(f: &Filter, out: &mut Vec<Option<String>>) {
+    match f {
+        Filter::Empty() => {}
+        Filter::EqualityMatch(a)
+        | Filter::GreaterOrEqual(a)
+        | Filter::LessOrEqual(a)
+        | Filter::ApproxMatch(a) => out.push(Some(a.name.clone())),
+        Filter::Present(p) => out.push(Some(p.name.clone())),
+        Filter::Substrings(s) => out.push(Some(s.name.clone())),
+        Filter::ExtensibleMatch(e) => out.push(e.name.clone()),
+        Filter::And(a) => a.items.iter().for_each(|f| filter_attributes(f, out)),
+        Filter::Or(o) => o.items.iter().for_each(|f| filter_attributes(f, out)),
+        Filter::Not(f) => filter_attributes(f, out),
+    }
+}
+
+// who a rule is about, matched against the dn the connection is bound as
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject {
+    Anyone,
+    Anonymous,
+    Authenticated,
+    // the entry the connection is bound as
+    SelfEntry,
+    Dn(Dn),
+    Subtree(Dn),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessRule {
+    // the rule covers this entry and everything below it, the root dn covers all entries
+    pub target: Dn,
+    // the attributes the rule is about, empty for the entry and all of its attributes
+    pub attrs: Vec<String>,
+    pub subject: Subject,
+    pub access: Access,
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+// splits on whitespace, double quotes keep dns with spaces together
+fn tokens(s: &str) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    out.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if quoted {
+        return Err(invalid(format!("unterminated quote in {}", s)));
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    Ok(out)
+}
+
+impl AccessRule {
+    // "to <dn|*> [attrs=<attr,...>] by <*|anonymous|users|self|dn=<dn>|dn.subtree=<dn>>
+    // <none|auth|read|write>"
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut t = tokens(s)?;
+        let attrs = match t.get(2).and_then(|a| a.strip_prefix("attrs=")) {
+            Some(a) => {
+                let attrs: Vec<String> = a.split(',').map(|a| a.trim().to_owned()).collect();
+                if attrs.iter().any(|a| a.is_empty()) {
+                    return Err(invalid(format!("empty attribute in {}", s)));
+                }
+                t.remove(2);
+                attrs
+            }
+            None => Vec::new(),
+        };
+        let [to, target, by, subject, access] = t.as_slice() else {
+            return Err(invalid(format!(
+                "expected to <dn> [attrs=<attrs>] by <who> <access>: {}",
+                s
+            )));
+        };
+        if to != "to" || by != "by" {
+            return Err(invalid(format!(
+                "expected to <dn> [attrs=<attrs>] by <who> <access>: {}",
+                s
+            )));
+        }
+        let target = if target == "*" {
+            Dn::root()
+        } else {
+            Dn::parse(target)?
+        };
+        let subject = match subject.as_str() {
+            "*" => Subject::Anyone,
+            "anonymous" => Subject::Anonymous,
+            "users" => Subject::Authenticated,
+            "self" => Subject::SelfEntry,
+            s => match s.split_once('=') {
+                Some(("dn", dn)) => Subject::Dn(Dn::parse(dn)?),
+                Some(("dn.subtree", dn)) => Subject::Subtree(Dn::parse(dn)?),
+                _ => return Err(invalid(format!("unknown subject {}", s))),
+            },
+        };
+        let access = match access.as_str() {
+            "none" => Access::None,
+            "auth" => Access::Auth,
+            "read" => Access::Read,
+            "write" => Access::Write,
+            a => return Err(invalid(format!("unknown access level {}", a))),
+        };
+        Ok(Self {
+            target,
+            attrs,
+            subject,
+            access,
+        })
+    }
+
+    fn covers(&self, entry: &Dn) -> bool {
+        self.target.is_root() || *entry == self.target || entry.is_descendant_of(&self.target)
+    }
+
+    fn covers_attribute(&self, attr: &str) -> bool {
+        self.attrs.is_empty() || self.attrs.iter().any(|a| same_attribute(a, attr))
+    }
+
+    fn applies_to(&self, bound: &Dn, entry: &Dn) -> bool {
+        match &self.subject {
+            Subject::Anyone => true,
+            Subject::Anonymous => bound.is_root(),
+            Subject::Authenticated => !bound.is_root(),
+            Subject::SelfEntry => !bound.is_root() && bound == entry,
+            Subject::Dn(dn) => bound == dn,
+            Subject::Subtree(dn) => bound == dn || bound.is_descendant_of(dn),
+        }
+    }
+}
+
+// the first rule that covers an entry and applies to the bound dn decides, no rule means no access
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    rules: Vec<AccessRule>,
+    // bypasses all rules
+    root: Option<Dn>,
+}
+
+impl AccessControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_root(&mut self, dn: Dn) {
+        self.root = Some(dn);
+    }
+
+    pub fn add_rule(&mut self, rule: AccessRule) {
+        self.rules.push(rule);
+    }
+
+    fn is_root(&self, dn: &Dn) -> bool {
+        self.root.as_ref().is_some_and(|r| r == dn && !r.is_root())
+    }
+
+    // access to the entry itself, rules with attrs= do not count
+    pub fn allowed(&self, bound: &Dn, entry: &Dn, access: Access) -> bool {
+        if self.is_root(bound) {
+            return true;
+        }
+        self.rules
+            .iter()
+            .find(|r| r.attrs.is_empty() && r.covers(entry) && r.applies_to(bound, entry))
+            .is_some_and(|r| r.access >= access)
+    }
+
+    pub fn allowed_attribute(&self, bound: &Dn, entry: &Dn, attr: &str, access: Access) -> bool {
+        if self.is_root(bound) {
+            return true;
+        }
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|r| r.covers(entry) && r.covers_attribute(attr) && r.applies_to(bound, entry))
+        else {
+            return false;
+        };
+        let protected = PROTECTED_ATTRIBUTES.iter().any(|a| same_attribute(a, attr));
+        let granted = if rule.attrs.is_empty() && protected && bound != entry {
+            rule.access.min(Access::Auth)
+        } else {
+            rule.access
+        };
+        granted >= access
+    }
+
+    // the attributes an extensible match without a name may have tested
+    fn sensitive_attributes(&self) -> Vec<String> {
+        let mut attrs: Vec<String> = PROTECTED_ATTRIBUTES.iter().map(|a| a.to_string()).collect();
+        attrs.extend(self.rules.iter().flat_map(|r| r.attrs.iter().cloned()));
+        attrs
+    }
+
+    // whether the bound dn may send a request. on top of the entry access required() names the
+    // attributes it reads or writes are checked, binding needs auth on the password, moving an
+    // entry needs write on its new parent and changing a password write on userPassword.
+    pub fn permits(&self, bound: &Dn, params: &MessageParams) -> bool {
+        if self.is_root(bound) {
+            return true;
+        }
+        // a dn that does not parse can not be checked against the rules, so it is refused
+        if let Some((dn, access)) = Self::required(params) {
+            match dn {
+                Ok(dn) if self.allowed(bound, &dn, access) => {}
+                _ => return false,
+            }
+        }
+        match params {
+            MessageParams::Bind(b) => match b.dn() {
+                Ok(dn) if !dn.is_root() && !self.is_root(&dn) => {
+                    self.allowed_attribute(bound, &dn, "userPassword", Access::Auth)
+                }
+                Ok(_) => true,
+                Err(_) => false,
+            },
+            MessageParams::Compare(c) => c
+                .dn()
+                .is_ok_and(|dn| self.allowed_attribute(bound, &dn, &c.name, Access::Read)),
+            MessageParams::Add(a) => a.dn().is_ok_and(|dn| {
+                a.attributes
+                    .iter()
+                    .all(|x| self.allowed_attribute(bound, &dn, &x.name, Access::Write))
+            }),
+            MessageParams::Modify(m) => m.dn().is_ok_and(|dn| {
+                m.changes.iter().all(|c| {
+                    self.allowed_attribute(bound, &dn, &c.modification.name, Access::Write)
+                })
+            }),
+            MessageParams::ModDn(m) => match m.new_superior.as_deref().map(Dn::parse) {
+                Some(Ok(parent)) => self.allowed(bound, &parent, Access::Write),
+                Some(Err(_)) => false,
+                None => true,
+            },
+            // requests without a user are refused by the service
+            MessageParams::ExtendedRequest(r) if r.name == ldap::PASSWORD_MODIFY_REQUEST => {
+                match r.value.as_deref().map(codec::ldap_read_password_modify) {
+                    Some(Ok((Some(user), _, _))) => Dn::parse(&user).is_ok_and(|dn| {
+                        self.allowed_attribute(bound, &dn, "userPassword", Access::Write)
+                    }),
+                    Some(Err(_)) => false,
+                    _ => true,
+                }
+            }
+            _ => true,
+        }
+    }
+
+    // the entry and access a request needs, None for requests not about an entry
+    pub fn required(params: &MessageParams) -> Option<(Result<Dn>, Access)> {
+        let (dn, access) = match params {
+            MessageParams::Search(s) => (s.base_dn(), Access::Read),
+            MessageParams::Compare(c) => (c.dn(), Access::Read),
+            MessageParams::Add(a) => (a.dn(), Access::Write),
+            MessageParams::Modify(m) => (m.dn(), Access::Write),
+            MessageParams::Del(d) => (d.dn(), Access::Write),
+            MessageParams::ModDn(m) => (m.dn(), Access::Write),
+            _ => return None,
+        };
+        Some((dn, access))
+    }
+
+    // drops the entries of an encoded search response the bound dn may not read and the
+    // attributes it may not read from the rest. entries the filter matched on such an attribute
+    // are dropped too, or the filter could be used to guess its values.
+    pub fn filter_entries(&self, bound: &Dn, filter: &Filter, resp: Vec<u8>) -> Result<Vec<u8>> {
+        if self.is_root(bound) {
+            return Ok(resp);
+        }
+        let mut tested = Vec::new();
+        filter_attributes(filter, &mut tested);
+        let tested: Vec<String> = tested
+            .into_iter()
+            .flat_map(|a| match a {
+                Some(a) => vec![a],
+                None => self.sensitive_attributes(),
+            })
+            .collect();
+        let mut out = Vec::with_capacity(resp.len());
+        let mut data = resp.as_slice();
+        while !data.is_empty() {
+            let (mut m, size) = Message::decode(data)?;
+            let raw = &data[..size];
+            data = &data[size..];
+            let MessageParams::SearchResult(e) = &mut m.params else {
+                out.extend_from_slice(raw);
+                continue;
+            };
+            let Ok(dn) = e.dn() else {
+                continue;
+            };
+            let readable = |attr: &str| self.allowed_attribute(bound, &dn, attr, Access::Read);
+            if !self.allowed(bound, &dn, Access::Read) || !tested.iter().all(|a| readable(a)) {
+                continue;
+            }
+            let n = e.values.len();
+            e.values.retain(|a| readable(&a.name));
+            if e.values.len() == n {
+                out.extend_from_slice(raw);
+            } else {
+                out.extend(m.encode()?);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[test]
+fn access_control_test() {
+    let dn = |s: &str| Dn::parse(s).unwrap();
+    let mut ac = AccessControl::new();
+    ac.set_root(dn("cn=root,dc=example"));
+    for rule in [
+        r#"to "ou=secret, dc=example" by dn.subtree=ou=admins,dc=example read"#,
+        "to ou=secret,dc=example by * none",
+        "to ou=people,dc=example attrs=userPassword by self write",
+        "to ou=people,dc=example attrs=userPassword by anonymous auth",
+        "to ou=people,dc=example by self write",
+        "to ou=people,dc=example by anonymous none",
+        "to * by users read",
+        "to dc=example by anonymous read",
+    ] {
+        ac.add_rule(AccessRule::parse(rule).unwrap());
+    }
+    let anon = Dn::root();
+    let alice = dn("uid=alice,ou=people,dc=example");
+    let admin = dn("cn=a,ou=admins,dc=example");
+
+    assert!(ac.allowed(&anon, &dn("dc=example"), Access::Read));
+    assert!(!ac.allowed(&anon, &alice, Access::Read));
+    assert!(ac.allowed(&alice, &alice, Access::Write));
+    let bob = dn("uid=bob,ou=people,dc=example");
+    assert!(ac.allowed(&alice, &bob, Access::Read));
+    assert!(!ac.allowed(&alice, &bob, Access::Write));
+    assert!(ac.allowed(&alice, &dn("ou=groups,dc=example"), Access::Read));
+    assert!(!ac.allowed(&alice, &dn("ou=groups,dc=example"), Access::Write));
+    assert!(!ac.allowed(&alice, &dn("cn=x,ou=secret,dc=example"), Access::Read));
+    assert!(ac.allowed(&admin, &dn("cn=x,ou=secret,dc=example"), Access::Read));
+    assert!(ac.allowed(
+        &dn("CN=Root,DC=example"),
+        &dn("cn=x,ou=secret,dc=example"),
+        Access::Write
+    ));
+
+    let password =
+        |who: &Dn, entry: &Dn, access| ac.allowed_attribute(who, entry, "userPassword", access);
+    assert!(password(&alice, &alice, Access::Write));
+    assert!(password(&anon, &alice, Access::Auth));
+    assert!(!password(&anon, &alice, Access::Read));
+    // "to * by users read" does not give read on someone else's password
+    assert!(password(&alice, &bob, Access::Auth));
+    assert!(!password(&alice, &bob, Access::Read));
+    assert!(ac.allowed_attribute(&alice, &bob, "cn", Access::Read));
+    let compare = |name: &str| {
+        MessageParams::Compare(crate::ldap::MsgCompare {
+            entry: bob.to_string(),
+            name: name.to_owned(),
+            value: "guess".to_owned(),
+        })
+    };
+    assert!(!ac.permits(&alice, &compare("userPassword;binary")));
+    assert!(ac.permits(&alice, &compare("cn")));
+    let bind = MessageParams::Bind(crate::ldap::MsgBind {
+        version: 3,
+        name: alice.to_string(),
+        password: "pw".to_owned(),
+    });
+    assert!(ac.permits(&anon, &bind));
+    let passwd = |user: &Dn| {
+        MessageParams::ExtendedRequest(crate::ldap::MsgExtendedRequest {
+            name: crate::ldap::PASSWORD_MODIFY_REQUEST.to_owned(),
+            value: Some(
+                codec::ldap_write_password_modify(Some(&user.to_string()), None, None).unwrap(),
+            ),
+        })
+    };
+    assert!(ac.permits(&alice, &passwd(&alice)));
+    assert!(!ac.permits(&alice, &passwd(&bob)));
+    let moddn = |superior: Option<&str>| {
+        MessageParams::ModDn(crate::ldap::MsgModDn {
+            entry: alice.to_string(),
+            new_rdn: "uid=alice2".to_owned(),
+            delete_old_rdn: false,
+            new_superior: superior.map(|s| s.to_owned()),
+        })
+    };
+    // alice may rename her entry but only read the entries she would move it below
+    assert!(ac.permits(&alice, &moddn(None)));
+    assert!(!ac.permits(&alice, &moddn(Some("ou=people,dc=example"))));
+    assert!(!ac.permits(&alice, &moddn(Some("ou=people,,dc=example"))));
+    assert!(!ac.permits(&alice, &moddn(Some("not a dn"))));
+    let bad_compare = MessageParams::Compare(crate::ldap::MsgCompare {
+        entry: "uid=bob,,dc=example".to_owned(),
+        name: "cn".to_owned(),
+        value: "bob".to_owned(),
+    });
+    assert!(!ac.permits(&alice, &bad_compare));
+
+    assert!(AccessRule::parse("to * attrs=cn, by * read").is_err());
+    assert!(AccessRule::parse("to * by nobody read").is_err());
+    assert!(AccessRule::parse("to \"dc=example by * read").is_err());
+    assert!(AccessRule::parse("to * by * delete").is_err());
+
+    let mut resp = crate::codec::ldap_write_search_res_entry(1, "dc=example", &[]).unwrap();
+    resp.extend(crate::codec::ldap_write_search_res_entry(1, &alice.to_string(), &[]).unwrap());
+    resp.extend(crate::codec::ldap_write_search_res_done(1, 0).unwrap());
+    let all = crate::filter::parse("(objectClass=*)").unwrap();
+    let filtered = ac.filter_entries(&anon, &all, resp).unwrap();
+    let (m, size) = Message::decode(&filtered).unwrap();
+    assert!(matches!(&m.params, MessageParams::SearchResult(e) if e.name == "dc=example"));
+    let (m, _) = Message::decode(&filtered[size..]).unwrap();
+    assert!(matches!(m.params, MessageParams::MsgSearchResultDone(_)));
+
+    let attrs = [
+        crate::ldap::PartialAttribute {
+            name: "cn".to_owned(),
+            values: vec!["bob".to_owned()],
+        },
+        crate::ldap::PartialAttribute {
+            name: "userPassword".to_owned(),
+            values: vec!["builder".to_owned()],
+        },
+    ];
+    let resp = crate::codec::ldap_write_search_res_entry(1, &bob.to_string(), &attrs).unwrap();
+    let filtered = ac.filter_entries(&alice, &all, resp.clone()).unwrap();
+    let (m, _) = Message::decode(&filtered).unwrap();
+    assert!(matches!(&m.params, MessageParams::SearchResult(e) if e.values == attrs[..1]));
+    assert_eq!(ac.filter_entries(&bob, &all, resp.clone()).unwrap(), resp);
+    let guess = crate::filter::parse("(|(cn=x)(userPassword=b*))").unwrap();
+    assert!(ac.filter_entries(&alice, &guess, resp).unwrap().is_empty());
+}
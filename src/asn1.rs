@@ -35,11 +35,27 @@ pub fn read_uint(cursor: &mut Cursor<&[u8]>) -> Result<u32> {
     Ok(out)
 }
 
+// the length comes from the peer, it is checked against what is left before allocating
+pub fn read_bytes(cursor: &mut Cursor<&[u8]>, size: usize) -> Result<Vec<u8>> {
+    let left = cursor
+        .get_ref()
+        .len()
+        .saturating_sub(cursor.position() as usize);
+    if size > left {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("length {} is past the end of the message", size),
+        ));
+    }
+    let mut buf = vec![0; size];
+    cursor.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
 pub fn read_string(cursor: &mut Cursor<&[u8]>) -> Result<String> {
     read_tag(cursor)?;
     let size = read_size(cursor)?;
-    let mut buf = vec![0; size];
-    cursor.read_exact(&mut buf)?;
+    let buf = read_bytes(cursor, size)?;
     match std::str::from_utf8(&buf) {
         Ok(s) => Ok(s.to_owned()),
         Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
@@ -50,8 +66,20 @@ pub fn write_tag(buf: &mut Vec<u8>, tag: u8) -> Result<()> {
     buf.write_u8(tag)
 }
 
-pub fn asn1_write_len(buf: &mut Vec<u8>, len: u8) -> Result<()> {
-    buf.write_u8(len)
+fn len_bytes(len: usize) -> Vec<u8> {
+    if len < 0x80 {
+        return vec![len as u8];
+    }
+    let be = (len as u64).to_be_bytes();
+    let skip = be.iter().take_while(|b| **b == 0).count();
+    let mut out = vec![0x80 | (be.len() - skip) as u8];
+    out.extend_from_slice(&be[skip..]);
+    out
+}
+
+pub fn asn1_write_len(buf: &mut Vec<u8>, len: usize) -> Result<()> {
+    buf.extend_from_slice(&len_bytes(len));
+    Ok(())
 }
 
 pub fn write_enum(buf: &mut Vec<u8>, val: u8) -> Result<()> {
@@ -61,12 +89,16 @@ pub fn write_enum(buf: &mut Vec<u8>, val: u8) -> Result<()> {
 }
 fn write_octet_string(buf: &mut Vec<u8>, val: &[u8]) -> Result<()> {
     write_tag(buf, 0x4)?;
-    asn1_write_len(buf, val.len() as u8)?;
+    asn1_write_len(buf, val.len())?;
     buf.extend_from_slice(val);
     Ok(())
 }
 fn write_bool(buf: &mut Vec<u8>, val: bool) -> Result<()> {
-    write_tag(buf, 0x1)?;
+    write_bool_with_tag(buf, 0x1, val)
+}
+
+fn write_bool_with_tag(buf: &mut Vec<u8>, tag: u8, val: bool) -> Result<()> {
+    write_tag(buf, tag)?;
     asn1_write_len(buf, 1)?;
     if val {
         buf.write_u8(0xff)?;
@@ -78,13 +110,17 @@ fn write_bool(buf: &mut Vec<u8>, val: bool) -> Result<()> {
 
 fn write_octet_string_with_tag(buf: &mut Vec<u8>, tag: u8, val: &[u8]) -> Result<()> {
     write_tag(buf, tag)?;
-    asn1_write_len(buf, val.len() as u8)?;
+    asn1_write_len(buf, val.len())?;
     buf.extend_from_slice(val);
     Ok(())
 }
 
 pub fn write_int(buf: &mut Vec<u8>, val: u32) -> Result<()> {
-    write_tag(buf, 0x2)?;
+    write_int_with_tag(buf, 0x2, val)
+}
+
+pub fn write_int_with_tag(buf: &mut Vec<u8>, tag: u8, val: u32) -> Result<()> {
+    write_tag(buf, tag)?;
     if val < 0x80 {
         asn1_write_len(buf, 1)?;
         buf.write_u8(val as u8)
@@ -97,8 +133,14 @@ pub fn write_int(buf: &mut Vec<u8>, val: u32) -> Result<()> {
         buf.write_u8((val >> 16) as u8)?;
         buf.write_u8((val >> 8) as u8)?;
         buf.write_u8(val as u8)
+    } else if val < 0x80000000 {
+        asn1_write_len(buf, 4)?;
+        buf.write_u32::<byteorder::BigEndian>(val)
     } else {
-        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+        // leading zero keeps it positive
+        asn1_write_len(buf, 5)?;
+        buf.write_u8(0)?;
+        buf.write_u32::<byteorder::BigEndian>(val)
     }
 }
 
@@ -136,9 +178,14 @@ impl Encoder {
         let i = self.stack.pop();
         if let Some(a) = i {
             let s = self.buffer.len() - a.pos - 2;
-            self.buffer[a.pos + 1] = s as u8;
+            let len = len_bytes(s);
+            self.buffer.splice(a.pos + 1..a.pos + 2, len);
         }
     }
+    // already encoded elements
+    pub fn write_raw(&mut self, val: &[u8]) {
+        self.buffer.extend_from_slice(val);
+    }
     pub fn write_octet_string(&mut self, val: &[u8]) -> Result<()> {
         write_octet_string(&mut self.buffer, val)
     }
@@ -148,12 +195,18 @@ impl Encoder {
     pub fn write_enum(&mut self, val: u8) -> Result<()> {
         write_enum(&mut self.buffer, val)
     }
+    pub fn write_int_with_tag(&mut self, tag: u8, val: u32) -> Result<()> {
+        write_int_with_tag(&mut self.buffer, tag, val)
+    }
     pub fn write_int(&mut self, val: u32) -> Result<()> {
         write_int(&mut self.buffer, val)
     }
     pub fn write_bool(&mut self, val: bool) -> Result<()> {
         write_bool(&mut self.buffer, val)
     }
+    pub fn write_bool_with_tag(&mut self, tag: u8, val: bool) -> Result<()> {
+        write_bool_with_tag(&mut self.buffer, tag, val)
+    }
 
     pub fn encode(mut self) -> Vec<u8> {
         self.fix();
@@ -174,6 +227,10 @@ fn a_test() {
         10034
     );
     assert_eq!(read_size(&mut std::io::Cursor::new(&[0x08])).unwrap(), 8);
+    let huge: &[u8] = &[
+        0x04, 0x88, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x61,
+    ];
+    assert!(read_string(&mut std::io::Cursor::new(huge)).is_err());
     let mut buf = Vec::new();
     write_int(&mut buf, 127).unwrap();
     assert_eq!(buf, vec![0x02, 0x01, 0x7f]);
@@ -189,4 +246,14 @@ fn a_test() {
     let mut buf = Vec::new();
     write_bool(&mut buf, true).unwrap();
     assert_eq!(buf, vec![0x01, 0x01, 0xff]);
+
+    let mut e = Encoder::new();
+    e.start_seq(0x30).unwrap();
+    e.write_octet_string(&[0x61; 300]).unwrap();
+    let buf = e.encode();
+    assert_eq!(buf[..8], [0x30, 0x82, 0x01, 0x30, 0x04, 0x82, 0x01, 0x2c]);
+    assert_eq!(buf.len(), 308);
+    let mut cursor = std::io::Cursor::new(buf.as_slice());
+    read_tag(&mut cursor).unwrap();
+    assert_eq!(read_size(&mut cursor).unwrap(), 304);
 }
@@ -0,0 +1,514 @@
+use lds::client::{self, ClientConnection};
+use lds::ldap::{self, Change, Message, MessageParams, ModifyOperation, MsgModify, MsgSearch};
+use lds::tokenbucket::{SharedTokenBucket, TokenBucket};
+use std::io::{Result, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: ldap-bench [options]
+  -H url          server to load, default ldap://127.0.0.1:389
+  -D dn -w pass   bind as dn before starting and for bind operations
+  -c n            connections, default 4
+  -r n            target operations per second over all connections, default 1000
+  -t secs         how long to run, default 10
+  -n ops          stop after this many operations instead
+  -m mix          operation weights, default search=8,bind=1,modify=1
+  -b base         search base, default empty
+  -f filter       search filter template, default (objectClass=*)
+  -M dn           modify target template, modify is skipped without it
+  -a attr         attribute replaced by modify, default description
+  -R n            {rand} in templates is replaced by 0..n, default 1000
+environment:
+  LDAPTLS_CACERT  pem file with ca certificates trusted for ldaps next to the public roots";
+
+#[derive(Clone)]
+struct Config {
+    url: String,
+    bind_dn: String,
+    password: String,
+    connections: usize,
+    rate: f64,
+    duration: Duration,
+    max_ops: Option<u64>,
+    mix: Vec<(Op, u32)>,
+    base: String,
+    filter: String,
+    modify_dn: Option<String>,
+    modify_attr: String,
+    range: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Search,
+    Bind,
+    Modify,
+}
+
+impl Op {
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Search => "search",
+            Op::Bind => "bind",
+            Op::Modify => "modify",
+        }
+    }
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+fn parse_args(args: &[String]) -> Result<Config> {
+    let mut c = Config {
+        url: "ldap://127.0.0.1:389".to_owned(),
+        bind_dn: String::new(),
+        password: String::new(),
+        connections: 4,
+        rate: 1000.0,
+        duration: Duration::from_secs(10),
+        max_ops: None,
+        mix: vec![(Op::Search, 8), (Op::Bind, 1), (Op::Modify, 1)],
+        base: String::new(),
+        filter: "(objectClass=*)".to_owned(),
+        modify_dn: None,
+        modify_attr: "description".to_owned(),
+        range: 1000,
+    };
+    let mut it = args.iter();
+    while let Some(flag) = it.next() {
+        if flag == "-h" || flag == "--help" {
+            return Err(invalid(USAGE.to_owned()));
+        }
+        let value = it
+            .next()
+            .ok_or_else(|| invalid(format!("{} needs a value\n{}", flag, USAGE)))?;
+        let number = |v: &str| {
+            v.parse::<f64>()
+                .map_err(|_| invalid(format!("{}: not a number: {}", flag, v)))
+        };
+        match flag.as_str() {
+            "-H" => c.url = value.clone(),
+            "-D" => c.bind_dn = value.clone(),
+            "-w" => c.password = value.clone(),
+            "-c" => c.connections = (number(value)? as usize).max(1),
+            "-r" => {
+                c.rate = number(value)?;
+                if c.rate.is_nan() || c.rate <= 0.0 {
+                    return Err(invalid(format!("-r must be above 0: {}", value)));
+                }
+            }
+            "-t" => c.duration = Duration::from_secs_f64(number(value)?),
+            "-n" => c.max_ops = Some(number(value)? as u64),
+            "-m" => c.mix = parse_mix(value)?,
+            "-b" => c.base = value.clone(),
+            "-f" => c.filter = value.clone(),
+            "-M" => c.modify_dn = Some(value.clone()),
+            "-a" => c.modify_attr = value.clone(),
+            "-R" => c.range = (number(value)? as u64).max(1),
+            _ => return Err(invalid(format!("unknown option {}\n{}", flag, USAGE))),
+        }
+    }
+    if c.modify_dn.is_none() {
+        c.mix.retain(|(op, _)| *op != Op::Modify);
+    }
+    if c.mix.iter().all(|(_, w)| *w == 0) {
+        return Err(invalid("nothing to run in the operation mix".to_owned()));
+    }
+    lds::filter::parse(&c.filter.replace("{rand}", "0"))?;
+    Ok(c)
+}
+
+fn parse_mix(s: &str) -> Result<Vec<(Op, u32)>> {
+    s.split(',')
+        .map(|part| {
+            let (name, weight) = part.split_once('=').unwrap_or((part, "1"));
+            let op = match name.trim() {
+                "search" => Op::Search,
+                "bind" => Op::Bind,
+                "modify" => Op::Modify,
+                n => return Err(invalid(format!("unknown operation {}", n))),
+            };
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| invalid(format!("bad weight {}", weight)))?;
+            Ok((op, weight))
+        })
+        .collect()
+}
+
+// xorshift, seeded per worker
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn pick(&mut self, mix: &[(Op, u32)]) -> Op {
+        let total: u64 = mix.iter().map(|(_, w)| *w as u64).sum();
+        let mut n = self.next() % total;
+        for (op, w) in mix {
+            if n < *w as u64 {
+                return *op;
+            }
+            n -= *w as u64;
+        }
+        mix[0].0
+    }
+    fn fill(&mut self, template: &str, range: u64) -> String {
+        let mut out = template.to_owned();
+        while let Some(pos) = out.find("{rand}") {
+            let n = (self.next() % range).to_string();
+            out.replace_range(pos..pos + "{rand}".len(), &n);
+        }
+        out
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<(Op, Duration)>,
+    errors: Vec<(Op, u64)>,
+}
+
+impl Stats {
+    fn error(&mut self, op: Op) {
+        match self.errors.iter_mut().find(|(o, _)| *o == op) {
+            Some((_, n)) => *n += 1,
+            None => self.errors.push((op, 1)),
+        }
+    }
+}
+
+fn result_code(msgs: &[Message]) -> u32 {
+    match msgs.last().map(|m| &m.params) {
+        Some(MessageParams::MsgSearchResultDone(r)) => r.res,
+        Some(MessageParams::ModifyResponse(r)) => r.res,
+        _ => ldap::RESULT_OTHER,
+    }
+}
+
+async fn run_op(c: &ClientConnection, cfg: &Config, rng: &mut Rng, op: Op) -> Result<u32> {
+    match op {
+        Op::Bind => Ok(c.send_request_bind(&cfg.bind_dn, &cfg.password).await?.res),
+        Op::Search => {
+            let msg = Message {
+                id: c.next_id(),
+                params: MessageParams::Search(MsgSearch {
+                    base_object: cfg.base.clone(),
+                    scope: ldap::SearchScope::WholeSubtree,
+                    deref: ldap::DerefAliases::NeverDerefAliases,
+                    filter: lds::filter::parse(&rng.fill(&cfg.filter, cfg.range))?,
+                    size_limit: 0,
+                    time_limit: 0,
+                    types_only: false,
+                    attributes: Vec::new(),
+                }),
+                controls: Vec::new(),
+            };
+            Ok(result_code(&c.send_request_w(msg).await?))
+        }
+        Op::Modify => {
+            let template = cfg.modify_dn.as_deref().unwrap_or_default();
+            let msg = Message {
+                id: c.next_id(),
+                params: MessageParams::Modify(MsgModify {
+                    object: rng.fill(template, cfg.range),
+                    changes: vec![Change {
+                        operation: ModifyOperation::Replace,
+                        modification: ldap::PartialAttribute {
+                            name: cfg.modify_attr.clone(),
+                            values: vec![format!("bench {}", rng.next())],
+                        },
+                    }],
+                }),
+                controls: Vec::new(),
+            };
+            Ok(result_code(&c.send_request_w(msg).await?))
+        }
+    }
+}
+
+async fn worker(
+    c: ClientConnection,
+    cfg: Arc<Config>,
+    bucket: SharedTokenBucket,
+    issued: Arc<std::sync::atomic::AtomicU64>,
+    deadline: Instant,
+    seed: u64,
+) -> Stats {
+    let mut rng = Rng(seed);
+    let mut stats = Stats::default();
+    loop {
+        if bucket.acquire(1).await.is_err() {
+            break;
+        }
+        let n = issued.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        if Instant::now() >= deadline || cfg.max_ops.is_some_and(|max| n >= max) {
+            break;
+        }
+        let op = rng.pick(&cfg.mix);
+        let start = Instant::now();
+        match run_op(&c, &cfg, &mut rng, op).await {
+            Ok(ldap::RESULT_SUCCESS) => stats.latencies.push((op, start.elapsed())),
+            Ok(_) => stats.error(op),
+            Err(e) => {
+                println!("{} failed: {}", op.name(), e);
+                stats.error(op);
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    // the connection is gone
+                    break;
+                }
+            }
+        }
+    }
+    let _ = c.close().await;
+    stats
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let i = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[i]
+}
+
+fn ms(d: Duration) -> String {
+    format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+}
+
+fn report<W: Write>(cfg: &Config, all: Vec<Stats>, elapsed: Duration, out: &mut W) -> Result<()> {
+    let mut total = 0;
+    for (op, _) in &cfg.mix {
+        let mut lat: Vec<Duration> = all
+            .iter()
+            .flat_map(|s| s.latencies.iter())
+            .filter(|(o, _)| o == op)
+            .map(|(_, d)| *d)
+            .collect();
+        let errors: u64 = all
+            .iter()
+            .flat_map(|s| s.errors.iter())
+            .filter(|(o, _)| o == op)
+            .map(|(_, n)| n)
+            .sum();
+        lat.sort();
+        total += lat.len();
+        writeln!(
+            out,
+            "{:<7} ok {:>8} errors {:>6}  p50 {}  p90 {}  p99 {}  max {}",
+            op.name(),
+            lat.len(),
+            errors,
+            ms(percentile(&lat, 0.5)),
+            ms(percentile(&lat, 0.9)),
+            ms(percentile(&lat, 0.99)),
+            ms(lat.last().copied().unwrap_or_default()),
+        )?;
+    }
+    writeln!(
+        out,
+        "{} operations in {:.2}s, {:.1} ops/s",
+        total,
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64()
+    )
+}
+
+async fn run<W: Write>(cfg: Config, out: &mut W) -> Result<()> {
+    let cfg = Arc::new(cfg);
+    let tls_config = match std::env::var("LDAPTLS_CACERT") {
+        Ok(ca) => client::tls_config_with_ca_file(&ca)?,
+        Err(_) => client::default_tls_config(),
+    };
+    let mut connections = Vec::new();
+    for _ in 0..cfg.connections {
+        let c = client::connect_with_tls_config(&cfg.url, tls_config.clone()).await?;
+        if !cfg.bind_dn.is_empty() {
+            let r = c.send_request_bind(&cfg.bind_dn, &cfg.password).await?;
+            if r.res != ldap::RESULT_SUCCESS {
+                return Err(invalid(format!("bind failed with {}: {}", r.res, r.diag)));
+            }
+        }
+        connections.push(c);
+    }
+    // a tenth of a second worth of burst keeps the pace even
+    let bucket = SharedTokenBucket::new(TokenBucket::with_capacity(
+        cfg.rate / 1000.0,
+        (cfg.rate / 10.0).max(1.0) as u64,
+    ));
+    let issued = Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(1)
+        | 1;
+    let start = Instant::now();
+    let deadline = start + cfg.duration;
+    let mut workers = tokio::task::JoinSet::new();
+    for (i, c) in connections.into_iter().enumerate() {
+        workers.spawn(worker(
+            c,
+            cfg.clone(),
+            bucket.clone(),
+            issued.clone(),
+            deadline,
+            seed.wrapping_mul(i as u64 + 1),
+        ));
+    }
+    let mut all = Vec::new();
+    while let Some(stats) = workers.join_next().await {
+        if let Ok(stats) = stats {
+            all.push(stats);
+        }
+    }
+    report(&cfg, all, start.elapsed(), out)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cfg = match parse_args(&args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    if let Err(e) = runtime.block_on(run(cfg, &mut std::io::stdout().lock())) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn parse_args_test() {
+    let c = parse_args(&[]).unwrap();
+    assert_eq!(c.url, "ldap://127.0.0.1:389");
+    assert_eq!((c.connections, c.rate), (4, 1000.0));
+    // modify needs a target
+    assert_eq!(c.mix, vec![(Op::Search, 8), (Op::Bind, 1)]);
+
+    let c = parse_args(&strings(&[
+        "-H",
+        "ldaps://h",
+        "-c",
+        "0",
+        "-r",
+        "50",
+        "-n",
+        "10",
+        "-m",
+        "modify=3, search",
+        "-M",
+        "uid=u{rand},dc=example",
+        "-f",
+        "(uid=u{rand})",
+        "-R",
+        "10",
+    ]))
+    .unwrap();
+    assert_eq!(c.url, "ldaps://h");
+    assert_eq!(c.connections, 1);
+    assert_eq!(c.max_ops, Some(10));
+    assert_eq!(c.mix, vec![(Op::Modify, 3), (Op::Search, 1)]);
+    assert_eq!(c.range, 10);
+    let mut rng = Rng(7);
+    let dn = rng.fill(c.modify_dn.as_deref().unwrap(), c.range);
+    assert!(dn.starts_with("uid=u") && !dn.contains("{rand}"));
+
+    let err = |args: &[&str]| parse_args(&strings(args)).err().unwrap().to_string();
+    assert!(err(&["-h"]).starts_with("usage: ldap-bench"));
+    assert!(err(&["-c"]).starts_with("-c needs a value"));
+    assert!(err(&["-x", "1"]).starts_with("unknown option -x"));
+    assert_eq!(err(&["-r", "0"]), "-r must be above 0: 0");
+    assert_eq!(err(&["-t", "soon"]), "-t: not a number: soon");
+    assert_eq!(err(&["-m", "delete=1"]), "unknown operation delete");
+    assert_eq!(
+        err(&["-m", "search=0,modify=1"]),
+        "nothing to run in the operation mix"
+    );
+    assert!(parse_args(&strings(&["-f", "(cn=x"])).is_err());
+}
+
+#[test]
+fn report_test() {
+    let cfg = parse_args(&strings(&["-m", "search,bind"])).unwrap();
+    let ms = Duration::from_millis;
+    let stats = vec![
+        Stats {
+            latencies: vec![(Op::Search, ms(4)), (Op::Search, ms(1)), (Op::Bind, ms(2))],
+            errors: vec![(Op::Bind, 1)],
+        },
+        Stats {
+            latencies: vec![(Op::Search, ms(2)), (Op::Search, ms(3))],
+            errors: vec![(Op::Bind, 2)],
+        },
+    ];
+    let mut out = Vec::new();
+    report(&cfg, stats, Duration::from_secs(2), &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "search  ok        4 errors      0  p50 2.00ms  p90 4.00ms  p99 4.00ms  max 4.00ms\n\
+         bind    ok        1 errors      3  p50 2.00ms  p90 2.00ms  p99 2.00ms  max 2.00ms\n\
+         5 operations in 2.00s, 2.5 ops/s\n"
+    );
+}
+
+#[tokio::test]
+async fn run_test() {
+    let mut dir = lds::directory::Directory::new();
+    dir.set_root("cn=admin,dc=example", "secret").unwrap();
+    dir.apply_ldif("dn: dc=example\ndc: example\n").unwrap();
+    let server = Arc::new(lds::server::LdapServer::new("127.0.0.1:0".to_owned()));
+    let bound = server.bind().await.unwrap();
+    let addr = bound.local_addrs().unwrap()[0].to_string();
+    tokio::spawn(async move { server.serve(bound, Arc::new(dir)).await });
+
+    let cfg = parse_args(&strings(&[
+        "-H",
+        &addr,
+        "-D",
+        "cn=admin,dc=example",
+        "-w",
+        "secret",
+        "-c",
+        "2",
+        "-n",
+        "20",
+        "-b",
+        "dc=example",
+    ]))
+    .unwrap();
+    let mut out = Vec::new();
+    run(cfg, &mut out).await.unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("search  ok"));
+    assert!(lines[1].starts_with("bind    ok"));
+    assert!(lines[..2].iter().all(|l| l.contains(" errors      0 ")));
+    assert!(lines[2].starts_with("20 operations in "));
+
+    let cfg = parse_args(&strings(&[
+        "-H",
+        &addr,
+        "-D",
+        "cn=admin,dc=example",
+        "-w",
+        "bad",
+    ]))
+    .unwrap();
+    let e = run(cfg, &mut Vec::new()).await.err().unwrap();
+    assert!(e.to_string().starts_with("bind failed with 49"));
+}
@@ -0,0 +1,430 @@
+use lds::acl::{AccessControl, AccessRule};
+use lds::directory::{Directory, Entry, Persistence};
+use lds::dn::Dn;
+use lds::ldap::{self, PartialAttribute};
+use lds::schema::Schema;
+use lds::schemacheck::SchemaCheck;
+use lds::server::{
+    LdapServer, Operation, RateLimit, RateLimitAction, RateLimitScope, ShutdownHandle,
+};
+use lds::url::{LdapUrl, Scheme};
+use std::io::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+const USAGE: &str = "usage: lds-server <config file>
+
+the config file holds one setting per line as key = value, # starts a comment:
+  listen = ldap://0.0.0.0:389          repeatable, ldaps:// urls need tls_cert and tls_key
+  tls_cert = cert.pem                  certificate chain, pem
+  tls_key = key.pem                    private key, pem
+  suffix = dc=example,dc=com           created if the seed does not contain it
+  seed = seed.ldif                     repeatable, loaded in order at startup
+  changelog = changes.ldif             changes are appended here and replayed at startup
+  root_dn = cn=admin,dc=example,dc=com
+  root_password = secret               the root dn binds with it and bypasses access rules
+  schema_check = true                  reject adds and modifies that violate the core schema
+  access = to <dn|*> [attrs=<attr,...>] by <*|anonymous|users|self|dn=<dn>|dn.subtree=<dn>>
+           <none|auth|read|write>      repeatable, first match wins, no rules allows everything
+                                       but userPassword. binding needs auth on userPassword and
+                                       rules without attrs= give only the entry itself more
+  size_limit = 500                     most entries a search returns
+  max_connections = 1000
+  max_connections_per_ip = 50
+  max_message_size = 8388608
+  idle_timeout = 300                   seconds
+  bind_timeout = 30                    seconds
+  write_timeout = 30                   seconds
+  connection_rate = 10                 new connections per second from one address
+  rate_limit = <connection|ip|binddn> <all|op,op...> <per second> <busy|delay>";
+
+#[derive(Default)]
+struct Config {
+    // address and whether it is ldaps
+    listen: Vec<(String, bool)>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    suffix: Option<String>,
+    seeds: Vec<String>,
+    changelog: Option<String>,
+    root_dn: Option<String>,
+    root_password: Option<String>,
+    schema_check: bool,
+    access: Vec<AccessRule>,
+    size_limit: u32,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    max_message_size: Option<usize>,
+    idle_timeout: Option<Duration>,
+    bind_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    connection_rate: Option<f64>,
+    rate_limits: Vec<RateLimit>,
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{}: not a number: {}", key, value)))
+}
+
+fn listen_address(value: &str) -> Result<(String, bool)> {
+    if !value.contains("://") {
+        return Ok((value.to_owned(), false));
+    }
+    let url = LdapUrl::parse(value)?;
+    match url.scheme {
+        Scheme::Ldap => Ok((url.socket_address(), false)),
+        Scheme::Ldaps => Ok((url.socket_address(), true)),
+        Scheme::Ldapi => Err(invalid("ldapi listeners are not supported".to_owned())),
+    }
+}
+
+fn rate_limit(value: &str) -> Result<RateLimit> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [scope, operations, per_second, action] = parts.as_slice() else {
+        return Err(invalid(
+            "expected <scope> <operations> <per second> <action>".to_owned(),
+        ));
+    };
+    let scope = match *scope {
+        "connection" => RateLimitScope::Connection,
+        "ip" => RateLimitScope::Ip,
+        "binddn" => RateLimitScope::BindDn,
+        s => return Err(invalid(format!("unknown rate limit scope {}", s))),
+    };
+    let operations = match *operations {
+        "all" => Vec::new(),
+        ops => ops
+            .split(',')
+            .map(|op| match op {
+                "bind" => Ok(Operation::Bind),
+                "search" => Ok(Operation::Search),
+                "add" => Ok(Operation::Add),
+                "modify" => Ok(Operation::Modify),
+                "delete" => Ok(Operation::Del),
+                "modrdn" => Ok(Operation::ModDn),
+                "compare" => Ok(Operation::Compare),
+                "extended" => Ok(Operation::Extended),
+                op => Err(invalid(format!("unknown operation {}", op))),
+            })
+            .collect::<Result<_>>()?,
+    };
+    let action = match *action {
+        "busy" => RateLimitAction::Busy,
+        "delay" => RateLimitAction::Delay,
+        a => return Err(invalid(format!("unknown rate limit action {}", a))),
+    };
+    Ok(RateLimit {
+        scope,
+        operations,
+        per_second: number("rate_limit", per_second)?,
+        action,
+    })
+}
+
+fn parse_config(text: &str) -> Result<Config> {
+    let mut c = Config::default();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split_once('#').map_or(line, |(l, _)| l).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let at_line = |e: std::io::Error| invalid(format!("line {}: {}", n + 1, e));
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(at_line(invalid("expected key = value".to_owned())));
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let secs = |v: &str| number::<u64>(key, v).map(Duration::from_secs);
+        let r: Result<()> = (|| {
+            match key {
+                "listen" => c.listen.push(listen_address(value)?),
+                "tls_cert" => c.tls_cert = Some(value.to_owned()),
+                "tls_key" => c.tls_key = Some(value.to_owned()),
+                "suffix" => c.suffix = Some(Dn::parse(value)?.to_string()),
+                "seed" => c.seeds.push(value.to_owned()),
+                "changelog" => c.changelog = Some(value.to_owned()),
+                "root_dn" => c.root_dn = Some(Dn::parse(value)?.to_string()),
+                "root_password" => c.root_password = Some(value.to_owned()),
+                "schema_check" => {
+                    c.schema_check = match value {
+                        "true" | "yes" | "on" => true,
+                        "false" | "no" | "off" => false,
+                        v => return Err(invalid(format!("not a boolean: {}", v))),
+                    }
+                }
+                "access" => c.access.push(AccessRule::parse(value)?),
+                "size_limit" => c.size_limit = number(key, value)?,
+                "max_connections" => c.max_connections = Some(number(key, value)?),
+                "max_connections_per_ip" => c.max_connections_per_ip = Some(number(key, value)?),
+                "max_message_size" => c.max_message_size = Some(number(key, value)?),
+                "idle_timeout" => c.idle_timeout = Some(secs(value)?),
+                "bind_timeout" => c.bind_timeout = Some(secs(value)?),
+                "write_timeout" => c.write_timeout = Some(secs(value)?),
+                "connection_rate" => c.connection_rate = Some(number(key, value)?),
+                "rate_limit" => c.rate_limits.push(rate_limit(value)?),
+                k => return Err(invalid(format!("unknown setting {}", k))),
+            }
+            Ok(())
+        })();
+        r.map_err(at_line)?;
+    }
+    if c.listen.is_empty() {
+        c.listen.push(("0.0.0.0:389".to_owned(), false));
+    }
+    if c.listen.iter().any(|(_, tls)| *tls) && (c.tls_cert.is_none() || c.tls_key.is_none()) {
+        return Err(invalid(
+            "ldaps listeners need tls_cert and tls_key".to_owned(),
+        ));
+    }
+    if c.root_dn.is_some() != c.root_password.is_some() {
+        return Err(invalid("root_dn and root_password go together".to_owned()));
+    }
+    Ok(c)
+}
+
+fn tls_acceptor(cert: &str, key: &str) -> Result<tokio_rustls::TlsAcceptor> {
+    let pem_error = |path: &str, e: tokio_rustls::rustls::pki_types::pem::Error| {
+        invalid(format!("{}: {}", path, e))
+    };
+    let certs = CertificateDer::pem_file_iter(cert)
+        .map_err(|e| pem_error(cert, e))?
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| pem_error(cert, e))?;
+    let key_der = PrivateKeyDer::from_pem_file(key).map_err(|e| pem_error(key, e))?;
+    let config = tokio_rustls::rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key_der)
+        .map_err(|e| invalid(format!("{}: {}", cert, e)))?;
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+}
+
+// the suffix entry with the object class its naming attribute usually comes with
+fn suffix_entry(suffix: &str) -> Result<Entry> {
+    let dn = Dn::parse(suffix)?;
+    let rdn = dn
+        .rdn()
+        .ok_or_else(|| invalid("the suffix can not be the root dn".to_owned()))?;
+    let mut attributes = Vec::new();
+    let mut classes = vec!["top".to_owned()];
+    for ava in &rdn.avas {
+        let class = match ava.attr.to_ascii_lowercase().as_str() {
+            "dc" => "domain",
+            "o" => "organization",
+            "ou" => "organizationalUnit",
+            "c" => "country",
+            _ => "extensibleObject",
+        };
+        classes.push(class.to_owned());
+        attributes.push(PartialAttribute {
+            name: ava.attr.clone(),
+            values: vec![ava.value.clone()],
+        });
+    }
+    attributes.insert(
+        0,
+        PartialAttribute {
+            name: "objectClass".to_owned(),
+            values: classes,
+        },
+    );
+    Ok(Entry {
+        dn: dn.to_string(),
+        attributes,
+    })
+}
+
+fn directory(c: &Config) -> Result<Directory> {
+    let mut dir = Directory::new();
+    for seed in &c.seeds {
+        println!("loading {}", seed);
+        dir.apply_ldif(&std::fs::read_to_string(seed)?)
+            .map_err(|e| invalid(format!("{}: {}", seed, e)))?;
+    }
+    if let Some(suffix) = &c.suffix {
+        dir.add_suffix(suffix)?;
+        if dir.get(suffix).is_none() {
+            let r = dir.add(suffix_entry(suffix)?);
+            if r.res != ldap::RESULT_SUCCESS {
+                return Err(invalid(format!("cannot create {}: {}", suffix, r.diag)));
+            }
+        }
+    }
+    if let Some(changelog) = &c.changelog {
+        match std::fs::read_to_string(changelog) {
+            Ok(changes) => dir
+                .apply_ldif(&changes)
+                .map_err(|e| invalid(format!("{}: {}", changelog, e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        dir.set_persistence(Persistence::Changelog(changelog.into()));
+    }
+    if let (Some(dn), Some(password)) = (&c.root_dn, &c.root_password) {
+        dir.set_root(dn, password)?;
+    }
+    dir.set_size_limit(c.size_limit);
+    Ok(dir)
+}
+
+fn server(c: &Config) -> Result<LdapServer> {
+    let tls = match (&c.tls_cert, &c.tls_key) {
+        (Some(cert), Some(key)) => Some(tls_acceptor(cert, key)?),
+        _ => None,
+    };
+    let mut listen = c.listen.iter();
+    let (first, first_tls) = listen.next().unwrap();
+    let mut server = LdapServer::new(first.clone());
+    if *first_tls {
+        server.set_tls(tls.clone().unwrap());
+    }
+    for (address, is_tls) in listen {
+        server.add_listener(address.clone(), tls.clone().filter(|_| *is_tls));
+    }
+    if let Some(n) = c.max_connections {
+        server.set_max_connections(n);
+    }
+    if let Some(n) = c.max_connections_per_ip {
+        server.set_max_connections_per_ip(n);
+    }
+    if let Some(n) = c.max_message_size {
+        server.set_max_message_size(n);
+    }
+    if let Some(t) = c.idle_timeout {
+        server.set_idle_timeout(t);
+    }
+    if let Some(t) = c.bind_timeout {
+        server.set_bind_timeout(t);
+    }
+    if let Some(t) = c.write_timeout {
+        server.set_write_timeout(t);
+    }
+    if let Some(r) = c.connection_rate {
+        server.set_connection_rate_limit(r);
+    }
+    for limit in &c.rate_limits {
+        server.add_rate_limit(limit.clone());
+    }
+    let mut ac = AccessControl::new();
+    if let Some(root) = &c.root_dn {
+        ac.set_root(Dn::parse(root)?);
+    }
+    if c.access.is_empty() {
+        println!("no access rules, every client may read and write everything but userPassword");
+        ac.add_rule(AccessRule::parse("to * by * write")?);
+    }
+    for rule in &c.access {
+        ac.add_rule(rule.clone());
+    }
+    server.set_access_control(ac);
+    Ok(server)
+}
+
+// sigint or sigterm shuts the server down, operations in flight get the shutdown timeout to finish
+async fn shutdown_on_signal(handle: ShutdownHandle) {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            println!("cannot listen for sigint: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                println!("cannot listen for sigterm: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+    println!("shutting down");
+    handle.shutdown();
+}
+
+async fn run(c: Config) -> Result<()> {
+    let dir = directory(&c)?;
+    let mut server = server(&c)?;
+    tokio::spawn(shutdown_on_signal(server.shutdown_handle()));
+    if c.schema_check {
+        let schema = Arc::new(Schema::core());
+        server.set_subschema_subentry("cn=Subschema", schema.to_subschema_attributes())?;
+        let svc = Arc::new(SchemaCheck::new(dir, schema));
+        Arc::new(server).start_server(svc).await
+    } else {
+        Arc::new(server).start_server(Arc::new(dir)).await
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [path] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    };
+    let config = match std::fs::read_to_string(path).and_then(|text| parse_config(&text)) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(2);
+        }
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    if let Err(e) = runtime.block_on(run(config)) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[test]
+fn parse_config_test() {
+    let c = parse_config(
+        "# dev directory\n\
+         listen = ldap://127.0.0.1:3389\n\
+         listen = ldaps://127.0.0.1\n\
+         tls_cert = cert.pem\n\
+         tls_key = key.pem\n\
+         suffix = DC=example, DC=com\n\
+         access = to * attrs=userPassword by anonymous auth\n\
+         access = to * by users read # everyone else gets nothing\n\
+         rate_limit = ip search,bind 100 busy\n\
+         idle_timeout = 300\n",
+    )
+    .unwrap();
+    assert_eq!(
+        c.listen,
+        vec![
+            ("127.0.0.1:3389".to_owned(), false),
+            ("127.0.0.1:636".to_owned(), true)
+        ]
+    );
+    assert_eq!(c.suffix.as_deref(), Some("DC=example,DC=com"));
+    assert_eq!(c.access.len(), 2);
+    assert_eq!(
+        c.rate_limits[0].operations,
+        vec![Operation::Search, Operation::Bind]
+    );
+    assert_eq!(c.idle_timeout, Some(Duration::from_secs(300)));
+
+    let e = parse_config("listen = ldaps://127.0.0.1\n").err().unwrap();
+    assert!(e.to_string().contains("tls_cert"));
+    let e = parse_config("\nsize_limit = lots\n").err().unwrap();
+    assert!(e.to_string().starts_with("line 2:"));
+    assert!(parse_config("root_dn = cn=admin\n").is_err());
+    assert!(parse_config("bogus = 1\n").is_err());
+}
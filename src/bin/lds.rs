@@ -0,0 +1,512 @@
+use lds::client::{self, ClientConnection};
+use lds::codec;
+use lds::ldap::{self, Message, MessageParams, MsgResult};
+use lds::ldif::{self, LdifChange};
+use std::io::{Read, Result, Write};
+
+const USAGE: &str = "usage: lds <command> [options] [arguments]
+commands:
+  search [-b base] [-s base|one|sub] [-z size] [-l secs] [-A] [-E pr=size] [filter [attrs...]]
+  add [-f file]                      add the LDIF entries in file, stdin by default
+  modify [-f file]                   apply LDIF change records (add, modify, delete, modrdn)
+  delete dn...
+  modrdn [-r] [-s newsuperior] dn newrdn
+  compare dn attr:value
+  whoami
+  passwd [-a oldpassword] [-s newpassword] [user]
+options for all commands:
+  -H urls         servers separated by spaces, tried in order, default ldap://127.0.0.1:389
+  -D dn -w pass   bind before running the command, anonymous otherwise
+environment:
+  LDAPTLS_CACERT  pem file with ca certificates trusted for ldaps next to the public roots";
+
+// exit status when the command could not run at all, ldap result codes otherwise
+const EXIT_USAGE: i32 = 2;
+const EXIT_FAILED: i32 = 1;
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+}
+
+struct Args {
+    options: Vec<(String, String)>,
+    switches: Vec<String>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String], with_value: &[&str], switches: &[&str]) -> Result<Args> {
+        let mut out = Args {
+            options: Vec::new(),
+            switches: Vec::new(),
+            positional: Vec::new(),
+        };
+        let mut it = args.iter();
+        while let Some(a) = it.next() {
+            let name = a.as_str();
+            if ["-H", "-D", "-w"].contains(&name) || with_value.contains(&name) {
+                match it.next() {
+                    Some(v) => out.options.push((a.clone(), v.clone())),
+                    None => return Err(invalid(format!("{} needs a value", a))),
+                }
+            } else if switches.contains(&name) {
+                out.switches.push(a.clone());
+            } else if name.starts_with('-') && name.len() > 1 {
+                return Err(invalid(format!("unknown option {}", a)));
+            } else {
+                out.positional.push(a.clone());
+            }
+        }
+        Ok(out)
+    }
+    fn get(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+    fn has(&self, name: &str) -> bool {
+        self.switches.iter().any(|s| s == name)
+    }
+}
+
+// prints a failed result the way the ldap tools do, returns the code as exit status
+fn report(what: &str, r: &MsgResult) -> i32 {
+    if r.res != ldap::RESULT_SUCCESS {
+        eprintln!("{} failed: result {}", what, r.res);
+        if !r.matched_dn.is_empty() {
+            eprintln!("  matched dn: {}", r.matched_dn);
+        }
+        if !r.diag.is_empty() {
+            eprintln!("  {}", r.diag);
+        }
+    }
+    r.res as i32
+}
+
+fn unexpected() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "unexpected response")
+}
+
+// the final result of an operation that is not a search
+fn result_of(mut resp: Vec<Message>) -> Result<MsgResult> {
+    match resp.pop().map(|m| m.params) {
+        Some(MessageParams::AddResponse(r))
+        | Some(MessageParams::ModifyResponse(r))
+        | Some(MessageParams::DelResponse(r))
+        | Some(MessageParams::ModDnResponse(r))
+        | Some(MessageParams::CompareResponse(r)) => Ok(r),
+        Some(MessageParams::ExtendedResponse(r)) => Ok(r.result),
+        _ => Err(unexpected()),
+    }
+}
+
+async fn request(c: &ClientConnection, params: MessageParams) -> Result<Vec<Message>> {
+    c.send_request_w(Message::new(c.next_id(), params)).await
+}
+
+fn read_input(args: &Args) -> Result<String> {
+    match args.get("-f") {
+        Some(path) => std::fs::read_to_string(path),
+        None => {
+            let mut s = String::new();
+            std::io::stdin().read_to_string(&mut s)?;
+            Ok(s)
+        }
+    }
+}
+
+async fn search<W: Write>(c: &ClientConnection, args: &Args, out: &mut W) -> Result<i32> {
+    let scope = match args.get("-s").unwrap_or("sub") {
+        "base" => ldap::SearchScope::BaseObject,
+        "one" => ldap::SearchScope::SingleLevel,
+        "sub" => ldap::SearchScope::WholeSubtree,
+        s => return Err(invalid(format!("unknown scope {}", s))),
+    };
+    let number = |name: &str| -> Result<u32> {
+        match args.get(name) {
+            Some(v) => v
+                .parse()
+                .map_err(|_| invalid(format!("{}: not a number: {}", name, v))),
+            None => Ok(0),
+        }
+    };
+    let page_size = match args.get("-E") {
+        Some(e) => match e.strip_prefix("pr=").map(|n| n.parse::<u32>()) {
+            Some(Ok(n)) if n > 0 => Some(n),
+            _ => return Err(invalid(format!("unsupported search extension {}", e))),
+        },
+        None => None,
+    };
+    let filter = args
+        .positional
+        .first()
+        .map_or("(objectClass=*)", |f| f.as_str());
+    let req = ldap::MsgSearch {
+        base_object: args.get("-b").unwrap_or("").to_owned(),
+        scope,
+        deref: ldap::DerefAliases::NeverDerefAliases,
+        filter: lds::filter::parse(filter)?,
+        size_limit: number("-z")?,
+        time_limit: number("-l")?,
+        types_only: args.has("-A"),
+        attributes: args.positional.iter().skip(1).cloned().collect(),
+    };
+
+    let mut entries = 0;
+    let mut cookie = Vec::new();
+    loop {
+        let mut msg = Message::new(c.next_id(), MessageParams::Search(req.clone()));
+        if let Some(size) = page_size {
+            msg.controls
+                .push(codec::ldap_paged_results_control(size, &cookie)?);
+        }
+        let resp = c.send_request_w(msg).await?;
+        let mut done = None;
+        for m in resp {
+            match &m.params {
+                MessageParams::SearchResult(e) => {
+                    let mut ldif = String::new();
+                    ldif::write_entry(&mut ldif, &e.name, &e.values);
+                    out.write_all(ldif.as_bytes())?;
+                    entries += 1;
+                }
+                MessageParams::MsgSearchResultDone(_) => done = Some(m),
+                _ => {}
+            }
+        }
+        let done = done.ok_or_else(unexpected)?;
+        if let MessageParams::MsgSearchResultDone(d) = &done.params {
+            if d.res != ldap::RESULT_SUCCESS {
+                eprintln!("search failed: result {}", d.res);
+                return Ok(d.res as i32);
+            }
+        }
+        cookie = match done
+            .control(ldap::PAGED_RESULTS_CONTROL)
+            .and_then(|c| c.value.as_deref())
+        {
+            Some(value) => codec::ldap_read_paged_results(value)?.1,
+            None => Vec::new(),
+        };
+        if page_size.is_none() || cookie.is_empty() {
+            break;
+        }
+    }
+    writeln!(out, "# {} entries", entries)?;
+    Ok(0)
+}
+
+// applies ldif records, stops at the first failure
+async fn apply(c: &ClientConnection, args: &Args, only_add: bool) -> Result<i32> {
+    for record in ldif::parse(&read_input(args)?)? {
+        let (what, params) = match record.change {
+            LdifChange::Add(attributes) => (
+                "add",
+                MessageParams::Add(ldap::MsgAdd {
+                    entry: record.dn.clone(),
+                    attributes,
+                }),
+            ),
+            _ if only_add => {
+                return Err(invalid(format!(
+                    "{}: only entries can be added, use modify for change records",
+                    record.dn
+                )))
+            }
+            LdifChange::Modify(changes) => (
+                "modify",
+                MessageParams::Modify(ldap::MsgModify {
+                    object: record.dn.clone(),
+                    changes,
+                }),
+            ),
+            LdifChange::Delete => (
+                "delete",
+                MessageParams::Del(ldap::MsgDel {
+                    entry: record.dn.clone(),
+                }),
+            ),
+            LdifChange::ModDn {
+                new_rdn,
+                delete_old_rdn,
+                new_superior,
+            } => (
+                "modrdn",
+                MessageParams::ModDn(ldap::MsgModDn {
+                    entry: record.dn.clone(),
+                    new_rdn,
+                    delete_old_rdn,
+                    new_superior,
+                }),
+            ),
+        };
+        println!("{} {}", what, record.dn);
+        let code = report(what, &result_of(request(c, params).await?)?);
+        if code != 0 {
+            return Ok(code);
+        }
+    }
+    Ok(0)
+}
+
+async fn delete(c: &ClientConnection, args: &Args) -> Result<i32> {
+    if args.positional.is_empty() {
+        return Err(invalid("delete needs at least one dn".to_owned()));
+    }
+    for dn in &args.positional {
+        let params = MessageParams::Del(ldap::MsgDel { entry: dn.clone() });
+        let code = report("delete", &result_of(request(c, params).await?)?);
+        if code != 0 {
+            return Ok(code);
+        }
+    }
+    Ok(0)
+}
+
+async fn modrdn(c: &ClientConnection, args: &Args) -> Result<i32> {
+    let [dn, new_rdn] = args.positional.as_slice() else {
+        return Err(invalid("modrdn needs a dn and a new rdn".to_owned()));
+    };
+    let params = MessageParams::ModDn(ldap::MsgModDn {
+        entry: dn.clone(),
+        new_rdn: new_rdn.clone(),
+        delete_old_rdn: args.has("-r"),
+        new_superior: args.get("-s").map(|s| s.to_owned()),
+    });
+    Ok(report("modrdn", &result_of(request(c, params).await?)?))
+}
+
+async fn compare(c: &ClientConnection, args: &Args) -> Result<i32> {
+    let ([dn, assertion], Some((name, value))) = (
+        args.positional.as_slice(),
+        args.positional.get(1).and_then(|a| a.split_once(':')),
+    ) else {
+        return Err(invalid("compare needs a dn and attr:value".to_owned()));
+    };
+    let params = MessageParams::Compare(ldap::MsgCompare {
+        entry: dn.clone(),
+        name: name.to_owned(),
+        value: value.to_owned(),
+    });
+    let r = result_of(request(c, params).await?)?;
+    match r.res {
+        ldap::RESULT_COMPARE_TRUE => println!("TRUE"),
+        ldap::RESULT_COMPARE_FALSE => println!("FALSE"),
+        _ => {
+            report(&format!("compare {}", assertion), &r);
+        }
+    }
+    Ok(r.res as i32)
+}
+
+async fn extended(
+    c: &ClientConnection,
+    name: &str,
+    value: Option<Vec<u8>>,
+) -> Result<(MsgResult, Option<Vec<u8>>)> {
+    let params = MessageParams::ExtendedRequest(ldap::MsgExtendedRequest {
+        name: name.to_owned(),
+        value,
+    });
+    match request(c, params).await?.pop().map(|m| m.params) {
+        Some(MessageParams::ExtendedResponse(r)) => Ok((r.result, r.value)),
+        _ => Err(unexpected()),
+    }
+}
+
+async fn whoami(c: &ClientConnection) -> Result<i32> {
+    let (r, value) = extended(c, ldap::WHO_AM_I_REQUEST, None).await?;
+    if r.res == ldap::RESULT_SUCCESS {
+        match value.filter(|v| !v.is_empty()) {
+            Some(v) => println!("{}", String::from_utf8_lossy(&v)),
+            None => println!("anonymous"),
+        }
+    }
+    Ok(report("whoami", &r))
+}
+
+async fn passwd(c: &ClientConnection, args: &Args) -> Result<i32> {
+    let value = codec::ldap_write_password_modify(
+        args.positional.first().map(|u| u.as_str()),
+        args.get("-a"),
+        args.get("-s"),
+    )?;
+    let (r, value) = extended(c, ldap::PASSWORD_MODIFY_REQUEST, Some(value)).await?;
+    if r.res == ldap::RESULT_SUCCESS {
+        if let Some(v) = value {
+            if let Some(generated) = codec::ldap_read_password_modify_response(&v)? {
+                println!("New password: {}", generated);
+            }
+        }
+    }
+    Ok(report("passwd", &r))
+}
+
+// the options each command takes
+fn parse_args(command: &str, args: &[String]) -> Result<Args> {
+    match command {
+        "search" => Args::parse(args, &["-b", "-s", "-z", "-l", "-E"], &["-A"]),
+        "add" | "modify" => Args::parse(args, &["-f"], &[]),
+        "modrdn" => Args::parse(args, &["-s"], &["-r"]),
+        "passwd" => Args::parse(args, &["-a", "-s"], &[]),
+        "delete" | "compare" | "whoami" => Args::parse(args, &[], &[]),
+        _ => Err(invalid(format!("unknown command {}\n{}", command, USAGE))),
+    }
+}
+
+async fn run(command: &str, args: &[String]) -> Result<i32> {
+    let args = parse_args(command, args)?;
+    let url = args.get("-H").unwrap_or("ldap://127.0.0.1:389");
+    let c = match std::env::var("LDAPTLS_CACERT") {
+        Ok(ca) => {
+            client::connect_with_tls_config(url, client::tls_config_with_ca_file(&ca)?).await?
+        }
+        Err(_) => client::connect(url).await?,
+    };
+    if let Some(dn) = args.get("-D") {
+        let r = c
+            .send_request_bind(dn, args.get("-w").unwrap_or(""))
+            .await?;
+        if r.res != ldap::RESULT_SUCCESS {
+            return Ok(report(
+                "bind",
+                &MsgResult {
+                    res: r.res,
+                    matched_dn: r.matched_dn,
+                    diag: r.diag,
+                },
+            ));
+        }
+    }
+    let code = match command {
+        "search" => search(&c, &args, &mut std::io::stdout().lock()).await?,
+        "add" => apply(&c, &args, true).await?,
+        "modify" => apply(&c, &args, false).await?,
+        "delete" => delete(&c, &args).await?,
+        "modrdn" => modrdn(&c, &args).await?,
+        "compare" => compare(&c, &args).await?,
+        "whoami" => whoami(&c).await?,
+        _ => passwd(&c, &args).await?,
+    };
+    c.close().await?;
+    Ok(code)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first().filter(|c| !c.starts_with('-')) else {
+        eprintln!("{}", USAGE);
+        std::process::exit(EXIT_USAGE);
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    match runtime.block_on(run(command, &args[1..])) {
+        Ok(code) => std::process::exit(code),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_USAGE);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_FAILED);
+        }
+    }
+}
+
+#[cfg(test)]
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn parse_args_test() {
+    let a = parse_args(
+        "search",
+        &strings(&[
+            "-H",
+            "ldap://a",
+            "-b",
+            "dc=example",
+            "-A",
+            "(cn=x)",
+            "cn",
+            "-b",
+            "dc=b",
+        ]),
+    )
+    .unwrap();
+    assert_eq!(a.get("-H"), Some("ldap://a"));
+    // the last of a repeated option wins
+    assert_eq!(a.get("-b"), Some("dc=b"));
+    assert_eq!(a.get("-z"), None);
+    assert!(a.has("-A"));
+    assert_eq!(a.positional, strings(&["(cn=x)", "cn"]));
+    // a lone dash is an argument, stdin for the tools that read files
+    assert_eq!(
+        parse_args("delete", &strings(&["-"])).unwrap().positional,
+        ["-"]
+    );
+
+    let kind = |command: &str, args: &[&str]| parse_args(command, &strings(args)).err().unwrap();
+    assert!(kind("search", &["-b"])
+        .to_string()
+        .contains("-b needs a value"));
+    assert!(kind("search", &["-r"])
+        .to_string()
+        .contains("unknown option -r"));
+    assert!(kind("rename", &[])
+        .to_string()
+        .starts_with("unknown command rename\nusage:"));
+    assert_eq!(
+        kind("whoami", &["-x"]).kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+}
+
+#[tokio::test]
+async fn search_test() {
+    let dir = lds::directory::Directory::new();
+    dir.apply_ldif(
+        "dn: dc=example\ndc: example\n\n\
+         dn: ou=people,dc=example\nou: people\n\n\
+         dn: ou=groups,dc=example\nou: groups\n",
+    )
+    .unwrap();
+    let server = std::sync::Arc::new(lds::server::LdapServer::new("127.0.0.1:0".to_owned()));
+    let bound = server.bind().await.unwrap();
+    let addr = bound.local_addrs().unwrap()[0].to_string();
+    tokio::spawn(async move { server.serve(bound, std::sync::Arc::new(dir)).await });
+    let c = client::connect(&addr).await.unwrap();
+
+    let run = |args: &[&str]| {
+        let args = parse_args("search", &strings(args)).unwrap();
+        let c = &c;
+        async move {
+            let mut out = Vec::new();
+            let code = search(c, &args, &mut out).await?;
+            Ok::<_, std::io::Error>((code, String::from_utf8(out).unwrap()))
+        }
+    };
+    let (code, out) = run(&["-b", "dc=example", "-s", "one", "(ou=people)", "ou"])
+        .await
+        .unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(out, "dn: ou=people,dc=example\nou: people\n\n# 1 entries\n");
+    let (_, out) = run(&["-b", "dc=example", "-s", "base", "-A"])
+        .await
+        .unwrap();
+    assert!(out.starts_with("dn: dc=example\n"));
+    assert!(out.ends_with("# 1 entries\n"));
+    let (_, out) = run(&["-b", "dc=example", "(ou=*)", "1.1"]).await.unwrap();
+    assert!(out.ends_with("# 2 entries\n"));
+
+    // failed searches exit with the result code
+    let (code, out) = run(&["-b", "dc=missing"]).await.unwrap();
+    assert_eq!(code, ldap::RESULT_NO_SUCH_OBJECT as i32);
+    assert!(out.is_empty());
+    let e = run(&["-s", "all"]).await.err().unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    let e = run(&["-E", "pr=0"]).await.err().unwrap();
+    assert!(e.to_string().contains("unsupported search extension"));
+}
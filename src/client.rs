@@ -1,9 +1,17 @@
-use crate::codec;
 use crate::ldap::{self, Message, MessageParams, MsgBind, MsgBindResponse};
+use crate::servers::{ServerList, Strategy};
+use crate::tokenbucket::SharedTokenBucket;
 use crate::tokiou;
+use crate::url::{LdapUrl, Scheme};
 use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
 use std::{collections::HashMap, io::Result};
-use tokio::{io::AsyncWriteExt, net::TcpStream, sync::oneshot};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tokio::{net::TcpStream, sync::oneshot};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::ClientConfig;
 
 struct Context {
     messages: Vec<Message>,
@@ -11,22 +19,37 @@ struct Context {
 }
 
 struct Contexts {
-    contexts: std::sync::Mutex<HashMap<u32, Context>>,
+    // none once the connection is gone
+    contexts: std::sync::Mutex<Option<HashMap<u32, Context>>>,
 }
 
 impl Contexts {
     fn new() -> Self {
         Self {
-            contexts: std::sync::Mutex::new(HashMap::new()),
+            contexts: std::sync::Mutex::new(Some(HashMap::new())),
         }
     }
-    fn add(&self, id: u32, c: Context) {
+    // false when the connection is gone and no response can arrive anymore
+    fn add(&self, id: u32, c: Context) -> bool {
         let mut l = self.contexts.lock().unwrap();
-        l.insert(id, c);
+        match l.as_mut() {
+            Some(l) => {
+                l.insert(id, c);
+                true
+            }
+            None => false,
+        }
     }
     fn remove(&self, id: u32) {
         let mut l = self.contexts.lock().unwrap();
-        l.remove(&id);
+        if let Some(l) = l.as_mut() {
+            l.remove(&id);
+        }
+    }
+    // fails every pending request once the connection is gone, and every later one
+    fn clear(&self) {
+        let mut l = self.contexts.lock().unwrap();
+        *l = None;
     }
     /*fn get(&self, id:u32) -> Option<Context>{
         let mut l = self.contexts.lock().unwrap();
@@ -40,8 +63,14 @@ impl Contexts {
         tokio::sync::oneshot::Sender<Vec<ldap::Message>>,
     )> {
         let id = m.id;
-        let last_fragment = !matches!(m.params, MessageParams::SearchResult(_));
+        let last_fragment = !matches!(
+            m.params,
+            MessageParams::SearchResult(_)
+                | MessageParams::SearchResultReference(_)
+                | MessageParams::IntermediateResponse(_)
+        );
         let mut l = self.contexts.lock().unwrap();
+        let l = l.as_mut()?;
         let c = l.get_mut(&id);
         match c {
             Some(c) => {
@@ -59,59 +88,124 @@ impl Contexts {
 }
 
 pub struct ClientConnection {
-    req_writer: tokio::sync::mpsc::Sender<Vec<u8>>,
+    // taken on close, the writer task flushes and shuts the stream down once it is gone
+    req_writer: std::sync::Mutex<Option<tokio::sync::mpsc::Sender<Vec<u8>>>>,
+    writer_task: std::sync::Mutex<Option<JoinHandle<()>>>,
+    reader_task: std::sync::Mutex<Option<JoinHandle<()>>>,
     contexts: std::sync::Arc<Contexts>,
     last_id: AtomicU32,
+    // paces outgoing requests, can be shared by several connections
+    rate_limiter: Option<SharedTokenBucket>,
+    // name of the last successful bind
+    bound: std::sync::Mutex<Option<String>>,
 }
 impl ClientConnection {
+    pub fn set_rate_limiter(&mut self, bucket: SharedTokenBucket) {
+        self.rate_limiter = Some(bucket);
+    }
+
+    pub fn bound_name(&self) -> Option<String> {
+        self.bound.lock().unwrap().clone()
+    }
+
+    // true once the connection was closed or the server went away
+    pub fn is_closed(&self) -> bool {
+        self.req_writer.lock().unwrap().is_none()
+            || self
+                .reader_task
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_none_or(|t| t.is_finished())
+    }
+
     async fn send_request(&self, msg: ldap::Message) -> Result<()> {
-        let tosend = match msg.params {
-            ldap::MessageParams::Bind(b) => {
-                codec::ldap_write_bind_request(msg.id, &b.name, &b.password)
-            }
-            ldap::MessageParams::BindResponse(_) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "unknown msg",
-                ))
-            }
-            ldap::MessageParams::Search(s) => codec::ldap_write_search_request(msg.id, &s),
-            ldap::MessageParams::SearchResult(_) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "unknown msg",
-                ))
-            }
-            ldap::MessageParams::MsgSearchResultDone(_) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "unknown msg",
-                ))
-            }
-            ldap::MessageParams::Unbind(_) => {
+        let tosend = msg.encode()?;
+        if let Some(r) = &self.rate_limiter {
+            r.acquire(1).await?;
+        }
+        let req_writer = self.req_writer.lock().unwrap().clone();
+        let req_writer = match req_writer {
+            Some(w) => w,
+            None => {
                 return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "unknown msg",
+                    std::io::ErrorKind::NotConnected,
+                    "connection closed",
                 ))
             }
-        }?;
-        let res = self.req_writer.send(tosend).await;
+        };
+        let res = req_writer.send(tosend).await;
         match res {
             Ok(_) => Ok(()),
             Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, e)),
         }
     }
 
+    // message id for a request built by the caller, unique on this connection
+    pub fn next_id(&self) -> u32 {
+        self.last_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn unbind_request(&self) -> Option<(tokio::sync::mpsc::Sender<Vec<u8>>, Vec<u8>)> {
+        let req_writer = self.req_writer.lock().unwrap().take()?;
+        let msg = Message {
+            id: self.next_id(),
+            params: MessageParams::Unbind(ldap::MsgUnbind {}),
+            controls: Vec::new(),
+        };
+        Some((req_writer, msg.encode().ok()?))
+    }
+
+    // sends an unbind, waits for everything queued to be written and closes the connection.
+    // requests still waiting for a response fail.
+    pub async fn unbind(&self) -> Result<()> {
+        let res = match self.unbind_request() {
+            Some((req_writer, unbind)) => req_writer
+                .send(unbind)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::BrokenPipe, e)),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "connection closed",
+            )),
+        };
+        let writer_task = self.writer_task.lock().unwrap().take();
+        if let Some(t) = writer_task {
+            let _ = t.await;
+        }
+        let reader_task = self.reader_task.lock().unwrap().take();
+        if let Some(t) = reader_task {
+            t.abort();
+            let _ = t.await;
+        }
+        self.contexts.clear();
+        res
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        match self.unbind().await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotConnected => Ok(()),
+            r => r,
+        }
+    }
+
     pub async fn send_request_w(&self, msg: ldap::Message) -> Result<Vec<Message>> {
         let (tx, rx) = oneshot::channel();
         let id = msg.id;
-        self.contexts.add(
+        let added = self.contexts.add(
             id,
             Context {
                 notif: tx,
                 messages: Vec::new(),
             },
         );
+        if !added {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "connection closed",
+            ));
+        }
         let res = self.send_request(msg).await;
         if let Err(e) = res {
             self.contexts.remove(id);
@@ -128,19 +222,21 @@ impl ClientConnection {
 
     pub async fn send_request_bind(&self, name: &str, password: &str) -> Result<MsgBindResponse> {
         let msg = Message {
-            id: self
-                .last_id
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            id: self.next_id(),
             params: MessageParams::Bind(MsgBind {
                 version: 3,
                 name: name.to_owned(),
                 password: password.to_owned(),
             }),
+            controls: Vec::new(),
         };
 
         let mut res = self.send_request_w(msg).await?;
         if res.len() == 1 {
             if let MessageParams::BindResponse(r) = res.remove(0).params {
+                // a failed bind leaves the connection anonymous
+                *self.bound.lock().unwrap() =
+                    (r.res == ldap::RESULT_SUCCESS).then(|| name.to_owned());
                 return Ok(r);
             }
         }
@@ -151,34 +247,173 @@ impl ClientConnection {
     }
 }
 
+// best effort close, the writer task still sends the unbind and shuts the stream down
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        if let Some((req_writer, unbind)) = self.unbind_request() {
+            let _ = req_writer.try_send(unbind);
+        }
+        if let Some(t) = self.reader_task.lock().unwrap().take() {
+            t.abort();
+        }
+        self.contexts.clear();
+    }
+}
+
+fn webpki_roots() -> tokio_rustls::rustls::RootCertStore {
+    let mut roots = tokio_rustls::rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    roots
+}
+
+// trusts the public webpki roots
+pub fn default_tls_config() -> Arc<ClientConfig> {
+    static CONFIG: std::sync::OnceLock<Arc<ClientConfig>> = std::sync::OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(webpki_roots())
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+// trusts the certificates in a pem file next to the webpki roots, e.g. a dev or ci ca
+pub fn tls_config_with_ca_file(path: &str) -> Result<Arc<ClientConfig>> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+    let mut roots = webpki_roots();
+    let certs =
+        CertificateDer::pem_file_iter(path).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+    for cert in certs {
+        let cert = cert.map_err(|e| invalid(format!("{}: {}", path, e)))?;
+        roots
+            .add(cert)
+            .map_err(|e| invalid(format!("{}: {}", path, e)))?;
+    }
+    Ok(Arc::new(
+        ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    ))
+}
+
+// accepts a plain host:port or an ldap://, ldaps:// or ldapi:// url, several separated by
+// whitespace are tried in order until one connects
 pub async fn connect(remote_address: &str) -> Result<ClientConnection> {
+    connect_with_max_message_size(remote_address, tokiou::DEFAULT_MAX_MESSAGE_SIZE).await
+}
+
+pub async fn connect_with_max_message_size(
+    remote_address: &str,
+    max_message_size: usize,
+) -> Result<ClientConnection> {
+    connect_with(remote_address, max_message_size, default_tls_config()).await
+}
+
+// ldaps urls are verified with tls_config instead of the webpki roots
+pub async fn connect_with_tls_config(
+    remote_address: &str,
+    tls_config: Arc<ClientConfig>,
+) -> Result<ClientConnection> {
+    connect_with(remote_address, tokiou::DEFAULT_MAX_MESSAGE_SIZE, tls_config).await
+}
+
+async fn connect_with(
+    remote_address: &str,
+    max_message_size: usize,
+    tls_config: Arc<ClientConfig>,
+) -> Result<ClientConnection> {
+    if remote_address.split_whitespace().nth(1).is_some() {
+        let mut servers = ServerList::parse(remote_address, Strategy::Failover)?;
+        servers.set_max_message_size(max_message_size);
+        servers.set_tls_config(tls_config);
+        return servers.connect().await;
+    }
+    connect_one(remote_address.trim(), max_message_size, tls_config).await
+}
+
+pub(crate) async fn connect_one(
+    remote_address: &str,
+    max_message_size: usize,
+    tls_config: Arc<ClientConfig>,
+) -> Result<ClientConnection> {
+    if !remote_address.contains("://") {
+        return Ok(start(
+            TcpStream::connect(remote_address).await?,
+            max_message_size,
+        ));
+    }
+    let url = LdapUrl::parse(remote_address)?;
+    match url.scheme {
+        Scheme::Ldap => Ok(start(
+            TcpStream::connect(url.socket_address()).await?,
+            max_message_size,
+        )),
+        Scheme::Ldaps => {
+            let stream = TcpStream::connect(url.socket_address()).await?;
+            let name = match ServerName::try_from(url.host.clone()) {
+                Ok(n) => n,
+                Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)),
+            };
+            Ok(start(
+                tokio_rustls::TlsConnector::from(tls_config)
+                    .connect(name, stream)
+                    .await?,
+                max_message_size,
+            ))
+        }
+        #[cfg(unix)]
+        Scheme::Ldapi => Ok(start(
+            tokio::net::UnixStream::connect(url.socket_path()).await?,
+            max_message_size,
+        )),
+        #[cfg(not(unix))]
+        Scheme::Ldapi => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "ldapi is not supported on this platform",
+        )),
+    }
+}
+
+fn start<S: AsyncRead + AsyncWrite + Send + 'static>(
+    stream: S,
+    max_message_size: usize,
+) -> ClientConnection {
     let (transmit_tx, mut transmit_rx) = tokio::sync::mpsc::channel(1024);
-    let stream = TcpStream::connect(remote_address).await?;
-    let (mut reader, mut writer) = stream.into_split();
+    let (mut reader, mut writer) = tokio::io::split(stream);
 
-    let _writer_task = tokio::spawn(async move {
+    let writer_task = tokio::spawn(async move {
         loop {
             let data: Option<Vec<u8>> = transmit_rx.recv().await;
             match data {
                 Some(d) => {
                     if (writer.write_all(d.as_ref()).await).is_err() {
-                        break;
+                        return;
                     }
                 }
                 None => break,
             }
         }
+        let _ = writer.shutdown().await;
     });
     let contexts = std::sync::Arc::new(Contexts::new());
     let contexts_clone = contexts.clone();
-    let _reader_task = tokio::spawn(async move {
-        let mut decode_context = tokiou::DecodeContext::new();
+    let reader_task = tokio::spawn(async move {
+        let mut decode_context = tokiou::DecodeContext::with_max_size(max_message_size);
         loop {
             let res = decode_context.get_message(&mut reader).await;
             let msg = match res {
                 Ok(msg) => msg,
                 Err(_) => break,
             };
+            if let MessageParams::ExtendedResponse(r) = &msg.params {
+                if msg.id == 0 && r.name.as_deref() == Some(ldap::NOTICE_OF_DISCONNECTION) {
+                    println!("server disconnected: {}", r.result.diag);
+                    break;
+                }
+            }
             let out = contexts_clone.update(msg);
             match out {
                 Some((m, s)) => {
@@ -189,10 +424,65 @@ pub async fn connect(remote_address: &str) -> Result<ClientConnection> {
                 None => continue,
             }
         }
+        contexts_clone.clear();
     });
-    Ok(ClientConnection {
-        req_writer: transmit_tx,
+    ClientConnection {
+        req_writer: std::sync::Mutex::new(Some(transmit_tx)),
+        writer_task: std::sync::Mutex::new(Some(writer_task)),
+        reader_task: std::sync::Mutex::new(Some(reader_task)),
         contexts,
-        last_id: AtomicU32::new(0),
-    })
+        // 0 is reserved for unsolicited notifications
+        last_id: AtomicU32::new(1),
+        rate_limiter: None,
+        bound: std::sync::Mutex::new(None),
+    }
+}
+
+#[tokio::test]
+async fn unbind_test() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = tokio::spawn(async move {
+        let (mut s, _) = listener.accept().await.unwrap();
+        let mut dec = tokiou::DecodeContext::new();
+        let mut got = Vec::new();
+        // never answers, reads until the client shuts its side down
+        while let Ok(m) = dec.get_message(&mut s).await {
+            got.push(m.params);
+        }
+        got
+    });
+    let c = std::sync::Arc::new(connect(&addr).await.unwrap());
+    let c2 = c.clone();
+    let pending = tokio::spawn(async move { c2.send_request_bind("", "").await });
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    c.unbind().await.unwrap();
+    assert!(pending.await.unwrap().is_err());
+    assert_eq!(
+        c.send_request_bind("", "").await.unwrap_err().kind(),
+        std::io::ErrorKind::NotConnected
+    );
+    c.close().await.unwrap();
+    let got = server.await.unwrap();
+    assert_eq!(got.len(), 2);
+    assert!(matches!(got[0], MessageParams::Bind(_)));
+    assert!(matches!(got[1], MessageParams::Unbind(_)));
+}
+
+#[tokio::test]
+async fn server_gone_test() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+    let c = connect(&addr).await.unwrap();
+    assert_eq!(c.next_id(), 1);
+    drop(server.await.unwrap());
+    while !c.is_closed() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let search = crate::server::search_request(c.next_id(), "", ldap::SearchScope::BaseObject, &[]);
+    let r = tokio::time::timeout(std::time::Duration::from_secs(5), c.send_request_w(search))
+        .await
+        .unwrap();
+    assert_eq!(r.unwrap_err().kind(), std::io::ErrorKind::NotConnected);
 }
@@ -1,11 +1,14 @@
 use std::io::Cursor;
-use std::io::Read;
 use std::io::Result;
 
+use byteorder::ReadBytesExt;
+
 use crate::asn1;
 use crate::ldap::*;
 
 const LDAP_MAX_PARAM_SIZE: usize = 1024;
+// same limit as the text filter parser, deeper nesting would overflow the stack
+const LDAP_MAX_FILTER_DEPTH: usize = 64;
 
 pub fn ldap_read_filter_attr_val_assertion(
     cursor: &mut Cursor<&[u8]>,
@@ -23,8 +26,7 @@ pub fn ldap_read_filter_attr_desc(cursor: &mut Cursor<&[u8]>) -> Result<FilterPr
     if size > LDAP_MAX_PARAM_SIZE {
         return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
     }
-    let mut buf = vec![0; size];
-    cursor.read_exact(&mut buf)?;
+    let buf = asn1::read_bytes(cursor, size)?;
     match std::str::from_utf8(&buf) {
         Ok(name) => Ok(FilterPresent {
             name: name.to_owned(),
@@ -33,7 +35,7 @@ pub fn ldap_read_filter_attr_desc(cursor: &mut Cursor<&[u8]>) -> Result<FilterPr
     }
 }
 
-pub fn ldap_read_filter_and(cursor: &mut Cursor<&[u8]>) -> Result<FilterAnd> {
+fn ldap_read_filter_list(cursor: &mut Cursor<&[u8]>, depth: usize) -> Result<Vec<Filter>> {
     let size = asn1::read_size(cursor)?;
     if size > LDAP_MAX_PARAM_SIZE {
         return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
@@ -41,18 +43,104 @@ pub fn ldap_read_filter_and(cursor: &mut Cursor<&[u8]>) -> Result<FilterAnd> {
     let pos = cursor.position();
     let mut items: Vec<Filter> = Vec::new();
     while cursor.position() < (pos + size as u64) {
-        let f = ldap_read_filter(cursor)?;
+        let f = read_filter(cursor, depth)?;
         items.push(f)
     }
-    Ok(FilterAnd { items })
+    Ok(items)
+}
+
+pub fn ldap_read_filter_and(cursor: &mut Cursor<&[u8]>) -> Result<FilterAnd> {
+    Ok(FilterAnd {
+        items: ldap_read_filter_list(cursor, 1)?,
+    })
+}
+
+fn read_tagged_string(cursor: &mut Cursor<&[u8]>) -> Result<String> {
+    let size = asn1::read_size(cursor)?;
+    let buf = asn1::read_bytes(cursor, size)?;
+    String::from_utf8(buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+pub fn ldap_read_filter_substrings(cursor: &mut Cursor<&[u8]>) -> Result<FilterSubstrings> {
+    let size = asn1::read_size(cursor)?;
+    if size > LDAP_MAX_PARAM_SIZE {
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+    }
+    let name = asn1::read_string(cursor)?;
+    let mut out = FilterSubstrings {
+        name,
+        initial: None,
+        any: Vec::new(),
+        final_: None,
+    };
+    asn1::read_tag(cursor)?;
+    let size = asn1::read_size(cursor)?;
+    let pos = cursor.position();
+    while cursor.position() < (pos + size as u64) {
+        let tag = asn1::read_tag(cursor)?;
+        let value = read_tagged_string(cursor)?;
+        match tag {
+            0x80 => out.initial = Some(value),
+            0x81 => out.any.push(value),
+            0x82 => out.final_ = Some(value),
+            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
+        }
+    }
+    Ok(out)
+}
+
+pub fn ldap_read_filter_extensible(cursor: &mut Cursor<&[u8]>) -> Result<FilterExtensible> {
+    let size = asn1::read_size(cursor)?;
+    if size > LDAP_MAX_PARAM_SIZE {
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+    }
+    let mut out = FilterExtensible {
+        matching_rule: None,
+        name: None,
+        value: String::new(),
+        dn_attributes: false,
+    };
+    let pos = cursor.position();
+    while cursor.position() < (pos + size as u64) {
+        match asn1::read_tag(cursor)? {
+            0x81 => out.matching_rule = Some(read_tagged_string(cursor)?),
+            0x82 => out.name = Some(read_tagged_string(cursor)?),
+            0x83 => out.value = read_tagged_string(cursor)?,
+            0x84 => {
+                asn1::read_size(cursor)?;
+                out.dn_attributes = cursor.read_u8()? != 0;
+            }
+            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
+        }
+    }
+    Ok(out)
 }
 
 pub fn ldap_read_filter(cursor: &mut Cursor<&[u8]>) -> Result<Filter> {
+    read_filter(cursor, 0)
+}
+
+fn read_filter(cursor: &mut Cursor<&[u8]>, depth: usize) -> Result<Filter> {
+    if depth > LDAP_MAX_FILTER_DEPTH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "filter nested too deep",
+        ));
+    }
     let tag = asn1::read_tag(cursor)?;
     match tag {
         0xa0 => {
             // and
-            Ok(Filter::And(ldap_read_filter_and(cursor)?))
+            Ok(Filter::And(FilterAnd {
+                items: ldap_read_filter_list(cursor, depth + 1)?,
+            }))
+        }
+        0xa1 => Ok(Filter::Or(FilterOr {
+            items: ldap_read_filter_list(cursor, depth + 1)?,
+        })),
+        0xa2 => {
+            asn1::read_size(cursor)?;
+            Ok(Filter::Not(Box::new(read_filter(cursor, depth + 1)?)))
         }
         0xa3 => {
             // equality match
@@ -60,35 +148,70 @@ pub fn ldap_read_filter(cursor: &mut Cursor<&[u8]>) -> Result<Filter> {
                 cursor,
             )?))
         }
+        0xa4 => Ok(Filter::Substrings(ldap_read_filter_substrings(cursor)?)),
+        0xa5 => Ok(Filter::GreaterOrEqual(ldap_read_filter_attr_val_assertion(
+            cursor,
+        )?)),
+        0xa6 => Ok(Filter::LessOrEqual(ldap_read_filter_attr_val_assertion(
+            cursor,
+        )?)),
         0x87 => {
             // present
             Ok(Filter::Present(ldap_read_filter_attr_desc(cursor)?))
         }
-        _ => Ok(Filter::Empty()),
+        0xa8 => Ok(Filter::ApproxMatch(ldap_read_filter_attr_val_assertion(
+            cursor,
+        )?)),
+        0xa9 => Ok(Filter::ExtensibleMatch(ldap_read_filter_extensible(
+            cursor,
+        )?)),
+        _ => {
+            // unknown filters are skipped
+            let size = asn1::read_size(cursor)?;
+            cursor.set_position(cursor.position() + size as u64);
+            Ok(Filter::Empty())
+        }
     }
 }
 
 pub fn ldap_write_bind_request(id: u32, name: &str, password: &str) -> Result<Vec<u8>> {
+    ldap_write_bind(
+        id,
+        &MsgBind {
+            version: 3,
+            name: name.to_owned(),
+            password: password.to_owned(),
+        },
+    )
+}
+
+pub fn ldap_write_bind(id: u32, msg: &MsgBind) -> Result<Vec<u8>> {
     let mut e = asn1::Encoder::new();
     e.start_seq(0x30)?;
     e.write_int(id)?;
     e.start_seq(0x60)?;
-    e.write_int(3)?; //version
-    e.write_octet_string(name.as_bytes())?;
-    e.write_octet_string_with_tag(0x80, password.as_bytes())?;
+    e.write_int(msg.version)?;
+    e.write_octet_string(msg.name.as_bytes())?;
+    e.write_octet_string_with_tag(0x80, msg.password.as_bytes())?;
     Ok(e.encode())
 }
 
 fn enc_filter(e: &mut asn1::Encoder, f: &Filter) -> Result<()> {
+    let enc_ava = |e: &mut asn1::Encoder, tag: u8, f: &FilterAttributeValueAssertion| {
+        e.start_seq(tag)?;
+        e.write_octet_string(f.name.as_bytes())?;
+        e.write_octet_string(f.value.as_bytes())?;
+        e.end_seq();
+        Ok(())
+    };
     match f {
-        Filter::Empty() => Ok(()),
-        Filter::EqualityMatch(f) => {
-            e.start_seq(0xa3)?;
-            e.write_octet_string(f.name.as_bytes())?;
-            e.write_octet_string(f.value.as_bytes())?;
+        // unknown filters are read as Empty and match nothing, like an empty or
+        Filter::Empty() => {
+            e.start_seq(0xa1)?;
             e.end_seq();
             Ok(())
         }
+        Filter::EqualityMatch(f) => enc_ava(e, 0xa3, f),
         Filter::Present(f) => e.write_octet_string_with_tag(0x87, f.name.as_bytes()),
         Filter::And(f) => {
             e.start_seq(0xa0)?;
@@ -98,6 +221,55 @@ fn enc_filter(e: &mut asn1::Encoder, f: &Filter) -> Result<()> {
             e.end_seq();
             Ok(())
         }
+        Filter::Or(f) => {
+            e.start_seq(0xa1)?;
+            for a in &f.items {
+                enc_filter(e, a)?
+            }
+            e.end_seq();
+            Ok(())
+        }
+        Filter::Not(f) => {
+            e.start_seq(0xa2)?;
+            enc_filter(e, f)?;
+            e.end_seq();
+            Ok(())
+        }
+        Filter::Substrings(f) => {
+            e.start_seq(0xa4)?;
+            e.write_octet_string(f.name.as_bytes())?;
+            e.start_seq(0x30)?;
+            if let Some(v) = &f.initial {
+                e.write_octet_string_with_tag(0x80, v.as_bytes())?;
+            }
+            for v in &f.any {
+                e.write_octet_string_with_tag(0x81, v.as_bytes())?;
+            }
+            if let Some(v) = &f.final_ {
+                e.write_octet_string_with_tag(0x82, v.as_bytes())?;
+            }
+            e.end_seq();
+            e.end_seq();
+            Ok(())
+        }
+        Filter::GreaterOrEqual(f) => enc_ava(e, 0xa5, f),
+        Filter::LessOrEqual(f) => enc_ava(e, 0xa6, f),
+        Filter::ApproxMatch(f) => enc_ava(e, 0xa8, f),
+        Filter::ExtensibleMatch(f) => {
+            e.start_seq(0xa9)?;
+            if let Some(r) = &f.matching_rule {
+                e.write_octet_string_with_tag(0x81, r.as_bytes())?;
+            }
+            if let Some(n) = &f.name {
+                e.write_octet_string_with_tag(0x82, n.as_bytes())?;
+            }
+            e.write_octet_string_with_tag(0x83, f.value.as_bytes())?;
+            if f.dn_attributes {
+                e.write_bool_with_tag(0x84, true)?;
+            }
+            e.end_seq();
+            Ok(())
+        }
     }
 }
 
@@ -111,74 +283,616 @@ pub fn ldap_write_search_request(id: u32, msg: &MsgSearch) -> Result<Vec<u8>> {
     e.write_enum(msg.deref as u8)?;
     e.write_int(msg.size_limit)?;
     e.write_int(msg.time_limit)?;
-    e.write_bool(false)?;
-
+    e.write_bool(msg.types_only)?;
     enc_filter(&mut e, &msg.filter)?;
+    e.start_seq(0x30)?;
+    for a in &msg.attributes {
+        e.write_octet_string(a.as_bytes())?;
+    }
     Ok(e.encode())
 }
 
 pub fn ldap_write_bind_response(id: u32, res: u32) -> Result<Vec<u8>> {
+    ldap_write_bind_result(
+        id,
+        &MsgBindResponse {
+            res,
+            matched_dn: String::new(),
+            diag: String::new(),
+        },
+    )
+}
+
+pub fn ldap_write_bind_result(id: u32, res: &MsgBindResponse) -> Result<Vec<u8>> {
+    ldap_write_result(
+        id,
+        0x61,
+        &MsgResult {
+            res: res.res,
+            matched_dn: res.matched_dn.clone(),
+            diag: res.diag.clone(),
+        },
+    )
+}
+
+pub fn ldap_write_search_res_done(id: u32, res: u32) -> Result<Vec<u8>> {
+    ldap_write_search_res_done_result(
+        id,
+        &MsgSearchResultDone {
+            res,
+            matched_dn: String::new(),
+            diag: String::new(),
+        },
+    )
+}
+
+pub fn ldap_write_search_res_done_result(id: u32, res: &MsgSearchResultDone) -> Result<Vec<u8>> {
     let mut e = asn1::Encoder::new();
     e.start_seq(0x30)?;
     e.write_int(id)?;
-    e.start_seq(0x61)?;
-    e.write_enum(res as u8)?;
-    e.write_octet_string(&[])?;
-    e.write_octet_string(&[])?;
+    e.start_seq(0x65)?;
+    e.write_enum(res.res as u8)?;
+    e.write_octet_string(res.matched_dn.as_bytes())?;
+    e.write_octet_string(res.diag.as_bytes())?;
+    e.end_seq();
+    e.end_seq();
     Ok(e.encode())
 }
 
-pub fn ldap_write_search_res_done(id: u32, res: u32) -> Result<Vec<u8>> {
+pub fn ldap_write_search_res_ref(id: u32, uris: &[String]) -> Result<Vec<u8>> {
     let mut e = asn1::Encoder::new();
     e.start_seq(0x30)?;
     e.write_int(id)?;
-    e.start_seq(0x65)?;
-    e.write_enum(res as u8)?;
-    e.write_octet_string("a1".as_bytes())?;
-    e.write_octet_string("a2".as_bytes())?;
+    e.start_seq(0x73)?;
+    for uri in uris {
+        e.write_octet_string(uri.as_bytes())?;
+    }
     e.end_seq();
     e.end_seq();
     Ok(e.encode())
 }
 
+fn enc_attribute_list(e: &mut asn1::Encoder, attrs: &[PartialAttribute]) -> Result<()> {
+    e.start_seq(0x30)?;
+    for attr in attrs {
+        e.start_seq(0x30)?;
+        e.write_octet_string(attr.name.as_bytes())?;
+        e.start_seq(0x31)?;
+        for value in &attr.values {
+            e.write_octet_string(value.as_bytes())?;
+        }
+        e.end_seq();
+        e.end_seq();
+    }
+    e.end_seq();
+    Ok(())
+}
+
 pub fn ldap_write_search_res_entry(
     id: u32,
     name: &str,
-    attrs: &Vec<crate::ldap::PartialAttribute>,
+    attrs: &[crate::ldap::PartialAttribute],
 ) -> Result<Vec<u8>> {
     let mut e = asn1::Encoder::new();
     e.start_seq(0x30)?;
     e.write_int(id)?;
     e.start_seq(0x64)?;
     e.write_octet_string(name.as_bytes())?;
+    enc_attribute_list(&mut e, attrs)?;
+    Ok(e.encode())
+}
+
+pub fn ldap_write_add_request(id: u32, msg: &MsgAdd) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
     e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.start_seq(0x68)?;
+    e.write_octet_string(msg.entry.as_bytes())?;
+    enc_attribute_list(&mut e, &msg.attributes)?;
+    Ok(e.encode())
+}
 
-    for attr in attrs {
+pub fn ldap_write_modify_request(id: u32, msg: &MsgModify) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.start_seq(0x66)?;
+    e.write_octet_string(msg.object.as_bytes())?;
+    e.start_seq(0x30)?;
+    for change in &msg.changes {
         e.start_seq(0x30)?;
-        e.write_octet_string(attr.name.as_bytes())?;
+        e.write_enum(change.operation as u8)?;
+        e.start_seq(0x30)?;
+        e.write_octet_string(change.modification.name.as_bytes())?;
         e.start_seq(0x31)?;
-        for value in &attr.values {
+        for value in &change.modification.values {
             e.write_octet_string(value.as_bytes())?;
         }
         e.end_seq();
         e.end_seq();
+        e.end_seq();
+    }
+    Ok(e.encode())
+}
+
+pub fn ldap_write_unbind_request(id: u32) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.write_octet_string_with_tag(0x42, &[])?;
+    Ok(e.encode())
+}
+
+pub fn ldap_write_del_request(id: u32, msg: &MsgDel) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.write_octet_string_with_tag(0x4a, msg.entry.as_bytes())?;
+    Ok(e.encode())
+}
+
+fn ldap_write_result(id: u32, tag: u8, res: &MsgResult) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.start_seq(tag)?;
+    e.write_enum(res.res as u8)?;
+    e.write_octet_string(res.matched_dn.as_bytes())?;
+    e.write_octet_string(res.diag.as_bytes())?;
+    Ok(e.encode())
+}
+
+pub fn ldap_write_add_response(id: u32, res: &MsgResult) -> Result<Vec<u8>> {
+    ldap_write_result(id, 0x69, res)
+}
+
+pub fn ldap_write_modify_response(id: u32, res: &MsgResult) -> Result<Vec<u8>> {
+    ldap_write_result(id, 0x67, res)
+}
+
+pub fn ldap_write_del_response(id: u32, res: &MsgResult) -> Result<Vec<u8>> {
+    ldap_write_result(id, 0x6b, res)
+}
+
+pub fn ldap_write_moddn_request(id: u32, msg: &MsgModDn) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.start_seq(0x6c)?;
+    e.write_octet_string(msg.entry.as_bytes())?;
+    e.write_octet_string(msg.new_rdn.as_bytes())?;
+    e.write_bool(msg.delete_old_rdn)?;
+    if let Some(superior) = &msg.new_superior {
+        e.write_octet_string_with_tag(0x80, superior.as_bytes())?;
     }
+    Ok(e.encode())
+}
 
+pub fn ldap_write_moddn_response(id: u32, res: &MsgResult) -> Result<Vec<u8>> {
+    ldap_write_result(id, 0x6d, res)
+}
+
+pub fn ldap_write_compare_request(id: u32, msg: &MsgCompare) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.start_seq(0x6e)?;
+    e.write_octet_string(msg.entry.as_bytes())?;
+    e.start_seq(0x30)?;
+    e.write_octet_string(msg.name.as_bytes())?;
+    e.write_octet_string(msg.value.as_bytes())?;
     Ok(e.encode())
 }
 
+pub fn ldap_write_compare_response(id: u32, res: &MsgResult) -> Result<Vec<u8>> {
+    ldap_write_result(id, 0x6f, res)
+}
+
+pub fn ldap_write_abandon_request(id: u32, msg: &MsgAbandon) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.write_int_with_tag(0x50, msg.id)?;
+    Ok(e.encode())
+}
+
+pub fn ldap_write_extended_request(id: u32, msg: &MsgExtendedRequest) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.start_seq(0x77)?;
+    e.write_octet_string_with_tag(0x80, msg.name.as_bytes())?;
+    if let Some(value) = &msg.value {
+        e.write_octet_string_with_tag(0x81, value)?;
+    }
+    Ok(e.encode())
+}
+
+pub fn ldap_write_cancel_request(id: u32, cancel_id: u32) -> Result<Vec<u8>> {
+    let mut value = asn1::Encoder::new();
+    value.start_seq(0x30)?;
+    value.write_int(cancel_id)?;
+    ldap_write_extended_request(
+        id,
+        &MsgExtendedRequest {
+            name: CANCEL_REQUEST.to_owned(),
+            value: Some(value.encode()),
+        },
+    )
+}
+
+// the id of the operation a cancel request refers to
+pub fn ldap_read_cancel_id(value: &[u8]) -> Result<u32> {
+    let mut cursor = Cursor::new(value);
+    let _tag = asn1::read_tag(&mut cursor)?;
+    let _size = asn1::read_size(&mut cursor)?;
+    asn1::read_uint(&mut cursor)
+}
+
+// value of a password modify request, rfc 3062
+pub fn ldap_write_password_modify(
+    user: Option<&str>,
+    old_password: Option<&str>,
+    new_password: Option<&str>,
+) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    for (tag, v) in [(0x80, user), (0x81, old_password), (0x82, new_password)] {
+        if let Some(v) = v {
+            e.write_octet_string_with_tag(tag, v.as_bytes())?;
+        }
+    }
+    Ok(e.encode())
+}
+
+// user, old password and new password of a password modify request, each optional
+pub fn ldap_read_password_modify(
+    value: &[u8],
+) -> Result<(Option<String>, Option<String>, Option<String>)> {
+    let mut fields = (None, None, None);
+    let mut cursor = Cursor::new(value);
+    let _tag = asn1::read_tag(&mut cursor)?;
+    let size = asn1::read_size(&mut cursor)?;
+    let end = cursor.position() + size as u64;
+    while cursor.position() < end {
+        let tag = asn1::read_tag(&mut cursor)?;
+        cursor.set_position(cursor.position() - 1);
+        let value = asn1::read_string(&mut cursor)?;
+        match tag {
+            0x80 => fields.0 = Some(value),
+            0x81 => fields.1 = Some(value),
+            0x82 => fields.2 = Some(value),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unknown password modify field {:#x}", tag),
+                ))
+            }
+        }
+    }
+    Ok(fields)
+}
+
+pub fn ldap_write_password_modify_response(generated: &str) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_octet_string_with_tag(0x80, generated.as_bytes())?;
+    Ok(e.encode())
+}
+
+// the password the server generated, if the request did not carry one
+pub fn ldap_read_password_modify_response(value: &[u8]) -> Result<Option<String>> {
+    let mut cursor = Cursor::new(value);
+    let _tag = asn1::read_tag(&mut cursor)?;
+    let size = asn1::read_size(&mut cursor)?;
+    let end = cursor.position() + size as u64;
+    while cursor.position() < end {
+        let tag = asn1::read_tag(&mut cursor)?;
+        cursor.set_position(cursor.position() - 1);
+        let value = asn1::read_string(&mut cursor)?;
+        if tag == 0x80 {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+pub fn ldap_paged_results_control(size: u32, cookie: &[u8]) -> Result<Control> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(size)?;
+    e.write_octet_string(cookie)?;
+    Ok(Control {
+        oid: PAGED_RESULTS_CONTROL.to_owned(),
+        critical: false,
+        value: Some(e.encode()),
+    })
+}
+
+// size estimate and cookie of a paged results control, an empty cookie ends the search
+pub fn ldap_read_paged_results(value: &[u8]) -> Result<(u32, Vec<u8>)> {
+    let mut cursor = Cursor::new(value);
+    let _tag = asn1::read_tag(&mut cursor)?;
+    let _size = asn1::read_size(&mut cursor)?;
+    let size = asn1::read_uint(&mut cursor)?;
+    let _tag = asn1::read_tag(&mut cursor)?;
+    let len = asn1::read_size(&mut cursor)?;
+    let cookie = asn1::read_bytes(&mut cursor, len)?;
+    Ok((size, cookie))
+}
+
+pub fn ldap_write_extended_response(id: u32, res: &MsgExtendedResponse) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.start_seq(0x78)?;
+    e.write_enum(res.result.res as u8)?;
+    e.write_octet_string(res.result.matched_dn.as_bytes())?;
+    e.write_octet_string(res.result.diag.as_bytes())?;
+    if let Some(name) = &res.name {
+        e.write_octet_string_with_tag(0x8a, name.as_bytes())?;
+    }
+    if let Some(value) = &res.value {
+        e.write_octet_string_with_tag(0x8b, value)?;
+    }
+    Ok(e.encode())
+}
+
+pub fn ldap_write_intermediate_response(id: u32, res: &MsgIntermediateResponse) -> Result<Vec<u8>> {
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_int(id)?;
+    e.start_seq(0x79)?;
+    if let Some(name) = &res.name {
+        e.write_octet_string_with_tag(0x80, name.as_bytes())?;
+    }
+    if let Some(value) = &res.value {
+        e.write_octet_string_with_tag(0x81, value)?;
+    }
+    Ok(e.encode())
+}
+
+// appends controls to an encoded message, inside its outer sequence
+fn ldap_write_controls(data: Vec<u8>, controls: &[Control]) -> Result<Vec<u8>> {
+    let mut cursor = Cursor::new(data.as_slice());
+    let _tag = asn1::read_tag(&mut cursor)?;
+    let _size = asn1::read_size(&mut cursor)?;
+    let mut e = asn1::Encoder::new();
+    e.start_seq(0x30)?;
+    e.write_raw(&data[cursor.position() as usize..]);
+    e.start_seq(0xa0)?;
+    for c in controls {
+        e.start_seq(0x30)?;
+        e.write_octet_string(c.oid.as_bytes())?;
+        if c.critical {
+            e.write_bool(true)?;
+        }
+        if let Some(value) = &c.value {
+            e.write_octet_string(value)?;
+        }
+        e.end_seq();
+    }
+    Ok(e.encode())
+}
+
+fn ldap_read_controls(cursor: &mut Cursor<&[u8]>) -> Result<Vec<Control>> {
+    let _tag = asn1::read_tag(cursor)?;
+    let size = asn1::read_size(cursor)?;
+    let end = cursor.position() + size as u64;
+    let mut controls = Vec::new();
+    while cursor.position() < end {
+        let _tag = asn1::read_tag(cursor)?;
+        let size = asn1::read_size(cursor)?;
+        let control_end = cursor.position() + size as u64;
+        let mut c = Control {
+            oid: asn1::read_string(cursor)?,
+            critical: false,
+            value: None,
+        };
+        while cursor.position() < control_end {
+            let tag = asn1::read_tag(cursor)?;
+            let size = asn1::read_size(cursor)?;
+            let buf = asn1::read_bytes(cursor, size)?;
+            match tag {
+                0x01 => c.critical = buf.iter().any(|b| *b != 0),
+                0x04 => c.value = Some(buf),
+                _ => {}
+            }
+        }
+        controls.push(c);
+    }
+    Ok(controls)
+}
+
+pub fn ldap_write_message(msg: &Message) -> Result<Vec<u8>> {
+    let data = ldap_write_params(msg.id, &msg.params)?;
+    if msg.controls.is_empty() {
+        return Ok(data);
+    }
+    ldap_write_controls(data, &msg.controls)
+}
+
+fn ldap_write_params(id: u32, params: &MessageParams) -> Result<Vec<u8>> {
+    match params {
+        MessageParams::Bind(b) => ldap_write_bind(id, b),
+        MessageParams::BindResponse(r) => ldap_write_bind_result(id, r),
+        MessageParams::Search(s) => ldap_write_search_request(id, s),
+        MessageParams::SearchResult(r) => ldap_write_search_res_entry(id, &r.name, &r.values),
+        MessageParams::MsgSearchResultDone(r) => ldap_write_search_res_done_result(id, r),
+        MessageParams::SearchResultReference(r) => ldap_write_search_res_ref(id, &r.uris),
+        MessageParams::Unbind(_) => ldap_write_unbind_request(id),
+        MessageParams::Add(a) => ldap_write_add_request(id, a),
+        MessageParams::AddResponse(r) => ldap_write_add_response(id, r),
+        MessageParams::Modify(m) => ldap_write_modify_request(id, m),
+        MessageParams::ModifyResponse(r) => ldap_write_modify_response(id, r),
+        MessageParams::Del(d) => ldap_write_del_request(id, d),
+        MessageParams::DelResponse(r) => ldap_write_del_response(id, r),
+        MessageParams::ModDn(m) => ldap_write_moddn_request(id, m),
+        MessageParams::ModDnResponse(r) => ldap_write_moddn_response(id, r),
+        MessageParams::Compare(c) => ldap_write_compare_request(id, c),
+        MessageParams::CompareResponse(r) => ldap_write_compare_response(id, r),
+        MessageParams::Abandon(a) => ldap_write_abandon_request(id, a),
+        MessageParams::ExtendedRequest(r) => ldap_write_extended_request(id, r),
+        MessageParams::ExtendedResponse(r) => ldap_write_extended_response(id, r),
+        MessageParams::IntermediateResponse(r) => ldap_write_intermediate_response(id, r),
+    }
+}
+
+fn ldap_read_partial_attribute(cursor: &mut Cursor<&[u8]>) -> Result<PartialAttribute> {
+    let _tag = asn1::read_tag(cursor)?;
+    let _size = asn1::read_size(cursor)?;
+    let name: String = asn1::read_string(cursor)?;
+    let _tag = asn1::read_tag(cursor)?;
+    let size = asn1::read_size(cursor)?;
+    let end = cursor.position() + size as u64;
+    let mut values = Vec::new();
+    while cursor.position() < end {
+        values.push(asn1::read_string(cursor)?);
+    }
+    Ok(PartialAttribute { name, values })
+}
+
+fn ldap_read_attribute_list(cursor: &mut Cursor<&[u8]>) -> Result<Vec<PartialAttribute>> {
+    let _tag = asn1::read_tag(cursor)?;
+    let size = asn1::read_size(cursor)?;
+    let end = cursor.position() + size as u64;
+    let mut attrs = Vec::new();
+    while cursor.position() < end {
+        attrs.push(ldap_read_partial_attribute(cursor)?);
+    }
+    Ok(attrs)
+}
+
+fn ldap_read_result(cursor: &mut Cursor<&[u8]>) -> Result<MsgResult> {
+    let _app_size = asn1::read_size(cursor)?;
+    let res = asn1::read_uint(cursor)?;
+    let matched_dn = asn1::read_string(cursor)?;
+    let diag = asn1::read_string(cursor)?;
+    Ok(MsgResult {
+        res,
+        matched_dn,
+        diag,
+    })
+}
+
+fn ldap_read_extended_request(cursor: &mut Cursor<&[u8]>) -> Result<MsgExtendedRequest> {
+    let size = asn1::read_size(cursor)?;
+    let end = cursor.position() + size as u64;
+    let mut out = MsgExtendedRequest {
+        name: String::new(),
+        value: None,
+    };
+    while cursor.position() < end {
+        let tag = asn1::read_tag(cursor)?;
+        let size = asn1::read_size(cursor)?;
+        let buf = asn1::read_bytes(cursor, size)?;
+        match tag {
+            0x80 => {
+                out.name = String::from_utf8(buf)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+            }
+            0x81 => out.value = Some(buf),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+fn ldap_read_extended_response(cursor: &mut Cursor<&[u8]>) -> Result<MsgExtendedResponse> {
+    let size = asn1::read_size(cursor)?;
+    let end = cursor.position() + size as u64;
+    let mut out = MsgExtendedResponse {
+        result: MsgResult {
+            res: asn1::read_uint(cursor)?,
+            matched_dn: asn1::read_string(cursor)?,
+            diag: asn1::read_string(cursor)?,
+        },
+        name: None,
+        value: None,
+    };
+    while cursor.position() < end {
+        let tag = asn1::read_tag(cursor)?;
+        let size = asn1::read_size(cursor)?;
+        let buf = asn1::read_bytes(cursor, size)?;
+        match tag {
+            0x8a => {
+                out.name = Some(
+                    String::from_utf8(buf)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+                )
+            }
+            0x8b => out.value = Some(buf),
+            // referrals are skipped
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+fn ldap_read_intermediate_response(cursor: &mut Cursor<&[u8]>) -> Result<MsgIntermediateResponse> {
+    let size = asn1::read_size(cursor)?;
+    let end = cursor.position() + size as u64;
+    let mut out = MsgIntermediateResponse {
+        name: None,
+        value: None,
+    };
+    while cursor.position() < end {
+        let tag = asn1::read_tag(cursor)?;
+        let size = asn1::read_size(cursor)?;
+        let buf = asn1::read_bytes(cursor, size)?;
+        match tag {
+            0x80 => {
+                out.name = Some(
+                    String::from_utf8(buf)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+                )
+            }
+            0x81 => out.value = Some(buf),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
 pub fn parse_message(data: &[u8]) -> Result<(Message, usize)> {
     if data.len() < 4 {
         return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock));
     }
     let mut cursor = std::io::Cursor::new(data);
     let _start_seq_tag = asn1::read_tag(&mut cursor)?;
-    let start_seq_len = asn1::read_size(&mut cursor)? as usize;
-    if data.len() < (start_seq_len + 2) {
+    let start_seq_len = match asn1::read_size(&mut cursor) {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock))
+        }
+        Err(e) => return Err(e),
+    };
+    // a length near usize::MAX must not wrap around to a small total
+    let total = (cursor.position() as usize)
+        .checked_add(start_seq_len)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "message too long"))?;
+    if data.len() < total {
         return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock));
     }
+    let mut cursor = std::io::Cursor::new(&data[..total]);
+    cursor.set_position((total - start_seq_len) as u64);
     let message_id = asn1::read_uint(&mut cursor)?;
     let msg_tag = asn1::read_tag(&mut cursor)?;
+    // controls follow the operation
+    let op_start = cursor.position();
+    let op_end = (asn1::read_size(&mut cursor)? as u64)
+        .checked_add(cursor.position())
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "operation too long")
+        })?;
+    cursor.set_position(op_start);
+    let controls = if op_end < total as u64 {
+        let mut c = std::io::Cursor::new(&data[..total]);
+        c.set_position(op_end);
+        ldap_read_controls(&mut c)?
+    } else {
+        Vec::new()
+    };
     match msg_tag {
         0x60 => {
             // bind
@@ -194,8 +908,9 @@ pub fn parse_message(data: &[u8]) -> Result<(Message, usize)> {
                         name,
                         password,
                     }),
+                    controls,
                 },
-                start_seq_len + 2,
+                total,
             ))
         }
         0x61 => {
@@ -212,8 +927,9 @@ pub fn parse_message(data: &[u8]) -> Result<(Message, usize)> {
                         matched_dn,
                         diag,
                     }),
+                    controls,
                 },
-                start_seq_len + 2,
+                total,
             ))
         }
         0x63 => {
@@ -224,8 +940,17 @@ pub fn parse_message(data: &[u8]) -> Result<(Message, usize)> {
             let deref = asn1::read_uint(&mut cursor)?;
             let size_limit = asn1::read_uint(&mut cursor)?;
             let time_limit = asn1::read_uint(&mut cursor)?;
-            let _typed_only = asn1::read_uint(&mut cursor);
+            let types_only = asn1::read_uint(&mut cursor)? != 0;
             let filter = ldap_read_filter(&mut cursor)?;
+            let mut attributes = Vec::new();
+            if cursor.position() < total as u64 {
+                let _tag = asn1::read_tag(&mut cursor)?;
+                let size = asn1::read_size(&mut cursor)?;
+                let end = cursor.position() + size as u64;
+                while cursor.position() < end {
+                    attributes.push(asn1::read_string(&mut cursor)?);
+                }
+            }
             Ok((
                 Message {
                     id: message_id,
@@ -236,38 +961,19 @@ pub fn parse_message(data: &[u8]) -> Result<(Message, usize)> {
                         filter,
                         size_limit,
                         time_limit,
+                        types_only,
+                        attributes,
                     }),
+                    controls,
                 },
-                start_seq_len + 2,
+                total,
             ))
         }
         0x64 => {
             // search result
             let _app_size = asn1::read_size(&mut cursor);
             let name = asn1::read_string(&mut cursor)?;
-            let _tag = asn1::read_tag(&mut cursor)?;
-            let _size = asn1::read_size(&mut cursor)?;
-            let mut partial_attr_list = Vec::new();
-            while cursor.position() < (start_seq_len + 2) as u64 {
-                let _tag = asn1::read_tag(&mut cursor)?;
-                let _size = asn1::read_size(&mut cursor)?;
-                let attr_name: String = asn1::read_string(&mut cursor)?;
-                let _tag = asn1::read_tag(&mut cursor)?;
-                let size = asn1::read_size(&mut cursor)?;
-                let mut buf = vec![0; size];
-                cursor.read_exact(buf.as_mut_slice())?;
-                let mut cur2 = std::io::Cursor::new(buf.as_slice());
-                let mut attr_values = Vec::new();
-                while cur2.position() < size as u64 {
-                    let s = asn1::read_string(&mut cur2)?;
-                    attr_values.push(s);
-                }
-                let attribute = crate::ldap::PartialAttribute {
-                    name: attr_name,
-                    values: attr_values,
-                };
-                partial_attr_list.push(attribute);
-            }
+            let partial_attr_list = ldap_read_attribute_list(&mut cursor)?;
             Ok((
                 Message {
                     id: message_id,
@@ -275,34 +981,236 @@ pub fn parse_message(data: &[u8]) -> Result<(Message, usize)> {
                         name,
                         values: partial_attr_list,
                     }),
+                    controls,
                 },
-                start_seq_len + 2,
+                total,
             ))
         }
         0x65 => {
             // search result done
-            let _app_size = asn1::read_size(&mut cursor);
-            let res = asn1::read_uint(&mut cursor)?;
+            let r = ldap_read_result(&mut cursor)?;
             Ok((
                 Message {
                     id: message_id,
-                    params: MessageParams::MsgSearchResultDone(MsgSearchResultDone { res }),
+                    params: MessageParams::MsgSearchResultDone(MsgSearchResultDone {
+                        res: r.res,
+                        matched_dn: r.matched_dn,
+                        diag: r.diag,
+                    }),
+                    controls,
                 },
-                start_seq_len + 2,
+                total,
             ))
         }
+        0x73 => {
+            // search result reference
+            let size = asn1::read_size(&mut cursor)?;
+            let end = cursor.position() + size as u64;
+            let mut uris = Vec::new();
+            while cursor.position() < end {
+                uris.push(asn1::read_string(&mut cursor)?);
+            }
+            Ok((
+                Message {
+                    id: message_id,
+                    params: MessageParams::SearchResultReference(MsgSearchResultReference { uris }),
+                    controls,
+                },
+                total,
+            ))
+        }
+        0x79 => Ok((
+            Message {
+                id: message_id,
+                params: MessageParams::IntermediateResponse(ldap_read_intermediate_response(
+                    &mut cursor,
+                )?),
+                controls,
+            },
+            total,
+        )),
 
+        0x66 => {
+            // modify
+            let _app_size = asn1::read_size(&mut cursor)?;
+            let object = asn1::read_string(&mut cursor)?;
+            let _tag = asn1::read_tag(&mut cursor)?;
+            let size = asn1::read_size(&mut cursor)?;
+            let end = cursor.position() + size as u64;
+            let mut changes = Vec::new();
+            while cursor.position() < end {
+                let _tag = asn1::read_tag(&mut cursor)?;
+                let _size = asn1::read_size(&mut cursor)?;
+                let operation = asn1::read_uint(&mut cursor)?.try_into()?;
+                let modification = ldap_read_partial_attribute(&mut cursor)?;
+                changes.push(Change {
+                    operation,
+                    modification,
+                });
+            }
+            Ok((
+                Message {
+                    id: message_id,
+                    params: MessageParams::Modify(MsgModify { object, changes }),
+                    controls,
+                },
+                total,
+            ))
+        }
+        0x67 => Ok((
+            Message {
+                id: message_id,
+                params: MessageParams::ModifyResponse(ldap_read_result(&mut cursor)?),
+                controls,
+            },
+            total,
+        )),
+        0x68 => {
+            // add
+            let _app_size = asn1::read_size(&mut cursor)?;
+            let entry = asn1::read_string(&mut cursor)?;
+            let attributes = ldap_read_attribute_list(&mut cursor)?;
+            Ok((
+                Message {
+                    id: message_id,
+                    params: MessageParams::Add(MsgAdd { entry, attributes }),
+                    controls,
+                },
+                total,
+            ))
+        }
+        0x69 => Ok((
+            Message {
+                id: message_id,
+                params: MessageParams::AddResponse(ldap_read_result(&mut cursor)?),
+                controls,
+            },
+            total,
+        )),
+        0x4a => {
+            // delete
+            let size = asn1::read_size(&mut cursor)?;
+            let buf = asn1::read_bytes(&mut cursor, size)?;
+            let entry = match String::from_utf8(buf) {
+                Ok(s) => s,
+                Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            };
+            Ok((
+                Message {
+                    id: message_id,
+                    params: MessageParams::Del(MsgDel { entry }),
+                    controls,
+                },
+                total,
+            ))
+        }
+        0x6b => Ok((
+            Message {
+                id: message_id,
+                params: MessageParams::DelResponse(ldap_read_result(&mut cursor)?),
+                controls,
+            },
+            total,
+        )),
         0x42 => Ok((
             Message {
                 id: message_id,
                 params: MessageParams::Unbind(MsgUnbind {}),
+                controls,
             },
-            start_seq_len + 2,
+            total,
         )),
-        r => {
-            println!("unknown req {:x}", r);
-            Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+        0x50 => {
+            // abandon, the tag is read again as the integer's
+            cursor.set_position(cursor.position() - 1);
+            let id = asn1::read_uint(&mut cursor)?;
+            Ok((
+                Message {
+                    id: message_id,
+                    params: MessageParams::Abandon(MsgAbandon { id }),
+                    controls,
+                },
+                total,
+            ))
         }
+        0x77 => Ok((
+            Message {
+                id: message_id,
+                params: MessageParams::ExtendedRequest(ldap_read_extended_request(&mut cursor)?),
+                controls,
+            },
+            total,
+        )),
+        0x78 => Ok((
+            Message {
+                id: message_id,
+                params: MessageParams::ExtendedResponse(ldap_read_extended_response(&mut cursor)?),
+                controls,
+            },
+            total,
+        )),
+        0x6c => {
+            // modify dn
+            let _app_size = asn1::read_size(&mut cursor)?;
+            let entry = asn1::read_string(&mut cursor)?;
+            let new_rdn = asn1::read_string(&mut cursor)?;
+            let delete_old_rdn = asn1::read_uint(&mut cursor)? != 0;
+            let new_superior = if cursor.position() < op_end {
+                Some(asn1::read_string(&mut cursor)?)
+            } else {
+                None
+            };
+            Ok((
+                Message {
+                    id: message_id,
+                    params: MessageParams::ModDn(MsgModDn {
+                        entry,
+                        new_rdn,
+                        delete_old_rdn,
+                        new_superior,
+                    }),
+                    controls,
+                },
+                total,
+            ))
+        }
+        0x6d => Ok((
+            Message {
+                id: message_id,
+                params: MessageParams::ModDnResponse(ldap_read_result(&mut cursor)?),
+                controls,
+            },
+            total,
+        )),
+        0x6e => {
+            // compare
+            let _app_size = asn1::read_size(&mut cursor)?;
+            let entry = asn1::read_string(&mut cursor)?;
+            let _tag = asn1::read_tag(&mut cursor)?;
+            let _size = asn1::read_size(&mut cursor)?;
+            let name = asn1::read_string(&mut cursor)?;
+            let value = asn1::read_string(&mut cursor)?;
+            Ok((
+                Message {
+                    id: message_id,
+                    params: MessageParams::Compare(MsgCompare { entry, name, value }),
+                    controls,
+                },
+                total,
+            ))
+        }
+        0x6f => Ok((
+            Message {
+                id: message_id,
+                params: MessageParams::CompareResponse(ldap_read_result(&mut cursor)?),
+                controls,
+            },
+            total,
+        )),
+        r => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("unknown operation {:x}", r),
+        )),
     }
 }
 
@@ -319,6 +1227,8 @@ fn search_test() {
         assert_eq!(s.deref, crate::ldap::DerefAliases::NeverDerefAliases);
         assert_eq!(s.size_limit, 0);
         assert_eq!(s.time_limit, 0);
+        assert!(!s.types_only);
+        assert!(s.attributes.is_empty());
         if let Filter::And(fa) = s.filter {
             assert_eq!(fa.items.len(), 2);
             if let Filter::EqualityMatch(fa1) = &fa.items[1] {
@@ -367,3 +1277,302 @@ fn bind_test() {
         hex::decode("3013020101600e0201030402787880056865736c6f".as_bytes()).unwrap()
     );
 }
+
+#[test]
+fn modify_test() {
+    let msg = MsgModify {
+        object: "cn=a,dc=example".to_owned(),
+        changes: vec![Change {
+            operation: ModifyOperation::Replace,
+            modification: PartialAttribute {
+                name: "description".to_owned(),
+                values: vec!["x".repeat(200)],
+            },
+        }],
+    };
+    let encoded = ldap_write_modify_request(7, &msg).unwrap();
+    let (m, size) = parse_message(&encoded).unwrap();
+    assert_eq!(size, encoded.len());
+    assert_eq!(m.id, 7);
+    if let MessageParams::Modify(m) = m.params {
+        assert_eq!(m.object, "cn=a,dc=example");
+        assert_eq!(m.changes.len(), 1);
+        assert_eq!(m.changes[0].operation, ModifyOperation::Replace);
+        assert_eq!(m.changes[0].modification.values[0].len(), 200);
+    } else {
+        unreachable!();
+    }
+    assert_eq!(
+        parse_message(&encoded[..encoded.len() - 1])
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+}
+
+#[test]
+fn message_length_overflow_test() {
+    let data = [
+        0x30, 0x88, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0,
+    ];
+    assert_eq!(
+        parse_message(&data).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn filter_depth_test() {
+    // (!(!(!...(a=*)))) nested past the limit is refused instead of overflowing the stack
+    let nested = |levels: usize| {
+        let mut f = vec![0x87, 0x01, 0x61];
+        for _ in 0..levels {
+            let mut outer = vec![0xa2];
+            asn1::asn1_write_len(&mut outer, f.len()).unwrap();
+            outer.extend(f);
+            f = outer;
+        }
+        f
+    };
+    assert!(ldap_read_filter(&mut Cursor::new(&nested(64))).is_ok());
+    assert_eq!(
+        ldap_read_filter(&mut Cursor::new(&nested(100)))
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn round_trip_test() {
+    // xorshift, enough to drive the round trip test without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+        fn string(&mut self) -> String {
+            const CHARS: &[char] = &['a', 'Z', '0', '=', ',', '*', '(', ')', '\\', ' ', 'é', '€'];
+            let len = self.below(12) as usize;
+            (0..len)
+                .map(|_| CHARS[self.below(CHARS.len() as u64) as usize])
+                .collect()
+        }
+        fn strings(&mut self) -> Vec<String> {
+            (0..self.below(4)).map(|_| self.string()).collect()
+        }
+        fn attrs(&mut self) -> Vec<PartialAttribute> {
+            (0..self.below(4))
+                .map(|_| PartialAttribute {
+                    name: self.string(),
+                    values: self.strings(),
+                })
+                .collect()
+        }
+        fn result(&mut self) -> MsgResult {
+            MsgResult {
+                res: self.below(90) as u32,
+                matched_dn: self.string(),
+                diag: self.string(),
+            }
+        }
+        fn filter(&mut self, depth: u32) -> Filter {
+            let ava = |r: &mut Rng| FilterAttributeValueAssertion {
+                name: r.string(),
+                value: r.string(),
+            };
+            let kinds = if depth > 2 { 7 } else { 10 };
+            match self.below(kinds) {
+                0 => Filter::EqualityMatch(ava(self)),
+                1 => Filter::Present(FilterPresent {
+                    name: self.string(),
+                }),
+                2 => Filter::Substrings(FilterSubstrings {
+                    name: self.string(),
+                    initial: (self.below(2) == 0).then(|| self.string()),
+                    any: self.strings(),
+                    final_: (self.below(2) == 0).then(|| self.string()),
+                }),
+                3 => Filter::GreaterOrEqual(ava(self)),
+                4 => Filter::LessOrEqual(ava(self)),
+                5 => Filter::ApproxMatch(ava(self)),
+                6 => Filter::ExtensibleMatch(FilterExtensible {
+                    matching_rule: (self.below(2) == 0).then(|| self.string()),
+                    name: (self.below(2) == 0).then(|| self.string()),
+                    value: self.string(),
+                    dn_attributes: self.below(2) == 0,
+                }),
+                7 => Filter::And(FilterAnd {
+                    items: (0..self.below(3)).map(|_| self.filter(depth + 1)).collect(),
+                }),
+                8 => Filter::Or(FilterOr {
+                    items: (0..self.below(3)).map(|_| self.filter(depth + 1)).collect(),
+                }),
+                _ => Filter::Not(Box::new(self.filter(depth + 1))),
+            }
+        }
+        fn message(&mut self) -> Message {
+            let params = match self.below(21) {
+                0 => MessageParams::Bind(MsgBind {
+                    version: 2 + self.below(2) as u32,
+                    name: self.string(),
+                    password: self.string(),
+                }),
+                1 => {
+                    let r = self.result();
+                    MessageParams::BindResponse(MsgBindResponse {
+                        res: r.res,
+                        matched_dn: r.matched_dn,
+                        diag: r.diag,
+                    })
+                }
+                2 => MessageParams::Search(MsgSearch {
+                    base_object: self.string(),
+                    scope: (self.below(3) as u32).try_into().unwrap(),
+                    deref: (self.below(4) as u32).try_into().unwrap(),
+                    filter: self.filter(0),
+                    size_limit: self.next() as u32,
+                    time_limit: self.below(3600) as u32,
+                    types_only: self.below(2) == 0,
+                    attributes: self.strings(),
+                }),
+                3 => MessageParams::SearchResult(MsgSearchResult {
+                    name: self.string(),
+                    values: self.attrs(),
+                }),
+                4 => {
+                    let r = self.result();
+                    MessageParams::MsgSearchResultDone(MsgSearchResultDone {
+                        res: r.res,
+                        matched_dn: r.matched_dn,
+                        diag: r.diag,
+                    })
+                }
+                5 => MessageParams::Unbind(MsgUnbind {}),
+                6 => MessageParams::Add(MsgAdd {
+                    entry: self.string(),
+                    attributes: self.attrs(),
+                }),
+                7 => MessageParams::AddResponse(self.result()),
+                8 => MessageParams::Modify(MsgModify {
+                    object: self.string(),
+                    changes: (0..self.below(4))
+                        .map(|_| Change {
+                            operation: (self.below(3) as u32).try_into().unwrap(),
+                            modification: PartialAttribute {
+                                name: self.string(),
+                                values: self.strings(),
+                            },
+                        })
+                        .collect(),
+                }),
+                9 => MessageParams::ModifyResponse(self.result()),
+                10 => MessageParams::Del(MsgDel {
+                    entry: self.string(),
+                }),
+                11 => MessageParams::DelResponse(self.result()),
+                12 => MessageParams::Abandon(MsgAbandon {
+                    id: self.next() as u32 & 0x7fffffff,
+                }),
+                13 => MessageParams::ModDn(MsgModDn {
+                    entry: self.string(),
+                    new_rdn: self.string(),
+                    delete_old_rdn: self.below(2) == 0,
+                    new_superior: (self.below(2) == 0).then(|| self.string()),
+                }),
+                14 => MessageParams::ModDnResponse(self.result()),
+                15 => MessageParams::Compare(MsgCompare {
+                    entry: self.string(),
+                    name: self.string(),
+                    value: self.string(),
+                }),
+                16 => MessageParams::CompareResponse(self.result()),
+                17 => MessageParams::ExtendedRequest(MsgExtendedRequest {
+                    name: self.string(),
+                    value: (self.below(2) == 0).then(|| self.string().into_bytes()),
+                }),
+                18 => MessageParams::SearchResultReference(MsgSearchResultReference {
+                    uris: (0..1 + self.below(3)).map(|_| self.string()).collect(),
+                }),
+                19 => MessageParams::IntermediateResponse(MsgIntermediateResponse {
+                    name: (self.below(2) == 0).then(|| self.string()),
+                    value: (self.below(2) == 0).then(|| self.string().into_bytes()),
+                }),
+                _ => MessageParams::ExtendedResponse(MsgExtendedResponse {
+                    result: self.result(),
+                    name: (self.below(2) == 0).then(|| self.string()),
+                    value: (self.below(2) == 0).then(|| self.string().into_bytes()),
+                }),
+            };
+            let controls = (0..self.below(3))
+                .map(|_| Control {
+                    oid: self.string(),
+                    critical: self.below(2) == 0,
+                    value: (self.below(2) == 0).then(|| self.string().into_bytes()),
+                })
+                .collect();
+            Message {
+                id: self.next() as u32 & 0x7fffffff,
+                params,
+                controls,
+            }
+        }
+    }
+
+    let mut rng = Rng(0x9e3779b97f4a7c15);
+    for _ in 0..5000 {
+        let m = rng.message();
+        let data = m.encode().unwrap();
+        let (decoded, size) = Message::decode(&data).unwrap();
+        assert_eq!(size, data.len());
+        assert_eq!(decoded, m);
+        assert_eq!(decoded.encode().unwrap(), data);
+    }
+
+    // an unknown filter is read as Empty and written back as one that matches nothing
+    let (m, _) = Message::decode(
+        &hex::decode("301a020102631504000a01020a01000201000201000101008f003000").unwrap(),
+    )
+    .unwrap();
+    assert!(matches!(&m.params, MessageParams::Search(s) if matches!(s.filter, Filter::Empty())));
+    let (decoded, _) = Message::decode(&m.encode().unwrap()).unwrap();
+    assert!(
+        matches!(decoded.params, MessageParams::Search(s) if matches!(&s.filter, Filter::Or(o) if o.items.is_empty()))
+    );
+}
+
+#[test]
+fn extended_values_test() {
+    let c = ldap_paged_results_control(100, b"\x00\x01").unwrap();
+    assert_eq!(c.oid, PAGED_RESULTS_CONTROL);
+    assert_eq!(
+        ldap_read_paged_results(c.value.as_deref().unwrap()).unwrap(),
+        (100, vec![0, 1])
+    );
+
+    let v = ldap_write_password_modify(Some("uid=a"), None, Some("secret")).unwrap();
+    assert_eq!(hex::encode(&v), "300f80057569643d618206736563726574");
+    assert_eq!(
+        ldap_read_password_modify(&v).unwrap(),
+        (Some("uid=a".to_owned()), None, Some("secret".to_owned()))
+    );
+    let v = ldap_write_password_modify_response("generated").unwrap();
+    assert_eq!(
+        ldap_read_password_modify_response(&v).unwrap(),
+        Some("generated".to_owned())
+    );
+
+    // a cookie claiming more bytes than the message has is rejected before allocating
+    let v = hex::decode("300e02010a0488ffffffffffffffff").unwrap();
+    assert_eq!(
+        ldap_read_paged_results(&v).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
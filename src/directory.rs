@@ -0,0 +1,879 @@
+use crate::codec;
+use crate::dn::Dn;
+use crate::filter::Evaluator;
+use crate::ldap::*;
+use crate::ldif::{self, LdifChange, LdifRecord};
+use crate::schema::Schema;
+use crate::server::{BoxFuture2, Service};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::BTreeMap;
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub dn: String,
+    pub attributes: Vec<PartialAttribute>,
+}
+
+impl Entry {
+    pub fn get(&self, name: &str) -> Option<&PartialAttribute> {
+        self.attributes
+            .iter()
+            .find(|a| a.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Persistence {
+    None,
+    // rewrite the whole directory as ldif after every change
+    Snapshot(PathBuf),
+    // append every change as an ldif change record
+    Changelog(PathBuf),
+}
+
+// entries keyed by normalized dn
+type Entries = BTreeMap<String, (Dn, Entry)>;
+
+// requests are answered in Service::call itself, so abandon and cancel always come too late.
+// persisting a change writes to disk while holding the entries lock on the runtime thread,
+// fine for the small directories this serves but not for slow disks or large snapshots.
+pub struct Directory {
+    entries: Mutex<Entries>,
+    persistence: Persistence,
+    // matching rules used by search filters
+    schema: Arc<Schema>,
+    // binds as this dn check the password here instead of an entry
+    root: Option<(Dn, String)>,
+    // upper bound for the size limit of searches, 0 for none
+    size_limit: u32,
+    // naming contexts clients may create, other entries need an existing parent
+    suffixes: Vec<Dn>,
+}
+
+// for password modify requests without a new password, none when the system rng fails
+fn generate_password() -> Option<String> {
+    const CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz23456789";
+    // bytes past the last full multiple of the alphabet are dropped so no character is
+    // more likely than another
+    let limit = 256 - 256 % CHARS.len();
+    let rng = SystemRandom::new();
+    let mut password = String::new();
+    let mut buf = [0u8; 32];
+    while password.len() < 16 {
+        rng.fill(&mut buf).ok()?;
+        for b in buf.iter().map(|b| *b as usize).filter(|b| *b < limit) {
+            if password.len() < 16 {
+                password.push(CHARS[b % CHARS.len()] as char);
+            }
+        }
+    }
+    Some(password)
+}
+
+fn result(res: u32, diag: &str) -> MsgResult {
+    MsgResult {
+        res,
+        matched_dn: String::new(),
+        diag: diag.to_owned(),
+    }
+}
+
+pub(crate) fn apply_change(
+    entry: &mut Entry,
+    change: &Change,
+) -> std::result::Result<(), MsgResult> {
+    let name = &change.modification.name;
+    let values = &change.modification.values;
+    let pos = entry
+        .attributes
+        .iter()
+        .position(|a| a.name.eq_ignore_ascii_case(name));
+    match change.operation {
+        ModifyOperation::Add => {
+            let attr = match pos {
+                Some(p) => &mut entry.attributes[p],
+                None => {
+                    entry.attributes.push(PartialAttribute {
+                        name: name.clone(),
+                        values: Vec::new(),
+                    });
+                    entry.attributes.last_mut().unwrap()
+                }
+            };
+            for v in values {
+                if attr.values.iter().any(|e| e.eq_ignore_ascii_case(v)) {
+                    return Err(result(RESULT_ATTRIBUTE_OR_VALUE_EXISTS, name));
+                }
+                attr.values.push(v.clone());
+            }
+        }
+        ModifyOperation::Delete => {
+            let p = match pos {
+                Some(p) => p,
+                None => return Err(result(RESULT_NO_SUCH_ATTRIBUTE, name)),
+            };
+            if values.is_empty() {
+                entry.attributes.remove(p);
+            } else {
+                let attr = &mut entry.attributes[p];
+                for v in values {
+                    match attr.values.iter().position(|e| e.eq_ignore_ascii_case(v)) {
+                        Some(i) => {
+                            attr.values.remove(i);
+                        }
+                        None => return Err(result(RESULT_NO_SUCH_ATTRIBUTE, name)),
+                    }
+                }
+                if attr.values.is_empty() {
+                    entry.attributes.remove(p);
+                }
+            }
+        }
+        ModifyOperation::Replace => {
+            if let Some(p) = pos {
+                entry.attributes.remove(p);
+            }
+            if !values.is_empty() {
+                entry.attributes.push(change.modification.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Directory {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(BTreeMap::new()),
+            persistence: Persistence::None,
+            schema: Arc::new(Schema::core()),
+            root: None,
+            size_limit: 0,
+            suffixes: Vec::new(),
+        }
+    }
+
+    pub fn load_ldif<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let d = Self::new();
+        d.apply_ldif(&std::fs::read_to_string(path)?)?;
+        Ok(d)
+    }
+
+    pub fn set_persistence(&mut self, persistence: Persistence) {
+        self.persistence = persistence;
+    }
+
+    pub fn set_schema(&mut self, schema: Arc<Schema>) {
+        self.schema = schema;
+    }
+
+    pub fn set_root(&mut self, dn: &str, password: &str) -> Result<()> {
+        self.root = Some((Dn::parse(dn)?, password.to_owned()));
+        Ok(())
+    }
+
+    pub fn set_size_limit(&mut self, limit: u32) {
+        self.size_limit = limit;
+    }
+
+    pub fn add_suffix(&mut self, suffix: &str) -> Result<()> {
+        self.suffixes.push(Dn::parse(suffix)?);
+        Ok(())
+    }
+
+    // applies content and change records in order, e.g. a seed file followed by a changelog.
+    // unlike add requests, records may start naming contexts that are not configured suffixes
+    pub fn apply_ldif(&self, input: &str) -> Result<()> {
+        for record in ldif::parse(input)? {
+            let r = match record.change {
+                LdifChange::Add(attributes) => self.add_entry(
+                    Entry {
+                        dn: record.dn.clone(),
+                        attributes,
+                    },
+                    true,
+                ),
+                LdifChange::Modify(changes) => self.modify(&record.dn, &changes),
+                LdifChange::Delete => self.delete(&record.dn),
+                LdifChange::ModDn {
+                    new_rdn,
+                    delete_old_rdn,
+                    new_superior,
+                } => self.moddn(
+                    &record.dn,
+                    &new_rdn,
+                    delete_old_rdn,
+                    new_superior.as_deref(),
+                ),
+            };
+            if r.res != RESULT_SUCCESS {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: error {} {}", record.dn, r.res, r.diag),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_ldif(&self) -> String {
+        let l = self.entries.lock().unwrap();
+        Self::snapshot(&l)
+    }
+
+    fn snapshot(entries: &Entries) -> String {
+        let mut sorted: Vec<&(Dn, Entry)> = entries.values().collect();
+        sorted.sort_by_key(|(dn, _)| dn.rdns.len());
+        let mut out = String::from("version: 1\n\n");
+        for (_, e) in sorted {
+            ldif::write_entry(&mut out, &e.dn, &e.attributes);
+        }
+        out
+    }
+
+    fn persist(&self, entries: &Entries, record: LdifRecord) -> Result<()> {
+        match &self.persistence {
+            Persistence::None => Ok(()),
+            Persistence::Snapshot(path) => {
+                let mut tmp = path.clone().into_os_string();
+                tmp.push(".tmp");
+                std::fs::write(&tmp, Self::snapshot(entries))?;
+                std::fs::rename(&tmp, path)
+            }
+            Persistence::Changelog(path) => {
+                let mut out = String::new();
+                ldif::write_record(&mut out, &record);
+                let mut f = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?;
+                f.write_all(out.as_bytes())
+            }
+        }
+    }
+
+    // stores the new state of one entry and persists it, the old state is restored when persisting fails
+    fn commit(
+        &self,
+        entries: &mut Entries,
+        dn: Dn,
+        new: Option<Entry>,
+        record: LdifRecord,
+    ) -> MsgResult {
+        let key = dn.normalized();
+        let old = match new {
+            Some(e) => entries.insert(key.clone(), (dn, e)),
+            None => entries.remove(&key),
+        };
+        if let Err(e) = self.persist(entries, record) {
+            println!("persisting directory failed {:?}", e);
+            match old {
+                Some(e) => entries.insert(key, e),
+                None => entries.remove(&key),
+            };
+            return result(RESULT_OTHER, "cannot persist change");
+        }
+        result(RESULT_SUCCESS, "")
+    }
+
+    // result for a missing entry with the closest existing ancestor as matched dn
+    fn no_such_object(entries: &Entries, dn: &Dn) -> MsgResult {
+        let mut r = result(RESULT_NO_SUCH_OBJECT, "");
+        let mut parent = dn.parent();
+        while let Some(p) = parent {
+            if let Some((_, e)) = entries.get(&p.normalized()) {
+                r.matched_dn = e.dn.clone();
+                break;
+            }
+            parent = p.parent();
+        }
+        r
+    }
+
+    pub fn get(&self, dn: &str) -> Option<Entry> {
+        let dn = Dn::parse(dn).ok()?;
+        let l = self.entries.lock().unwrap();
+        l.get(&dn.normalized()).map(|(_, e)| e.clone())
+    }
+
+    pub fn add(&self, entry: Entry) -> MsgResult {
+        self.add_entry(entry, false)
+    }
+
+    fn add_entry(&self, entry: Entry, any_context: bool) -> MsgResult {
+        let dn = match Dn::parse(&entry.dn) {
+            Ok(dn) if !dn.is_root() => dn,
+            _ => return result(RESULT_INVALID_DN_SYNTAX, ""),
+        };
+        let mut l = self.entries.lock().unwrap();
+        if l.contains_key(&dn.normalized()) {
+            return result(RESULT_ENTRY_ALREADY_EXISTS, "");
+        }
+        // entries without a parent start a new naming context
+        let parent = dn.parent().unwrap_or_default();
+        if !l.contains_key(&parent.normalized()) {
+            let r = Self::no_such_object(&l, &dn);
+            if !r.matched_dn.is_empty() || !(any_context || self.suffixes.contains(&dn)) {
+                return r;
+            }
+        }
+        let record = LdifRecord {
+            dn: entry.dn.clone(),
+            change: LdifChange::Add(entry.attributes.clone()),
+        };
+        self.commit(&mut l, dn, Some(entry), record)
+    }
+
+    pub fn modify(&self, dn: &str, changes: &[Change]) -> MsgResult {
+        let dn = match Dn::parse(dn) {
+            Ok(dn) => dn,
+            Err(_) => return result(RESULT_INVALID_DN_SYNTAX, ""),
+        };
+        let mut l = self.entries.lock().unwrap();
+        let mut entry = match l.get(&dn.normalized()) {
+            Some((_, e)) => e.clone(),
+            None => return Self::no_such_object(&l, &dn),
+        };
+        for change in changes {
+            if let Err(r) = apply_change(&mut entry, change) {
+                return r;
+            }
+        }
+        let record = LdifRecord {
+            dn: entry.dn.clone(),
+            change: LdifChange::Modify(changes.to_vec()),
+        };
+        self.commit(&mut l, dn, Some(entry), record)
+    }
+
+    pub fn delete(&self, dn: &str) -> MsgResult {
+        let dn = match Dn::parse(dn) {
+            Ok(dn) => dn,
+            Err(_) => return result(RESULT_INVALID_DN_SYNTAX, ""),
+        };
+        let mut l = self.entries.lock().unwrap();
+        let entry = match l.get(&dn.normalized()) {
+            Some((_, e)) => e,
+            None => return Self::no_such_object(&l, &dn),
+        };
+        if l.values().any(|(d, _)| d.is_child_of(&dn)) {
+            return result(RESULT_NOT_ALLOWED_ON_NON_LEAF, "");
+        }
+        let record = LdifRecord {
+            dn: entry.dn.clone(),
+            change: LdifChange::Delete,
+        };
+        self.commit(&mut l, dn, None, record)
+    }
+
+    // renames an entry and moves it with everything below it. the values of the new rdn are
+    // added to the entry, those of the old one are removed when delete_old_rdn is set.
+    pub fn moddn(
+        &self,
+        dn: &str,
+        new_rdn: &str,
+        delete_old_rdn: bool,
+        new_superior: Option<&str>,
+    ) -> MsgResult {
+        let (dn, rdn) = match (Dn::parse(dn), Dn::parse(new_rdn)) {
+            (Ok(dn), Ok(rdn)) if !dn.is_root() && rdn.rdns.len() == 1 => (dn, rdn.rdns[0].clone()),
+            _ => return result(RESULT_INVALID_DN_SYNTAX, ""),
+        };
+        let parent = match new_superior.map(Dn::parse) {
+            Some(Ok(p)) => p,
+            Some(Err(_)) => return result(RESULT_INVALID_DN_SYNTAX, ""),
+            None => dn.parent().unwrap_or_default(),
+        };
+        let new_dn = parent.child(rdn.clone());
+        let mut l = self.entries.lock().unwrap();
+        let mut entry = match l.get(&dn.normalized()) {
+            Some((_, e)) => e.clone(),
+            None => return Self::no_such_object(&l, &dn),
+        };
+        if new_dn.is_descendant_of(&dn) {
+            return result(
+                RESULT_UNWILLING_TO_PERFORM,
+                "cannot move an entry below itself",
+            );
+        }
+        if new_dn != dn && l.contains_key(&new_dn.normalized()) {
+            return result(RESULT_ENTRY_ALREADY_EXISTS, "");
+        }
+        if !parent.is_root() && !l.contains_key(&parent.normalized()) {
+            return Self::no_such_object(&l, &parent);
+        }
+        // values in both rdns are deleted and added back
+        let old_rdn = dn.rdn().unwrap();
+        let changes = old_rdn
+            .avas
+            .iter()
+            .filter(|_| delete_old_rdn)
+            .map(|a| (ModifyOperation::Delete, a))
+            .chain(rdn.avas.iter().map(|a| (ModifyOperation::Add, a)));
+        for (operation, ava) in changes {
+            let change = Change {
+                operation,
+                modification: PartialAttribute {
+                    name: ava.attr.clone(),
+                    values: vec![ava.value.clone()],
+                },
+            };
+            match apply_change(&mut entry, &change) {
+                Ok(()) => {}
+                Err(r) if r.res == RESULT_ATTRIBUTE_OR_VALUE_EXISTS => {}
+                Err(r) if r.res == RESULT_NO_SUCH_ATTRIBUTE => {}
+                Err(r) => return r,
+            }
+        }
+        let record = LdifRecord {
+            dn: entry.dn.clone(),
+            change: LdifChange::ModDn {
+                new_rdn: new_rdn.to_owned(),
+                delete_old_rdn,
+                new_superior: new_superior.map(|s| s.to_owned()),
+            },
+        };
+        let moved: Vec<String> = l
+            .iter()
+            .filter(|(_, (d, _))| *d == dn || d.is_descendant_of(&dn))
+            .map(|(k, _)| k.clone())
+            .collect();
+        let backup = l.clone();
+        for key in moved {
+            let (old, mut e) = l.remove(&key).unwrap();
+            let mut rdns = old.rdns[..old.rdns.len() - dn.rdns.len()].to_vec();
+            rdns.extend_from_slice(&new_dn.rdns);
+            let moved = Dn { rdns };
+            if old == dn {
+                e = entry.clone();
+            }
+            e.dn = moved.to_string();
+            l.insert(moved.normalized(), (moved, e));
+        }
+        if let Err(e) = self.persist(&l, record) {
+            println!("persisting directory failed {:?}", e);
+            *l = backup;
+            return result(RESULT_OTHER, "cannot persist change");
+        }
+        result(RESULT_SUCCESS, "")
+    }
+
+    // sets the password of an entry after checking the old one, when given. without a new
+    // password one is generated and returned.
+    pub fn modify_password(
+        &self,
+        user: &str,
+        old: Option<&str>,
+        new: Option<&str>,
+    ) -> (MsgResult, Option<String>) {
+        let dn = match Dn::parse(user) {
+            Ok(dn) if !dn.is_root() => dn,
+            _ => return (result(RESULT_INVALID_DN_SYNTAX, ""), None),
+        };
+        let generated = match new {
+            Some(_) => None,
+            None => match generate_password() {
+                Some(p) => Some(p),
+                None => return (result(RESULT_OTHER, "cannot generate password"), None),
+            },
+        };
+        let password = new.or(generated.as_deref()).unwrap_or_default().to_owned();
+        let mut l = self.entries.lock().unwrap();
+        let mut entry = match l.get(&dn.normalized()) {
+            Some((_, e)) => e.clone(),
+            None => return (Self::no_such_object(&l, &dn), None),
+        };
+        if let Some(old) = old {
+            let matches = entry
+                .get("userPassword")
+                .is_some_and(|a| a.values.iter().any(|v| v == old));
+            if !matches {
+                return (
+                    result(RESULT_INVALID_CREDENTIALS, "old password differs"),
+                    None,
+                );
+            }
+        }
+        let change = Change {
+            operation: ModifyOperation::Replace,
+            modification: PartialAttribute {
+                name: "userPassword".to_owned(),
+                values: vec![password],
+            },
+        };
+        if let Err(r) = apply_change(&mut entry, &change) {
+            return (r, None);
+        }
+        let record = LdifRecord {
+            dn: entry.dn.clone(),
+            change: LdifChange::Modify(vec![change]),
+        };
+        let r = self.commit(&mut l, dn, Some(entry), record);
+        let generated = generated.filter(|_| r.res == RESULT_SUCCESS);
+        (r, generated)
+    }
+
+    fn password_modify(&self, id: u32, value: Option<&[u8]>) -> Result<Vec<u8>> {
+        let fields = match value {
+            Some(v) => codec::ldap_read_password_modify(v),
+            None => Ok((None, None, None)),
+        };
+        let (res, value) = match fields {
+            Err(e) => (result(RESULT_PROTOCOL_ERROR, &e.to_string()), None),
+            // the server fills in the bound dn, so only anonymous requests lack a user
+            Ok((None, _, _)) => (result(RESULT_UNWILLING_TO_PERFORM, "no user given"), None),
+            Ok((Some(user), old, new)) => {
+                let (r, generated) = self.modify_password(&user, old.as_deref(), new.as_deref());
+                let value = match generated {
+                    Some(g) => Some(codec::ldap_write_password_modify_response(&g)?),
+                    None => None,
+                };
+                (r, value)
+            }
+        };
+        codec::ldap_write_extended_response(
+            id,
+            &MsgExtendedResponse {
+                result: res,
+                name: None,
+                value,
+            },
+        )
+    }
+
+    pub fn bind(&self, name: &str, password: &str) -> u32 {
+        let dn = match Dn::parse(name) {
+            Ok(dn) => dn,
+            Err(_) => return RESULT_INVALID_DN_SYNTAX,
+        };
+        if dn.is_root() {
+            return RESULT_SUCCESS;
+        }
+        if password.is_empty() {
+            return RESULT_UNWILLING_TO_PERFORM;
+        }
+        if let Some((root, root_password)) = &self.root {
+            if *root == dn {
+                return match password == root_password {
+                    true => RESULT_SUCCESS,
+                    false => RESULT_INVALID_CREDENTIALS,
+                };
+            }
+        }
+        let l = self.entries.lock().unwrap();
+        let ok = l
+            .get(&dn.normalized())
+            .and_then(|(_, e)| e.get("userPassword"))
+            .map(|a| a.values.iter().any(|v| v == password))
+            .unwrap_or(false);
+        if ok {
+            RESULT_SUCCESS
+        } else {
+            RESULT_INVALID_CREDENTIALS
+        }
+    }
+
+    pub fn search(&self, req: &MsgSearch) -> (Vec<Entry>, u32) {
+        let base = match req.base_dn() {
+            Ok(dn) => dn,
+            Err(_) => return (Vec::new(), RESULT_INVALID_DN_SYNTAX),
+        };
+        let l = self.entries.lock().unwrap();
+        if !base.is_root() && !l.contains_key(&base.normalized()) {
+            return (Vec::new(), RESULT_NO_SUCH_OBJECT);
+        }
+        let ev = Evaluator::new(&self.schema);
+        let size_limit = match (req.size_limit, self.size_limit) {
+            (0, l) | (l, 0) => l,
+            (a, b) => a.min(b),
+        };
+        let mut out = Vec::new();
+        for (dn, entry) in l.values() {
+            let in_scope = match req.scope {
+                SearchScope::BaseObject => *dn == base,
+                SearchScope::SingleLevel => dn.is_child_of(&base),
+                SearchScope::WholeSubtree => *dn == base || dn.is_descendant_of(&base),
+            };
+            if in_scope && ev.matches(&req.filter, &entry.dn, &entry.attributes) {
+                if size_limit > 0 && out.len() >= size_limit as usize {
+                    return (out, RESULT_SIZE_LIMIT_EXCEEDED);
+                }
+                out.push(entry.clone());
+            }
+        }
+        (out, RESULT_SUCCESS)
+    }
+
+    // compares with the equality rule of the attribute, like an equality filter
+    pub fn compare(&self, dn: &str, name: &str, value: &str) -> MsgResult {
+        let dn = match Dn::parse(dn) {
+            Ok(dn) => dn,
+            Err(_) => return result(RESULT_INVALID_DN_SYNTAX, ""),
+        };
+        let l = self.entries.lock().unwrap();
+        let entry = match l.get(&dn.normalized()) {
+            Some((_, e)) => e,
+            None => return Self::no_such_object(&l, &dn),
+        };
+        if entry.get(name).is_none() {
+            return result(RESULT_NO_SUCH_ATTRIBUTE, name);
+        }
+        let filter = Filter::EqualityMatch(FilterAttributeValueAssertion {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        });
+        match Evaluator::new(&self.schema).matches(&filter, &entry.dn, &entry.attributes) {
+            true => result(RESULT_COMPARE_TRUE, ""),
+            false => result(RESULT_COMPARE_FALSE, ""),
+        }
+    }
+
+    fn handle(&self, req: Message) -> Result<Vec<u8>> {
+        let id = req.id;
+        match req.params {
+            MessageParams::Bind(b) => {
+                codec::ldap_write_bind_response(id, self.bind(&b.name, &b.password))
+            }
+            MessageParams::Search(s) => {
+                let (entries, res) = self.search(&s);
+                let mut out = Vec::new();
+                for e in entries {
+                    out.append(&mut codec::ldap_write_search_res_entry(
+                        id,
+                        &e.dn,
+                        &s.select(&e.attributes, &[]),
+                    )?);
+                }
+                out.append(&mut codec::ldap_write_search_res_done(id, res)?);
+                Ok(out)
+            }
+            MessageParams::Add(a) => codec::ldap_write_add_response(
+                id,
+                &self.add(Entry {
+                    dn: a.entry,
+                    attributes: a.attributes,
+                }),
+            ),
+            MessageParams::Modify(m) => {
+                codec::ldap_write_modify_response(id, &self.modify(&m.object, &m.changes))
+            }
+            MessageParams::Del(d) => codec::ldap_write_del_response(id, &self.delete(&d.entry)),
+            MessageParams::Compare(c) => {
+                codec::ldap_write_compare_response(id, &self.compare(&c.entry, &c.name, &c.value))
+            }
+            MessageParams::ModDn(m) => codec::ldap_write_moddn_response(
+                id,
+                &self.moddn(
+                    &m.entry,
+                    &m.new_rdn,
+                    m.delete_old_rdn,
+                    m.new_superior.as_deref(),
+                ),
+            ),
+            MessageParams::ExtendedRequest(r) if r.name == PASSWORD_MODIFY_REQUEST => {
+                self.password_modify(id, r.value.as_deref())
+            }
+            MessageParams::ExtendedRequest(r) => codec::ldap_write_extended_response(
+                id,
+                &MsgExtendedResponse {
+                    result: result(
+                        RESULT_PROTOCOL_ERROR,
+                        &format!("unsupported extended operation {}", r.name),
+                    ),
+                    name: None,
+                    value: None,
+                },
+            ),
+            MessageParams::Unbind(_) => Ok(vec![]),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unknown request",
+            )),
+        }
+    }
+}
+
+impl Default for Directory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Service for Directory {
+    type Future = BoxFuture2<Result<Vec<u8>>>;
+
+    fn call(&self, req: Message) -> Self::Future {
+        let res = self.handle(req);
+        Box::pin(async move { res })
+    }
+
+    fn naming_contexts(&self) -> Vec<String> {
+        let l = self.entries.lock().unwrap();
+        l.values()
+            .filter(|(dn, _)| !l.contains_key(&dn.parent().unwrap_or_default().normalized()))
+            .map(|(_, e)| e.dn.clone())
+            .collect()
+    }
+
+    fn supported_extensions(&self) -> Vec<String> {
+        vec![PASSWORD_MODIFY_REQUEST.to_owned()]
+    }
+
+    fn entry(&self, dn: &str) -> Option<Vec<PartialAttribute>> {
+        self.get(dn).map(|e| e.attributes)
+    }
+}
+
+#[tokio::test]
+async fn directory_test() {
+    let seed = "dn: dc=example\n\
+        objectClass: domain\n\
+        dc: example\n\
+        \n\
+        dn: cn=admin,dc=example\n\
+        objectClass: person\n\
+        cn: admin\n\
+        userPassword: secret\n";
+    let path = std::env::temp_dir().join(format!("lds-directory-{}.ldif", std::process::id()));
+    let mut dir = Directory::new();
+    dir.apply_ldif(seed).unwrap();
+    dir.set_persistence(Persistence::Snapshot(path.clone()));
+
+    let person = |dn: &str| Entry {
+        dn: dn.to_owned(),
+        attributes: vec![PartialAttribute {
+            name: "objectClass".to_owned(),
+            values: vec!["person".to_owned()],
+        }],
+    };
+    // only configured suffixes may be added without a parent
+    assert_eq!(
+        dir.add(person("cn=x,dc=elsewhere")).res,
+        RESULT_NO_SUCH_OBJECT
+    );
+    assert_eq!(dir.add(person("dc=elsewhere")).res, RESULT_NO_SUCH_OBJECT);
+    dir.add_suffix("DC=Elsewhere").unwrap();
+    assert_eq!(dir.add(person("dc=elsewhere")).res, RESULT_SUCCESS);
+    assert_eq!(dir.delete("dc=elsewhere").res, RESULT_SUCCESS);
+
+    let (addr, _) = crate::server::spawn_test_server(
+        crate::server::LdapServer::new("127.0.0.1:0".to_owned()),
+        std::sync::Arc::new(dir),
+    )
+    .await;
+
+    let c = crate::client::connect(&addr).await.unwrap();
+    assert_eq!(
+        c.send_request_bind("cn=admin,dc=example", "bad")
+            .await
+            .unwrap()
+            .res,
+        49
+    );
+    assert_eq!(
+        c.send_request_bind("CN=Admin, DC=example", "secret")
+            .await
+            .unwrap()
+            .res,
+        0
+    );
+    let r = c
+        .send_request_w(Message {
+            id: 100,
+            params: MessageParams::Add(MsgAdd {
+                entry: "cn=user,dc=example".to_owned(),
+                attributes: vec![PartialAttribute {
+                    name: "cn".to_owned(),
+                    values: vec!["user".to_owned()],
+                }],
+            }),
+            controls: Vec::new(),
+        })
+        .await
+        .unwrap();
+    assert!(matches!(&r[0].params, MessageParams::AddResponse(r) if r.res == 0));
+    let r = c
+        .send_request_w(Message {
+            id: 101,
+            params: MessageParams::Search(MsgSearch {
+                base_object: "dc=example".to_owned(),
+                scope: SearchScope::SingleLevel,
+                deref: DerefAliases::NeverDerefAliases,
+                filter: Filter::Present(FilterPresent {
+                    name: "cn".to_owned(),
+                }),
+                size_limit: 0,
+                time_limit: 0,
+                types_only: false,
+                attributes: Vec::new(),
+            }),
+            controls: Vec::new(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(r.len(), 3);
+
+    let mut reloaded = Directory::load_ldif(&path).unwrap();
+    assert!(reloaded.get("cn=user,dc=example").is_some());
+    assert_eq!(
+        reloaded.delete("dc=example").res,
+        RESULT_NOT_ALLOWED_ON_NON_LEAF
+    );
+    let compare =
+        |name: &str, value: &str| reloaded.compare("cn=admin,dc=example", name, value).res;
+    assert_eq!(compare("cn", "ADMIN"), RESULT_COMPARE_TRUE);
+    assert_eq!(compare("cn", "user"), RESULT_COMPARE_FALSE);
+    assert_eq!(compare("mail", "x"), RESULT_NO_SUCH_ATTRIBUTE);
+    reloaded.set_root("cn=root", "pw").unwrap();
+    assert_eq!(reloaded.bind("CN=Root", "pw"), RESULT_SUCCESS);
+    assert_eq!(
+        reloaded.bind("cn=root", "secret"),
+        RESULT_INVALID_CREDENTIALS
+    );
+
+    let r = reloaded.moddn("cn=user,dc=example", "cn=member", true, None);
+    assert_eq!(r.res, RESULT_SUCCESS);
+    assert!(reloaded.get("cn=user,dc=example").is_none());
+    let member = reloaded.get("cn=member,dc=example").unwrap();
+    assert_eq!(member.get("cn").unwrap().values, vec!["member"]);
+    assert_eq!(
+        reloaded
+            .moddn("dc=example", "cn=x", false, Some("cn=admin,dc=example"))
+            .res,
+        RESULT_UNWILLING_TO_PERFORM
+    );
+    assert_eq!(
+        reloaded
+            .moddn("cn=member,dc=example", "cn=admin", false, None)
+            .res,
+        RESULT_ENTRY_ALREADY_EXISTS
+    );
+    // the whole subtree moves with its root
+    assert_eq!(reloaded.moddn("dc=example", "dc=other", false, None).res, 0);
+    assert_eq!(
+        reloaded.get("cn=admin,dc=other").unwrap().dn,
+        "cn=admin,dc=other"
+    );
+    let dc = reloaded.get("dc=other").unwrap();
+    assert_eq!(dc.get("dc").unwrap().values, vec!["example", "other"]);
+
+    let (r, _) = reloaded.modify_password("cn=admin,dc=other", Some("bad"), Some("new"));
+    assert_eq!(r.res, RESULT_INVALID_CREDENTIALS);
+    let (r, generated) = reloaded.modify_password("cn=admin,dc=other", Some("secret"), None);
+    assert_eq!(r.res, RESULT_SUCCESS);
+    let generated = generated.unwrap();
+    assert_eq!(generated.len(), 16);
+    assert_ne!(generate_password(), generate_password());
+    assert_eq!(
+        reloaded.bind("cn=admin,dc=other", &generated),
+        RESULT_SUCCESS
+    );
+    assert_eq!(
+        reloaded.bind("cn=admin,dc=other", "secret"),
+        RESULT_INVALID_CREDENTIALS
+    );
+    std::fs::remove_file(&path).unwrap();
+}
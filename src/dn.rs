@@ -0,0 +1,404 @@
+use crate::asn1;
+use std::io::Result;
+
+#[derive(Debug, Clone)]
+pub struct Ava {
+    pub attr: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rdn {
+    pub avas: Vec<Ava>,
+}
+
+// rdns[0] is the leaf, an empty list is the root dse
+#[derive(Debug, Clone, Default)]
+pub struct Dn {
+    pub rdns: Vec<Rdn>,
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid dn: {}", msg),
+    )
+}
+
+fn hex_val(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+pub fn escape_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '"' | '+' | ',' | ';' | '<' | '>' | '\\' | '=' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\0' => out.push_str("\\00"),
+            ' ' if i == 0 || i == last => out.push_str("\\ "),
+            '#' if i == 0 => out.push_str("\\#"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn normalize_value(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn normalize_attr(attr: &str) -> String {
+    let attr = attr.to_ascii_lowercase();
+    match attr.strip_prefix("oid.") {
+        Some(oid) => oid.to_owned(),
+        None => attr,
+    }
+}
+
+impl Ava {
+    fn normalized(&self) -> String {
+        format!(
+            "{}={}",
+            normalize_attr(&self.attr),
+            escape_value(&normalize_value(&self.value))
+        )
+    }
+}
+
+impl Rdn {
+    pub fn new(attr: &str, value: &str) -> Self {
+        Self {
+            avas: vec![Ava {
+                attr: attr.to_owned(),
+                value: value.to_owned(),
+            }],
+        }
+    }
+
+    pub fn get(&self, attr: &str) -> Option<&str> {
+        self.avas
+            .iter()
+            .find(|a| normalize_attr(&a.attr) == normalize_attr(attr))
+            .map(|a| a.value.as_str())
+    }
+
+    pub fn normalized(&self) -> String {
+        let mut avas: Vec<String> = self.avas.iter().map(|a| a.normalized()).collect();
+        avas.sort();
+        avas.join("+")
+    }
+}
+
+impl std::fmt::Display for Rdn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, ava) in self.avas.iter().enumerate() {
+            if i > 0 {
+                f.write_str("+")?;
+            }
+            write!(f, "{}={}", ava.attr, escape_value(&ava.value))?;
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for Rdn {
+    fn eq(&self, other: &Self) -> bool {
+        self.normalized() == other.normalized()
+    }
+}
+impl Eq for Rdn {}
+
+fn decode_ber_string(ber: &[u8]) -> Result<String> {
+    let mut cursor = std::io::Cursor::new(ber);
+    asn1::read_tag(&mut cursor).map_err(|_| invalid("bad hex value"))?;
+    let len = asn1::read_size(&mut cursor).map_err(|_| invalid("bad hex value"))?;
+    let content = &ber[cursor.position() as usize..];
+    if content.len() != len {
+        return Err(invalid("bad hex value"));
+    }
+    String::from_utf8(content.to_vec()).map_err(|_| invalid("bad utf8"))
+}
+
+// parses one attribute value starting at pos, returns the value and the position after it
+fn parse_value(b: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut val = Vec::new();
+    if pos < b.len() && b[pos] == b'#' {
+        // hex encoded ber value, decoded to the string it holds
+        pos += 1;
+        while pos + 1 < b.len() {
+            match (hex_val(b[pos]), hex_val(b[pos + 1])) {
+                (Some(h), Some(l)) => val.push(h << 4 | l),
+                _ => break,
+            }
+            pos += 2;
+        }
+        while pos < b.len() && b[pos] == b' ' {
+            pos += 1;
+        }
+        if pos < b.len() && !matches!(b[pos], b',' | b';' | b'+') {
+            return Err(invalid("bad hex value"));
+        }
+        return Ok((decode_ber_string(&val)?, pos));
+    }
+    let quoted = pos < b.len() && b[pos] == b'"';
+    if quoted {
+        pos += 1;
+    }
+    // length of the value without unescaped trailing spaces
+    let mut keep = 0;
+    loop {
+        if pos >= b.len() {
+            if quoted {
+                return Err(invalid("missing closing quote"));
+            }
+            break;
+        }
+        let c = b[pos];
+        if quoted && c == b'"' {
+            pos += 1;
+            while pos < b.len() && b[pos] == b' ' {
+                pos += 1;
+            }
+            keep = val.len();
+            break;
+        }
+        if !quoted && matches!(c, b',' | b';' | b'+') {
+            break;
+        }
+        if c == b'\\' {
+            let n1 = *b
+                .get(pos + 1)
+                .ok_or_else(|| invalid("trailing backslash"))?;
+            if let (Some(h), Some(l)) = (hex_val(n1), b.get(pos + 2).and_then(|c| hex_val(*c))) {
+                val.push(h << 4 | l);
+                pos += 3;
+            } else if b" \"#+,;<=>\\".contains(&n1) {
+                val.push(n1);
+                pos += 2;
+            } else {
+                return Err(invalid("bad escape"));
+            }
+            keep = val.len();
+            continue;
+        }
+        val.push(c);
+        if c != b' ' || quoted {
+            keep = val.len();
+        }
+        pos += 1;
+    }
+    val.truncate(keep);
+    match String::from_utf8(val) {
+        Ok(v) => Ok((v, pos)),
+        Err(_) => Err(invalid("bad utf8")),
+    }
+}
+
+impl Dn {
+    pub fn root() -> Self {
+        Self { rdns: Vec::new() }
+    }
+
+    pub fn parse(s: &str) -> Result<Self> {
+        let b = s.as_bytes();
+        let mut rdns = Vec::new();
+        let mut avas = Vec::new();
+        let mut pos = 0;
+        if s.trim().is_empty() {
+            return Ok(Self::root());
+        }
+        loop {
+            while pos < b.len() && b[pos] == b' ' {
+                pos += 1;
+            }
+            let start = pos;
+            while pos < b.len() && b[pos] != b'=' {
+                if !(b[pos].is_ascii_alphanumeric() || matches!(b[pos], b'-' | b'.' | b' ')) {
+                    return Err(invalid("bad attribute type"));
+                }
+                pos += 1;
+            }
+            if pos >= b.len() {
+                return Err(invalid("missing '='"));
+            }
+            let attr = s[start..pos].trim();
+            if attr.is_empty() {
+                return Err(invalid("empty attribute type"));
+            }
+            pos += 1;
+            while pos < b.len() && b[pos] == b' ' {
+                pos += 1;
+            }
+            let (value, next) = parse_value(b, pos)?;
+            pos = next;
+            avas.push(Ava {
+                attr: attr.to_owned(),
+                value,
+            });
+            if pos >= b.len() {
+                rdns.push(Rdn { avas });
+                break;
+            }
+            if b[pos] != b'+' {
+                rdns.push(Rdn {
+                    avas: std::mem::take(&mut avas),
+                });
+            }
+            pos += 1;
+            if pos >= b.len() {
+                return Err(invalid("empty rdn"));
+            }
+        }
+        Ok(Self { rdns })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.rdns.is_empty()
+    }
+
+    pub fn rdn(&self) -> Option<&Rdn> {
+        self.rdns.first()
+    }
+
+    pub fn parent(&self) -> Option<Dn> {
+        if self.rdns.is_empty() {
+            return None;
+        }
+        Some(Dn {
+            rdns: self.rdns[1..].to_vec(),
+        })
+    }
+
+    pub fn child(&self, rdn: Rdn) -> Dn {
+        let mut rdns = Vec::with_capacity(self.rdns.len() + 1);
+        rdns.push(rdn);
+        rdns.extend_from_slice(&self.rdns);
+        Dn { rdns }
+    }
+
+    pub fn is_descendant_of(&self, other: &Dn) -> bool {
+        self.rdns.len() > other.rdns.len()
+            && self.rdns[self.rdns.len() - other.rdns.len()..] == other.rdns[..]
+    }
+
+    pub fn is_ancestor_of(&self, other: &Dn) -> bool {
+        other.is_descendant_of(self)
+    }
+
+    pub fn is_child_of(&self, other: &Dn) -> bool {
+        self.rdns.len() == other.rdns.len() + 1 && self.is_descendant_of(other)
+    }
+
+    pub fn is_parent_of(&self, other: &Dn) -> bool {
+        other.is_child_of(self)
+    }
+
+    pub fn normalized(&self) -> String {
+        let rdns: Vec<String> = self.rdns.iter().map(|r| r.normalized()).collect();
+        rdns.join(",")
+    }
+}
+
+impl std::fmt::Display for Dn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, rdn) in self.rdns.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", rdn)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Dn {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Dn::parse(s)
+    }
+}
+
+impl PartialEq for Dn {
+    fn eq(&self, other: &Self) -> bool {
+        self.rdns == other.rdns
+    }
+}
+impl Eq for Dn {}
+
+impl std::hash::Hash for Dn {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.normalized().hash(state)
+    }
+}
+
+#[test]
+fn dn_test() {
+    let a = Dn::parse("CN=John\\, Smith,OU=People").unwrap();
+    let b = Dn::parse("cn=john\\2c  smith, ou = people").unwrap();
+    assert_eq!(a, b);
+    assert_eq!(a.rdns.len(), 2);
+    assert_eq!(a.rdns[0].avas[0].value, "John, Smith");
+    assert_eq!(a.to_string(), "CN=John\\, Smith,OU=People");
+    assert_eq!(b.normalized(), "cn=john\\, smith,ou=people");
+
+    let m = Dn::parse("cn=a+uid=b,dc=example,dc=com").unwrap();
+    assert_eq!(m.rdns[0].avas.len(), 2);
+    assert_eq!(m.rdns[0], Dn::parse("UID=B+cn=A").unwrap().rdns[0]);
+    assert_eq!(m.rdns[0].get("uid"), Some("b"));
+
+    let base = Dn::parse("dc=example,dc=com").unwrap();
+    assert!(m.is_child_of(&base));
+    assert!(base.is_parent_of(&m));
+    assert!(Dn::parse("dc=com").unwrap().is_ancestor_of(&m));
+    assert!(m.is_descendant_of(&Dn::root()));
+    assert!(!base.is_descendant_of(&base));
+    assert_eq!(m.parent().unwrap(), base);
+    assert_eq!(
+        base.child(Rdn::new("ou", " lead#,")).to_string(),
+        "ou=\\ lead#\\,,dc=example,dc=com"
+    );
+
+    assert_eq!(
+        Dn::parse("cn=\\c4\\8d x\\ ").unwrap().rdns[0].avas[0].value,
+        "\u{10d} x "
+    );
+    assert_eq!(
+        Dn::parse("cn=\"a,b\"").unwrap().rdns[0].avas[0].value,
+        "a,b"
+    );
+    assert!(Dn::parse("").unwrap().is_root());
+    assert!(Dn::parse("cn=a,").is_err());
+    assert!(Dn::parse("cn").is_err());
+    assert!(Dn::parse("cn=a\\").is_err());
+
+    let hex = Dn::parse("cn=#0403616263 ,dc=example").unwrap();
+    assert_eq!(hex.rdns[0].avas[0].value, "abc");
+    assert_eq!(hex.to_string(), "cn=abc,dc=example");
+    assert_eq!(hex, Dn::parse("cn=abc,dc=example").unwrap());
+    assert_eq!(
+        Dn::parse(&Dn::parse("cn=\\#a").unwrap().to_string())
+            .unwrap()
+            .rdns[0]
+            .avas[0]
+            .value,
+        "#a"
+    );
+    assert!(Dn::parse("cn=#zz").is_err());
+    assert!(Dn::parse("cn=#04036162").is_err());
+    assert!(Dn::parse("cn=#040").is_err());
+    assert!(Dn::parse("cn=#").is_err());
+}
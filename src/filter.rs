@@ -0,0 +1,761 @@
+use std::io::Result;
+
+use crate::dn::Dn;
+use crate::ldap::*;
+use crate::schema::Schema;
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("invalid filter: {}", msg),
+    )
+}
+
+fn unescape(s: &str) -> Result<String> {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'\\' {
+            let hex = s.get(i + 1..i + 3).ok_or_else(|| invalid("bad escape"))?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| invalid("bad escape"))?);
+            i += 3;
+        } else {
+            out.push(b[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| invalid("bad utf8"))
+}
+
+pub fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '*' => out.push_str("\\2a"),
+            '(' => out.push_str("\\28"),
+            ')' => out.push_str("\\29"),
+            '\\' => out.push_str("\\5c"),
+            '\0' => out.push_str("\\00"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn ava(name: &str, value: &str) -> Result<FilterAttributeValueAssertion> {
+    Ok(FilterAttributeValueAssertion {
+        name: name.to_owned(),
+        value: unescape(value)?,
+    })
+}
+
+fn parse_item(item: &str) -> Result<Filter> {
+    let eq = item.find('=').ok_or_else(|| invalid("missing '='"))?;
+    let (lhs, value) = (&item[..eq], &item[eq + 1..]);
+    if let Some(lhs) = lhs.strip_suffix(':') {
+        // extensible match, attr[:dn][:rule]:=value
+        let mut parts = lhs.split(':');
+        let name = parts.next().unwrap_or_default();
+        let mut out = FilterExtensible {
+            matching_rule: None,
+            name: (!name.is_empty()).then(|| name.to_owned()),
+            value: unescape(value)?,
+            dn_attributes: false,
+        };
+        for p in parts {
+            if p.eq_ignore_ascii_case("dn") && !out.dn_attributes && out.matching_rule.is_none() {
+                out.dn_attributes = true;
+            } else if !p.is_empty() && out.matching_rule.is_none() {
+                out.matching_rule = Some(p.to_owned());
+            } else {
+                return Err(invalid("bad extensible match"));
+            }
+        }
+        if out.name.is_none() && out.matching_rule.is_none() {
+            return Err(invalid("extensible match needs a type or a rule"));
+        }
+        return Ok(Filter::ExtensibleMatch(out));
+    }
+    if lhs.is_empty() {
+        return Err(invalid("missing attribute"));
+    }
+    if let Some(name) = lhs.strip_suffix('~') {
+        return Ok(Filter::ApproxMatch(ava(name, value)?));
+    }
+    if let Some(name) = lhs.strip_suffix('>') {
+        return Ok(Filter::GreaterOrEqual(ava(name, value)?));
+    }
+    if let Some(name) = lhs.strip_suffix('<') {
+        return Ok(Filter::LessOrEqual(ava(name, value)?));
+    }
+    if value == "*" {
+        return Ok(Filter::Present(FilterPresent {
+            name: lhs.to_owned(),
+        }));
+    }
+    if !value.contains('*') {
+        return Ok(Filter::EqualityMatch(ava(lhs, value)?));
+    }
+    let parts: Vec<&str> = value.split('*').collect();
+    let opt = |s: &str| -> Result<Option<String>> {
+        if s.is_empty() {
+            Ok(None)
+        } else {
+            unescape(s).map(Some)
+        }
+    };
+    let mut any = Vec::new();
+    for p in &parts[1..parts.len() - 1] {
+        if p.is_empty() {
+            return Err(invalid("empty substring"));
+        }
+        any.push(unescape(p)?);
+    }
+    Ok(Filter::Substrings(FilterSubstrings {
+        name: lhs.to_owned(),
+        initial: opt(parts[0])?,
+        any,
+        final_: opt(parts[parts.len() - 1])?,
+    }))
+}
+
+// parses one parenthesized filter at pos, returns the filter and the position after it
+fn parse_at(s: &str, mut pos: usize, depth: usize) -> Result<(Filter, usize)> {
+    if depth > 64 {
+        return Err(invalid("nested too deep"));
+    }
+    let b = s.as_bytes();
+    if b.get(pos) != Some(&b'(') {
+        return Err(invalid("expected '('"));
+    }
+    pos += 1;
+    let list = |mut pos: usize| -> Result<(Vec<Filter>, usize)> {
+        let mut items = Vec::new();
+        while b.get(pos) == Some(&b'(') {
+            let (f, next) = parse_at(s, pos, depth + 1)?;
+            items.push(f);
+            pos = next;
+        }
+        Ok((items, pos))
+    };
+    let (f, pos) = match b.get(pos) {
+        Some(b'&') => {
+            let (items, pos) = list(pos + 1)?;
+            (Filter::And(FilterAnd { items }), pos)
+        }
+        Some(b'|') => {
+            let (items, pos) = list(pos + 1)?;
+            (Filter::Or(FilterOr { items }), pos)
+        }
+        Some(b'!') => {
+            let (f, pos) = parse_at(s, pos + 1, depth + 1)?;
+            (Filter::Not(Box::new(f)), pos)
+        }
+        _ => {
+            let end = s[pos..].find(')').ok_or_else(|| invalid("missing ')'"))? + pos;
+            (parse_item(&s[pos..end])?, end)
+        }
+    };
+    if b.get(pos) != Some(&b')') {
+        return Err(invalid("expected ')'"));
+    }
+    Ok((f, pos + 1))
+}
+
+// RFC 4515 string form, the outer parentheses may be left out
+pub fn parse(s: &str) -> Result<Filter> {
+    let s = s.trim();
+    if !s.starts_with('(') {
+        return parse(&format!("({})", s));
+    }
+    let (f, pos) = parse_at(s, 0, 0)?;
+    if pos != s.len() {
+        return Err(invalid("trailing data"));
+    }
+    Ok(f)
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filter::Empty() => Ok(()),
+            Filter::EqualityMatch(a) => write!(f, "({}={})", a.name, escape(&a.value)),
+            Filter::Present(p) => write!(f, "({}=*)", p.name),
+            Filter::And(a) => {
+                f.write_str("(&")?;
+                for i in &a.items {
+                    write!(f, "{}", i)?;
+                }
+                f.write_str(")")
+            }
+            Filter::Or(o) => {
+                f.write_str("(|")?;
+                for i in &o.items {
+                    write!(f, "{}", i)?;
+                }
+                f.write_str(")")
+            }
+            Filter::Not(n) => write!(f, "(!{})", n),
+            Filter::Substrings(s) => {
+                write!(f, "({}=", s.name)?;
+                if let Some(i) = &s.initial {
+                    f.write_str(&escape(i))?;
+                }
+                f.write_str("*")?;
+                for a in &s.any {
+                    write!(f, "{}*", escape(a))?;
+                }
+                if let Some(e) = &s.final_ {
+                    f.write_str(&escape(e))?;
+                }
+                f.write_str(")")
+            }
+            Filter::GreaterOrEqual(a) => write!(f, "({}>={})", a.name, escape(&a.value)),
+            Filter::LessOrEqual(a) => write!(f, "({}<={})", a.name, escape(&a.value)),
+            Filter::ApproxMatch(a) => write!(f, "({}~={})", a.name, escape(&a.value)),
+            Filter::ExtensibleMatch(e) => {
+                f.write_str("(")?;
+                if let Some(n) = &e.name {
+                    f.write_str(n)?;
+                }
+                if e.dn_attributes {
+                    f.write_str(":dn")?;
+                }
+                if let Some(r) = &e.matching_rule {
+                    write!(f, ":{}", r)?;
+                }
+                write!(f, ":={})", escape(&e.value))
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse(s)
+    }
+}
+
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// nanoseconds since the unix epoch of a GeneralizedTime value, None when it is malformed
+pub fn generalized_time(v: &str) -> Option<i128> {
+    let (v, offset) = if let Some(v) = v.strip_suffix('Z') {
+        (v, 0)
+    } else if let Some(p) = v.rfind(['+', '-']) {
+        let zone = &v[p + 1..];
+        if !(zone.len() == 2 || zone.len() == 4) || !zone.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let h: i128 = zone[..2].parse().ok()?;
+        let m: i128 = zone
+            .get(2..)
+            .filter(|m| !m.is_empty())
+            .map_or(Some(0), |m| m.parse().ok())?;
+        if h > 23 || m > 59 {
+            return None;
+        }
+        let sign = if v.as_bytes()[p] == b'-' { -1 } else { 1 };
+        (&v[..p], sign * (h * 3600 + m * 60))
+    } else {
+        (v, 0)
+    };
+    let (v, fraction) = match v.find(['.', ',']) {
+        Some(p) => (&v[..p], Some(&v[p + 1..])),
+        None => (v, None),
+    };
+    if !v.bytes().all(|b| b.is_ascii_digit()) || !matches!(v.len(), 10 | 12 | 14) {
+        return None;
+    }
+    let field = |i: usize| v.get(i..i + 2).map(|f| f.parse::<i64>().unwrap_or(-1));
+    let (month, day, hour) = (field(4)?, field(6)?, field(8)?);
+    let minute = field(10).unwrap_or(0);
+    let second = field(12).unwrap_or(0);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..=23).contains(&hour)
+        || !(0..=59).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+    let year: i64 = v[..4].parse().ok()?;
+    let secs =
+        (days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second) as i128;
+    let mut nanos = secs * 1_000_000_000;
+    if let Some(fraction) = fraction {
+        if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        // the fraction applies to the last unit present
+        let unit: i128 = match v.len() {
+            10 => 3600,
+            12 => 60,
+            _ => 1,
+        };
+        let digits = &fraction[..fraction.len().min(18)];
+        let scale = 10i128.pow(digits.len() as u32);
+        nanos += digits.parse::<i128>().ok()? * unit * 1_000_000_000 / scale;
+    }
+    Some(nanos - offset * 1_000_000_000)
+}
+
+#[derive(Debug, PartialEq, PartialOrd)]
+enum Key {
+    Int(i128),
+    Str(String),
+}
+
+fn fold(v: &str) -> String {
+    v.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Equality,
+    Ordering,
+    Substrings,
+}
+
+fn kind(rule: &str) -> Kind {
+    if rule.ends_with("OrderingMatch") {
+        Kind::Ordering
+    } else if rule.ends_with("SubstringsMatch") {
+        Kind::Substrings
+    } else {
+        Kind::Equality
+    }
+}
+
+// evaluates filters against entries using the matching rules of the schema,
+// attributes the schema does not know are compared case-insensitively
+pub struct Evaluator<'a> {
+    schema: &'a Schema,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(schema: &'a Schema) -> Self {
+        Self { schema }
+    }
+
+    pub fn matches(&self, filter: &Filter, dn: &str, attrs: &[PartialAttribute]) -> bool {
+        self.evaluate(filter, dn, attrs) == Some(true)
+    }
+
+    // None is the Undefined result of RFC 4511
+    pub fn evaluate(&self, filter: &Filter, dn: &str, attrs: &[PartialAttribute]) -> Option<bool> {
+        match filter {
+            Filter::Empty() => Some(false),
+            Filter::And(a) => {
+                let mut out = Some(true);
+                for i in &a.items {
+                    match self.evaluate(i, dn, attrs) {
+                        Some(false) => return Some(false),
+                        None => out = None,
+                        _ => {}
+                    }
+                }
+                out
+            }
+            Filter::Or(o) => {
+                let mut out = Some(false);
+                for i in &o.items {
+                    match self.evaluate(i, dn, attrs) {
+                        Some(true) => return Some(true),
+                        None => out = None,
+                        _ => {}
+                    }
+                }
+                out
+            }
+            Filter::Not(n) => self.evaluate(n, dn, attrs).map(|r| !r),
+            Filter::Present(p) => Some(
+                p.name.eq_ignore_ascii_case("objectClass")
+                    || self.values(attrs, &p.name).next().is_some(),
+            ),
+            Filter::EqualityMatch(a) | Filter::ApproxMatch(a) => {
+                let rule = self.rule(&a.name, Kind::Equality)?;
+                self.any_value(&rule, attrs, &a.name, |v, k| v == k, &a.value)
+            }
+            Filter::GreaterOrEqual(a) => {
+                let rule = self.rule(&a.name, Kind::Ordering)?;
+                self.any_value(&rule, attrs, &a.name, |v, k| v >= k, &a.value)
+            }
+            Filter::LessOrEqual(a) => {
+                let rule = self.rule(&a.name, Kind::Ordering)?;
+                self.any_value(&rule, attrs, &a.name, |v, k| v <= k, &a.value)
+            }
+            Filter::Substrings(s) => {
+                let rule = self.rule(&s.name, Kind::Substrings)?;
+                let values: Vec<&String> = self.values(attrs, &s.name).collect();
+                self.substrings(&rule, &values, s)
+            }
+            Filter::ExtensibleMatch(e) => self.extensible(e, dn, attrs),
+        }
+    }
+
+    // values of the attribute and its subtypes, options in the filter must be present on the attribute
+    fn values<'b>(
+        &'b self,
+        attrs: &'b [PartialAttribute],
+        desc: &'b str,
+    ) -> impl Iterator<Item = &'b String> + 'b {
+        let mut parts = desc.split(';');
+        let name = parts.next().unwrap_or_default();
+        let options: Vec<&str> = parts.collect();
+        attrs
+            .iter()
+            .filter(move |a| {
+                let mut p = a.name.split(';');
+                let an = p.next().unwrap_or_default();
+                let ao: Vec<&str> = p.collect();
+                self.schema.is_subtype(an, name)
+                    && options
+                        .iter()
+                        .all(|o| ao.iter().any(|a| a.eq_ignore_ascii_case(o)))
+            })
+            .flat_map(|a| a.values.iter())
+    }
+
+    // name of the matching rule of the given kind for an attribute
+    fn rule(&self, attr: &str, kind: Kind) -> Option<String> {
+        let at = match self.schema.attribute_type(attr) {
+            Some(at) => at,
+            None => {
+                return Some(
+                    match kind {
+                        Kind::Equality => "caseIgnoreMatch",
+                        Kind::Ordering => "caseIgnoreOrderingMatch",
+                        Kind::Substrings => "caseIgnoreSubstringsMatch",
+                    }
+                    .to_owned(),
+                )
+            }
+        };
+        let rule = match kind {
+            Kind::Equality => self.schema.equality_rule(at.name()),
+            Kind::Ordering => self.schema.ordering_rule(at.name()),
+            Kind::Substrings => self.schema.substr_rule(at.name()),
+        };
+        rule.map(|r| r.name().to_owned())
+    }
+
+    fn oid(&self, name: &str) -> String {
+        let s = self.schema;
+        let oid = s
+            .attribute_type(name)
+            .map(|a| &a.oid)
+            .or_else(|| s.object_class(name).map(|o| &o.oid))
+            .or_else(|| s.matching_rule(name).map(|m| &m.oid));
+        match oid {
+            Some(oid) => oid.clone(),
+            None => name.to_ascii_lowercase(),
+        }
+    }
+
+    // the normalized form of a value under a matching rule, None when the rule is unknown
+    // or the value is not valid for it
+    fn key(&self, rule: &str, v: &str) -> Option<Key> {
+        let base = rule
+            .trim_end_matches("OrderingMatch")
+            .trim_end_matches("SubstringsMatch")
+            .trim_end_matches("Match");
+        let k = match base {
+            "caseIgnore" | "caseIgnoreIA5" | "caseIgnoreList" => Key::Str(fold(v).to_lowercase()),
+            "caseExact" | "caseExactIA5" => Key::Str(fold(v)),
+            "numericString" => Key::Str(v.replace(' ', "")),
+            "telephoneNumber" => Key::Str(v.replace([' ', '-'], "").to_lowercase()),
+            "integer" => Key::Int(v.trim().parse().ok()?),
+            "boolean" => match v {
+                "TRUE" | "FALSE" => Key::Str(v.to_owned()),
+                _ => return None,
+            },
+            "distinguishedName" => Key::Str(Dn::parse(v).ok()?.normalized()),
+            "uniqueMember" => match v.rsplit_once('#') {
+                Some((dn, uid)) if uid.starts_with('\'') => {
+                    Key::Str(format!("{}#{}", Dn::parse(dn).ok()?.normalized(), uid))
+                }
+                _ => Key::Str(Dn::parse(v).ok()?.normalized()),
+            },
+            "generalizedTime" => Key::Int(generalized_time(v)?),
+            "objectIdentifier" => Key::Str(self.oid(v.trim())),
+            "objectIdentifierFirstComponent" => {
+                let first = v
+                    .trim_start()
+                    .trim_start_matches('(')
+                    .split_whitespace()
+                    .next()?;
+                Key::Str(self.oid(first))
+            }
+            "octetString" | "bitString" => Key::Str(v.to_owned()),
+            _ => return None,
+        };
+        Some(k)
+    }
+
+    fn any_value<F: Fn(&Key, &Key) -> bool>(
+        &self,
+        rule: &str,
+        attrs: &[PartialAttribute],
+        name: &str,
+        cmp: F,
+        assertion: &str,
+    ) -> Option<bool> {
+        let a = self.key(rule, assertion)?;
+        let mut out = Some(false);
+        for v in self.values(attrs, name) {
+            match self.key(rule, v) {
+                Some(k) if cmp(&k, &a) => return Some(true),
+                Some(_) => {}
+                None => out = None,
+            }
+        }
+        out
+    }
+
+    fn substrings(&self, rule: &str, values: &[&String], s: &FilterSubstrings) -> Option<bool> {
+        let part = |p: &str| match self.key(rule, p) {
+            Some(Key::Str(s)) => Some(s),
+            _ => None,
+        };
+        let initial = match &s.initial {
+            Some(i) => Some(part(i)?),
+            None => None,
+        };
+        let final_ = match &s.final_ {
+            Some(f) => Some(part(f)?),
+            None => None,
+        };
+        let any: Vec<String> = s.any.iter().map(|a| part(a)).collect::<Option<_>>()?;
+        for v in values {
+            let v = match part(v) {
+                Some(v) => v,
+                None => continue,
+            };
+            let mut rest = v.as_str();
+            if let Some(i) = &initial {
+                match rest.strip_prefix(i.as_str()) {
+                    Some(r) => rest = r,
+                    None => continue,
+                }
+            }
+            let mut ok = true;
+            for a in &any {
+                match rest.find(a.as_str()) {
+                    Some(p) => rest = &rest[p + a.len()..],
+                    None => {
+                        ok = false;
+                        break;
+                    }
+                }
+            }
+            if ok && final_.as_ref().is_none_or(|f| rest.ends_with(f.as_str())) {
+                return Some(true);
+            }
+        }
+        Some(false)
+    }
+
+    fn extensible(
+        &self,
+        e: &FilterExtensible,
+        dn: &str,
+        attrs: &[PartialAttribute],
+    ) -> Option<bool> {
+        let rule = match &e.matching_rule {
+            Some(r) => match self.schema.matching_rule(r) {
+                Some(r) => Some(r),
+                // rules the schema does not define are undefined
+                None => return None,
+            },
+            None => None,
+        };
+        let rule_name = rule
+            .map(|r| r.name().to_owned())
+            .or_else(|| e.matching_rule.clone());
+        // attribute values to test, the dn values are added for dnAttributes
+        let mut candidates: Vec<PartialAttribute> = match &e.name {
+            Some(n) => attrs
+                .iter()
+                .filter(|a| {
+                    self.schema
+                        .is_subtype(a.name.split(';').next().unwrap_or_default(), n)
+                })
+                .cloned()
+                .collect(),
+            None => attrs
+                .iter()
+                .filter(|a| match (rule, self.schema.attribute_syntax(&a.name)) {
+                    (Some(r), Some(syntax)) => r.syntax == syntax,
+                    _ => true,
+                })
+                .cloned()
+                .collect(),
+        };
+        if e.dn_attributes {
+            if let Ok(dn) = Dn::parse(dn) {
+                for rdn in &dn.rdns {
+                    for ava in &rdn.avas {
+                        if e.name
+                            .as_ref()
+                            .is_none_or(|n| self.schema.is_subtype(&ava.attr, n))
+                        {
+                            candidates.push(PartialAttribute {
+                                name: ava.attr.clone(),
+                                values: vec![ava.value.clone()],
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        let mut out = Some(false);
+        for a in &candidates {
+            let rule = match &rule_name {
+                Some(r) => r.clone(),
+                None => match self.rule(&a.name, Kind::Equality) {
+                    Some(r) => r,
+                    None => {
+                        out = None;
+                        continue;
+                    }
+                },
+            };
+            let r = match kind(&rule) {
+                Kind::Equality => self.any_value(
+                    &rule,
+                    std::slice::from_ref(a),
+                    &a.name,
+                    |v, k| v == k,
+                    &e.value,
+                ),
+                // ordering rules match values less than the assertion
+                Kind::Ordering => self.any_value(
+                    &rule,
+                    std::slice::from_ref(a),
+                    &a.name,
+                    |v, k| v < k,
+                    &e.value,
+                ),
+                Kind::Substrings => {
+                    let parts: Vec<&str> = e.value.split('*').collect();
+                    let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_owned());
+                    let s = FilterSubstrings {
+                        name: a.name.clone(),
+                        initial: non_empty(parts[0]),
+                        any: parts[1..parts.len().saturating_sub(1)]
+                            .iter()
+                            .filter(|p| !p.is_empty())
+                            .map(|p| p.to_string())
+                            .collect(),
+                        final_: if parts.len() > 1 {
+                            non_empty(parts[parts.len() - 1])
+                        } else {
+                            None
+                        },
+                    };
+                    self.substrings(&rule, &a.values.iter().collect::<Vec<_>>(), &s)
+                }
+            };
+            match r {
+                Some(true) => return Some(true),
+                None => out = None,
+                _ => {}
+            }
+        }
+        out
+    }
+}
+
+#[test]
+fn filter_test() {
+    for s in [
+        "(&(objectClass=person)(|(cn=J*n)(!(sn=x\\2a))))",
+        "(cn=*a*b*)",
+        "(age>=18)",
+        "(cn:caseExactMatch:=Bob)",
+        "(ou:dn:=people)",
+        "(:dn:2.5.13.5:=x)",
+        "(mail~=j)",
+    ] {
+        assert_eq!(parse(s).unwrap().to_string(), s);
+    }
+    assert_eq!(parse("cn=a").unwrap().to_string(), "(cn=a)");
+    assert!(parse("(cn=a").is_err());
+    assert!(parse("(=a)").is_err());
+    assert!(parse("(&(cn=a))x").is_err());
+
+    // the wire form keeps every filter variant
+    let f = parse("(&(!(a=1))(b=x*y*z)(c<=3)(d~=4)(e:dn:integerMatch:=5)(|(f=*)))").unwrap();
+    let req = MsgSearch {
+        base_object: String::new(),
+        scope: SearchScope::WholeSubtree,
+        deref: DerefAliases::NeverDerefAliases,
+        filter: f.clone(),
+        size_limit: 0,
+        time_limit: 0,
+        types_only: false,
+        attributes: Vec::new(),
+    };
+    let buf = crate::codec::ldap_write_search_request(1, &req).unwrap();
+    match crate::codec::parse_message(&buf).unwrap().0.params {
+        MessageParams::Search(s) => assert_eq!(s.filter.to_string(), f.to_string()),
+        _ => unreachable!(),
+    }
+
+    let schema = Schema::core();
+    let ev = Evaluator::new(&schema);
+    let attr = |name: &str, values: &[&str]| PartialAttribute {
+        name: name.to_owned(),
+        values: values.iter().map(|v| v.to_string()).collect(),
+    };
+    let dn = "uid=jdoe,ou=People,dc=example";
+    let attrs = vec![
+        attr("objectClass", &["top", "inetOrgPerson"]),
+        attr("cn", &["John  Doe"]),
+        attr("sn", &["Doe"]),
+        attr("labeledURI", &["http://Example"]),
+        attr("telephoneNumber", &["+1 555-0100"]),
+        attr("manager", &["CN=Boss, dc=Example"]),
+        attr("createTimestamp", &["20240101120000+0100"]),
+        attr("employeeNumber", &["42"]),
+        attr("x-count", &["10"]),
+    ];
+    let m = |f: &str| ev.matches(&parse(f).unwrap(), dn, &attrs);
+    assert!(m("(cn=john doe)"));
+    assert!(m("(name=JOHN DOE)"));
+    assert!(!m("(labeledURI=http://example)"));
+    assert!(m("(labeledURI=http://Example)"));
+    assert!(m("(telephoneNumber=+15550100)"));
+    assert!(m("(telephoneNumber=*5550*)"));
+    assert!(m("(manager=cn=boss,dc=example)"));
+    assert!(m("(objectClass=2.16.840.1.113730.3.2.2)"));
+    assert!(m("(createTimestamp>=20240101110000Z)"));
+    assert!(!m("(createTimestamp>=20240101110000.5Z)"));
+    assert!(m("(createTimestamp<=202401011100Z)"));
+    assert!(m("(cn=J*n D*)"));
+    assert!(!m("(cn=*x*)"));
+    assert!(m("(x-count=10)"));
+    assert!(m("(!(sn=smith))"));
+    assert!(m("(cn:caseExactMatch:=John Doe)"));
+    assert!(!m("(cn:caseExactMatch:=john doe)"));
+    assert!(m("(ou:dn:=people)"));
+    assert!(!m("(ou=people)"));
+    assert!(m("(:dn:caseIgnoreMatch:=EXAMPLE)"));
+    assert!(m("(employeeNumber:integerOrderingMatch:=43)"));
+    // no ordering rule for employeeNumber, undefined and so also not matched when negated
+    assert!(!m("(employeeNumber>=1)"));
+    assert!(!m("(!(employeeNumber>=1))"));
+    assert!(m("(|(employeeNumber>=1)(sn=doe))"));
+    assert!(!m("(cn:unknownMatch:=x)"));
+}
@@ -1,35 +1,101 @@
-#[derive(Debug, Clone)]
+use crate::dn::Dn;
+
+pub const RESULT_SUCCESS: u32 = 0;
+pub const RESULT_OPERATIONS_ERROR: u32 = 1;
+pub const RESULT_PROTOCOL_ERROR: u32 = 2;
+pub const RESULT_SIZE_LIMIT_EXCEEDED: u32 = 4;
+pub const RESULT_COMPARE_FALSE: u32 = 5;
+pub const RESULT_COMPARE_TRUE: u32 = 6;
+pub const RESULT_NO_SUCH_ATTRIBUTE: u32 = 16;
+pub const RESULT_UNDEFINED_ATTRIBUTE_TYPE: u32 = 17;
+pub const RESULT_CONSTRAINT_VIOLATION: u32 = 19;
+pub const RESULT_ATTRIBUTE_OR_VALUE_EXISTS: u32 = 20;
+pub const RESULT_INVALID_ATTRIBUTE_SYNTAX: u32 = 21;
+pub const RESULT_NO_SUCH_OBJECT: u32 = 32;
+pub const RESULT_INVALID_DN_SYNTAX: u32 = 34;
+pub const RESULT_INVALID_CREDENTIALS: u32 = 49;
+pub const RESULT_INSUFFICIENT_ACCESS_RIGHTS: u32 = 50;
+pub const RESULT_BUSY: u32 = 51;
+pub const RESULT_UNAVAILABLE: u32 = 52;
+pub const RESULT_UNWILLING_TO_PERFORM: u32 = 53;
+pub const RESULT_NAMING_VIOLATION: u32 = 64;
+pub const RESULT_OBJECT_CLASS_VIOLATION: u32 = 65;
+pub const RESULT_NOT_ALLOWED_ON_NON_LEAF: u32 = 66;
+pub const RESULT_NOT_ALLOWED_ON_RDN: u32 = 67;
+pub const RESULT_ENTRY_ALREADY_EXISTS: u32 = 68;
+pub const RESULT_OBJECT_CLASS_MODS_PROHIBITED: u32 = 69;
+pub const RESULT_OTHER: u32 = 80;
+pub const RESULT_CANCELED: u32 = 118;
+pub const RESULT_NO_SUCH_OPERATION: u32 = 119;
+pub const RESULT_TOO_LATE: u32 = 120;
+pub const RESULT_CANNOT_CANCEL: u32 = 121;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FilterAttributeValueAssertion {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FilterPresent {
     pub name: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FilterAnd {
     pub items: Vec<Filter>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterOr {
+    pub items: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterSubstrings {
+    pub name: String,
+    pub initial: Option<String>,
+    pub any: Vec<String>,
+    pub final_: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterExtensible {
+    pub matching_rule: Option<String>,
+    pub name: Option<String>,
+    pub value: String,
+    pub dn_attributes: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Filter {
     Empty(),
     EqualityMatch(FilterAttributeValueAssertion),
     Present(FilterPresent),
     And(FilterAnd),
+    Or(FilterOr),
+    Not(Box<Filter>),
+    Substrings(FilterSubstrings),
+    GreaterOrEqual(FilterAttributeValueAssertion),
+    LessOrEqual(FilterAttributeValueAssertion),
+    ApproxMatch(FilterAttributeValueAssertion),
+    ExtensibleMatch(FilterExtensible),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MsgBind {
     pub version: u32,
     pub name: String,
     pub password: String,
 }
 
-#[derive(Debug, Clone)]
+impl MsgBind {
+    pub fn dn(&self) -> std::io::Result<Dn> {
+        Dn::parse(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct MsgBindResponse {
     pub res: u32,
     pub matched_dn: String,
@@ -82,7 +148,7 @@ impl TryFrom<u32> for DerefAliases {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MsgSearch {
     pub base_object: String,
     pub scope: SearchScope,
@@ -90,40 +156,276 @@ pub struct MsgSearch {
     pub filter: Filter,
     pub size_limit: u32,
     pub time_limit: u32,
+    pub types_only: bool,
+    pub attributes: Vec<String>,
+}
+
+impl MsgSearch {
+    pub fn base_dn(&self) -> std::io::Result<Dn> {
+        Dn::parse(&self.base_object)
+    }
+
+    // picks the attributes the request asked for, operational ones only when named or with "+"
+    pub fn select(
+        &self,
+        attrs: &[PartialAttribute],
+        operational: &[&str],
+    ) -> Vec<PartialAttribute> {
+        let all_user = self.attributes.is_empty() || self.attributes.iter().any(|a| a == "*");
+        let all_operational = self.attributes.iter().any(|a| a == "+");
+        attrs
+            .iter()
+            .filter(|attr| {
+                let is_operational = operational
+                    .iter()
+                    .any(|o| o.eq_ignore_ascii_case(&attr.name));
+                (all_user && !is_operational)
+                    || (all_operational && is_operational)
+                    || self
+                        .attributes
+                        .iter()
+                        .any(|a| a.eq_ignore_ascii_case(&attr.name))
+            })
+            .map(|attr| PartialAttribute {
+                name: attr.name.clone(),
+                values: if self.types_only {
+                    Vec::new()
+                } else {
+                    attr.values.clone()
+                },
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PartialAttribute {
     pub name: String,
     pub values: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MsgSearchResult {
     pub name: String,
     pub values: Vec<PartialAttribute>,
 }
 
-#[derive(Debug, Clone)]
+impl MsgSearchResult {
+    pub fn dn(&self) -> std::io::Result<Dn> {
+        Dn::parse(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct MsgSearchResultDone {
     pub res: u32,
+    pub matched_dn: String,
+    pub diag: String,
+}
+
+// continuation references, urls of other servers that hold part of the searched tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgSearchResultReference {
+    pub uris: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgIntermediateResponse {
+    pub name: Option<String>,
+    pub value: Option<Vec<u8>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MsgUnbind {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgResult {
+    pub res: u32,
+    pub matched_dn: String,
+    pub diag: String,
+}
+
+// unsolicited notification sent with message id 0 before the server closes a connection
+pub const NOTICE_OF_DISCONNECTION: &str = "1.3.6.1.4.1.1466.20036";
+
+// rfc 3909, the request value holds the id of the operation to cancel
+pub const CANCEL_REQUEST: &str = "1.3.6.1.1.8";
+
+// rfc 4532, the response value is the authorization identity
+pub const WHO_AM_I_REQUEST: &str = "1.3.6.1.4.1.4203.1.11.3";
+
+// rfc 3062
+pub const PASSWORD_MODIFY_REQUEST: &str = "1.3.6.1.4.1.4203.1.11.1";
+
+// rfc 2696, the value holds a page size and the cookie returned with the previous page
+pub const PAGED_RESULTS_CONTROL: &str = "1.2.840.113556.1.4.319";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Control {
+    pub oid: String,
+    pub critical: bool,
+    pub value: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgAbandon {
+    pub id: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgExtendedRequest {
+    pub name: String,
+    pub value: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgExtendedResponse {
+    pub result: MsgResult,
+    pub name: Option<String>,
+    pub value: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgAdd {
+    pub entry: String,
+    pub attributes: Vec<PartialAttribute>,
+}
+
+impl MsgAdd {
+    pub fn dn(&self) -> std::io::Result<Dn> {
+        Dn::parse(&self.entry)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModifyOperation {
+    Add,
+    Delete,
+    Replace,
+}
+impl TryFrom<u32> for ModifyOperation {
+    type Error = std::io::Error;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ModifyOperation::Add),
+            1 => Ok(ModifyOperation::Delete),
+            2 => Ok(ModifyOperation::Replace),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unknown modify operation",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub operation: ModifyOperation,
+    pub modification: PartialAttribute,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgModify {
+    pub object: String,
+    pub changes: Vec<Change>,
+}
+
+impl MsgModify {
+    pub fn dn(&self) -> std::io::Result<Dn> {
+        Dn::parse(&self.object)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgDel {
+    pub entry: String,
+}
+
+impl MsgDel {
+    pub fn dn(&self) -> std::io::Result<Dn> {
+        Dn::parse(&self.entry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgCompare {
+    pub entry: String,
+    pub name: String,
+    pub value: String,
+}
+
+impl MsgCompare {
+    pub fn dn(&self) -> std::io::Result<Dn> {
+        Dn::parse(&self.entry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgModDn {
+    pub entry: String,
+    pub new_rdn: String,
+    pub delete_old_rdn: bool,
+    pub new_superior: Option<String>,
+}
+
+impl MsgModDn {
+    pub fn dn(&self) -> std::io::Result<Dn> {
+        Dn::parse(&self.entry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MessageParams {
     Bind(MsgBind),
     BindResponse(MsgBindResponse),
     Search(MsgSearch),
     SearchResult(MsgSearchResult),
     MsgSearchResultDone(MsgSearchResultDone),
+    SearchResultReference(MsgSearchResultReference),
     Unbind(MsgUnbind),
+    Add(MsgAdd),
+    AddResponse(MsgResult),
+    Modify(MsgModify),
+    ModifyResponse(MsgResult),
+    Del(MsgDel),
+    DelResponse(MsgResult),
+    ModDn(MsgModDn),
+    ModDnResponse(MsgResult),
+    Compare(MsgCompare),
+    CompareResponse(MsgResult),
+    Abandon(MsgAbandon),
+    ExtendedRequest(MsgExtendedRequest),
+    ExtendedResponse(MsgExtendedResponse),
+    IntermediateResponse(MsgIntermediateResponse),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Message {
     pub id: u32,
     pub params: MessageParams,
+    pub controls: Vec<Control>,
+}
+
+impl Message {
+    pub fn new(id: u32, params: MessageParams) -> Self {
+        Self {
+            id,
+            params,
+            controls: Vec::new(),
+        }
+    }
+
+    pub fn control(&self, oid: &str) -> Option<&Control> {
+        self.controls.iter().find(|c| c.oid == oid)
+    }
+
+    pub fn encode(&self) -> std::io::Result<Vec<u8>> {
+        crate::codec::ldap_write_message(self)
+    }
+
+    // returns the message and the number of bytes it used, WouldBlock if data is incomplete
+    pub fn decode(data: &[u8]) -> std::io::Result<(Message, usize)> {
+        crate::codec::parse_message(data)
+    }
 }
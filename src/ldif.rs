@@ -0,0 +1,380 @@
+use std::io::Result;
+
+use crate::ldap::{Change, ModifyOperation, PartialAttribute};
+
+#[derive(Debug, Clone)]
+pub enum LdifChange {
+    Add(Vec<PartialAttribute>),
+    Modify(Vec<Change>),
+    Delete,
+    ModDn {
+        new_rdn: String,
+        delete_old_rdn: bool,
+        new_superior: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct LdifRecord {
+    pub dn: String,
+    pub change: LdifChange,
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        out.push(BASE64[(n >> 18) as usize & 0x3f] as char);
+        out.push(BASE64[(n >> 12) as usize & 0x3f] as char);
+        if chunk.len() > 1 {
+            out.push(BASE64[(n >> 6) as usize & 0x3f] as char);
+        } else {
+            out.push('=');
+        }
+        if chunk.len() > 2 {
+            out.push(BASE64[n as usize & 0x3f] as char);
+        } else {
+            out.push('=');
+        }
+    }
+    out
+}
+
+pub fn base64_decode(data: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in data.bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            b' ' | b'\t' => continue,
+            _ => return Err(invalid("invalid base64 value")),
+        };
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+// joins folded lines and drops comments, returns records as lists of lines
+fn split_records(input: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut current: Vec<String> = Vec::new();
+    let mut in_comment = false;
+    for line in input.lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if let Some(rest) = line.strip_prefix(' ') {
+            if in_comment {
+                continue;
+            }
+            if let Some(last) = current.last_mut() {
+                last.push_str(rest);
+            }
+            continue;
+        }
+        in_comment = false;
+        if line.is_empty() {
+            if !current.is_empty() {
+                records.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            in_comment = true;
+            continue;
+        }
+        current.push(line.to_owned());
+    }
+    if !current.is_empty() {
+        records.push(current);
+    }
+    records
+}
+
+fn parse_line(line: &str) -> Result<(String, String)> {
+    let colon = match line.find(':') {
+        Some(c) => c,
+        None => return Err(invalid("missing ':' in ldif line")),
+    };
+    let name = line[..colon].to_owned();
+    let rest = &line[colon + 1..];
+    if let Some(b64) = rest.strip_prefix(':') {
+        let decoded = base64_decode(b64.trim())?;
+        match String::from_utf8(decoded) {
+            Ok(value) => Ok((name, value)),
+            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        }
+    } else if rest.starts_with('<') {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "url values are not supported",
+        ))
+    } else {
+        Ok((name, rest.trim_start_matches(' ').to_owned()))
+    }
+}
+
+fn push_value(attrs: &mut Vec<PartialAttribute>, name: String, value: String) {
+    match attrs
+        .iter_mut()
+        .find(|a| a.name.eq_ignore_ascii_case(&name))
+    {
+        Some(a) => a.values.push(value),
+        None => attrs.push(PartialAttribute {
+            name,
+            values: vec![value],
+        }),
+    }
+}
+
+fn parse_modify(lines: &[(String, String)]) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    let mut iter = lines.iter();
+    while let Some((op, attr)) = iter.next() {
+        let operation = match op.to_ascii_lowercase().as_str() {
+            "add" => ModifyOperation::Add,
+            "delete" => ModifyOperation::Delete,
+            "replace" => ModifyOperation::Replace,
+            _ => return Err(invalid("unknown modify operation")),
+        };
+        let mut values = Vec::new();
+        for (name, value) in iter.by_ref() {
+            if name == "-" {
+                break;
+            }
+            if !name.eq_ignore_ascii_case(attr) {
+                return Err(invalid("attribute does not match modify operation"));
+            }
+            values.push(value.clone());
+        }
+        changes.push(Change {
+            operation,
+            modification: PartialAttribute {
+                name: attr.clone(),
+                values,
+            },
+        });
+    }
+    Ok(changes)
+}
+
+pub fn parse(input: &str) -> Result<Vec<LdifRecord>> {
+    let mut out = Vec::new();
+    for record in split_records(input) {
+        let mut lines = Vec::with_capacity(record.len());
+        for line in &record {
+            if line == "-" {
+                lines.push(("-".to_owned(), String::new()));
+            } else {
+                lines.push(parse_line(line)?);
+            }
+        }
+        if lines[0].0.eq_ignore_ascii_case("version") {
+            lines.remove(0);
+            if lines.is_empty() {
+                continue;
+            }
+        }
+        if !lines[0].0.eq_ignore_ascii_case("dn") {
+            return Err(invalid("ldif record does not start with dn"));
+        }
+        let dn = lines.remove(0).1;
+        lines.retain(|(name, _)| !name.eq_ignore_ascii_case("control"));
+        let changetype = match lines.first() {
+            Some((name, value)) if name.eq_ignore_ascii_case("changetype") => {
+                let v = value.to_ascii_lowercase();
+                lines.remove(0);
+                v
+            }
+            _ => "add".to_owned(),
+        };
+        let change = match changetype.as_str() {
+            "add" => {
+                let mut attrs = Vec::new();
+                for (name, value) in lines {
+                    push_value(&mut attrs, name, value);
+                }
+                LdifChange::Add(attrs)
+            }
+            "modify" => LdifChange::Modify(parse_modify(&lines)?),
+            "delete" => LdifChange::Delete,
+            "modrdn" | "moddn" => {
+                let get = |key: &str| {
+                    lines
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                        .map(|(_, v)| v.clone())
+                };
+                LdifChange::ModDn {
+                    new_rdn: get("newrdn").ok_or_else(|| invalid("modrdn without newrdn"))?,
+                    delete_old_rdn: match get("deleteoldrdn").as_deref() {
+                        Some("0") => false,
+                        Some("1") => true,
+                        _ => return Err(invalid("modrdn needs deleteoldrdn 0 or 1")),
+                    },
+                    new_superior: get("newsuperior"),
+                }
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("unsupported changetype {}", changetype),
+                ))
+            }
+        };
+        out.push(LdifRecord { dn, change });
+    }
+    Ok(out)
+}
+
+fn is_safe(value: &str) -> bool {
+    if value.starts_with([' ', ':', '<']) || value.ends_with(' ') {
+        return false;
+    }
+    value.bytes().all(|b| (0x20..0x7f).contains(&b))
+}
+
+fn write_line(out: &mut String, name: &str, value: &str) {
+    out.push_str(name);
+    if is_safe(value) {
+        out.push_str(": ");
+        out.push_str(value);
+    } else {
+        out.push_str(":: ");
+        out.push_str(&base64_encode(value.as_bytes()));
+    }
+    out.push('\n');
+}
+
+fn write_attributes(out: &mut String, attrs: &[PartialAttribute]) {
+    for attr in attrs {
+        for value in &attr.values {
+            write_line(out, &attr.name, value);
+        }
+    }
+}
+
+pub fn write_entry(out: &mut String, dn: &str, attrs: &[PartialAttribute]) {
+    write_line(out, "dn", dn);
+    write_attributes(out, attrs);
+    out.push('\n');
+}
+
+pub fn write_record(out: &mut String, record: &LdifRecord) {
+    write_line(out, "dn", &record.dn);
+    match &record.change {
+        LdifChange::Add(attrs) => {
+            out.push_str("changetype: add\n");
+            write_attributes(out, attrs);
+        }
+        LdifChange::Modify(changes) => {
+            out.push_str("changetype: modify\n");
+            for change in changes {
+                let op = match change.operation {
+                    ModifyOperation::Add => "add",
+                    ModifyOperation::Delete => "delete",
+                    ModifyOperation::Replace => "replace",
+                };
+                write_line(out, op, &change.modification.name);
+                for value in &change.modification.values {
+                    write_line(out, &change.modification.name, value);
+                }
+                out.push_str("-\n");
+            }
+        }
+        LdifChange::Delete => out.push_str("changetype: delete\n"),
+        LdifChange::ModDn {
+            new_rdn,
+            delete_old_rdn,
+            new_superior,
+        } => {
+            out.push_str("changetype: modrdn\n");
+            write_line(out, "newrdn", new_rdn);
+            write_line(out, "deleteoldrdn", if *delete_old_rdn { "1" } else { "0" });
+            if let Some(s) = new_superior {
+                write_line(out, "newsuperior", s);
+            }
+        }
+    }
+    out.push('\n');
+}
+
+#[test]
+fn ldif_test() {
+    let input = "version: 1\n\
+        # comment\n \
+         continued comment\n\
+        dn: cn=John Smith,ou=people,\n dc=example\n\
+        objectClass: person\n\
+        cn: John Smith\n\
+        sn:: U21pdGgg\n\
+        objectclass: top\n\
+        \n\
+        dn: cn=x,dc=example\n\
+        changetype: modify\n\
+        replace: mail\n\
+        mail: a@b\n\
+        mail: c@d\n\
+        -\n\
+        delete: description\n\
+        -\n\
+        \n\
+        dn: cn=x,dc=example\n\
+        changetype: modrdn\n\
+        newrdn: cn=y\n\
+        deleteoldrdn: 1\n";
+    let records = parse(input).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].dn, "cn=John Smith,ou=people,dc=example");
+    if let LdifChange::Add(attrs) = &records[0].change {
+        assert_eq!(attrs.len(), 3);
+        assert_eq!(attrs[0].values, vec!["person", "top"]);
+        assert_eq!(attrs[2].values, vec!["Smith "]);
+    } else {
+        unreachable!();
+    }
+    if let LdifChange::Modify(changes) = &records[1].change {
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].operation, ModifyOperation::Replace);
+        assert_eq!(changes[0].modification.values.len(), 2);
+        assert_eq!(changes[1].operation, ModifyOperation::Delete);
+        assert!(changes[1].modification.values.is_empty());
+    } else {
+        unreachable!();
+    }
+
+    assert!(matches!(
+        &records[2].change,
+        LdifChange::ModDn { new_rdn, delete_old_rdn: true, new_superior: None } if new_rdn == "cn=y"
+    ));
+
+    let mut out = String::new();
+    for r in &records {
+        write_record(&mut out, r);
+    }
+    let again = parse(&out).unwrap();
+    assert_eq!(again.len(), 3);
+    assert!(out.contains("changetype: modrdn\nnewrdn: cn=y\ndeleteoldrdn: 1\n"));
+    assert_eq!(again[0].dn, records[0].dn);
+    assert!(out.contains("sn:: U21pdGgg\n"));
+}
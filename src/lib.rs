@@ -1,7 +1,18 @@
+pub mod acl;
 pub mod asn1;
 pub mod client;
 pub mod codec;
+pub mod directory;
+pub mod dn;
+pub mod filter;
 pub mod ldap;
+pub mod ldif;
+pub mod pool;
+pub mod reconnect;
+pub mod schema;
+pub mod schemacheck;
 pub mod server;
+pub mod servers;
 pub mod tokenbucket;
 pub mod tokiou;
+pub mod url;
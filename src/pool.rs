@@ -0,0 +1,316 @@
+use crate::client::{self, ClientConnection};
+use crate::ldap::{self, Message, MessageParams, MsgBindResponse};
+use std::io::Result;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_rustls::rustls::ClientConfig;
+
+struct Idle {
+    conn: ClientConnection,
+    since: Instant,
+}
+
+// keeps up to max_size connections to one server, idle ones are reused by the next checkout
+pub struct ConnectionPool {
+    address: String,
+    min_size: usize,
+    max_size: usize,
+    // bound on every connection handed out, anonymous when unset
+    credentials: Option<(String, String)>,
+    // the webpki roots when unset
+    tls_config: Option<Arc<ClientConfig>>,
+    checkout_timeout: Duration,
+    // idle connections older than this are probed before they are handed out again
+    health_check_after: Duration,
+    health_check_timeout: Duration,
+    idle: Mutex<Vec<Idle>>,
+    permits: Arc<Semaphore>,
+}
+
+fn pool_error(kind: std::io::ErrorKind, msg: &str) -> std::io::Error {
+    std::io::Error::new(kind, msg.to_owned())
+}
+
+impl ConnectionPool {
+    pub fn new(address: String, min_size: usize, max_size: usize) -> Self {
+        let max_size = max_size.max(1);
+        Self {
+            address,
+            min_size: min_size.min(max_size),
+            max_size,
+            credentials: None,
+            tls_config: None,
+            checkout_timeout: Duration::from_secs(30),
+            health_check_after: Duration::from_secs(30),
+            health_check_timeout: Duration::from_secs(5),
+            idle: Mutex::new(Vec::new()),
+            permits: Arc::new(Semaphore::new(max_size)),
+        }
+    }
+
+    pub fn set_credentials(&mut self, name: &str, password: &str) {
+        self.credentials = Some((name.to_owned(), password.to_owned()));
+    }
+
+    pub fn set_tls_config(&mut self, config: Arc<ClientConfig>) {
+        self.tls_config = Some(config);
+    }
+
+    pub fn set_checkout_timeout(&mut self, t: Duration) {
+        self.checkout_timeout = t;
+    }
+
+    pub fn set_health_check(&mut self, after: Duration, timeout: Duration) {
+        self.health_check_after = after;
+        self.health_check_timeout = timeout;
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    pub fn in_use_count(&self) -> usize {
+        self.max_size - self.permits.available_permits()
+    }
+
+    async fn open(&self) -> Result<ClientConnection> {
+        let tls_config = self
+            .tls_config
+            .clone()
+            .unwrap_or_else(client::default_tls_config);
+        let conn = client::connect_with_tls_config(&self.address, tls_config).await?;
+        self.prepare(&conn).await?;
+        Ok(conn)
+    }
+
+    // binds with the service credentials unless the connection already is
+    async fn prepare(&self, conn: &ClientConnection) -> Result<()> {
+        let (name, password) = match &self.credentials {
+            Some(c) => c.clone(),
+            None => (String::new(), String::new()),
+        };
+        // a successful bind proved the password, the name is enough to compare
+        let bound = conn.bound_name();
+        if bound.as_ref() == Some(&name) {
+            return Ok(());
+        }
+        // a fresh anonymous connection needs no bind
+        if self.credentials.is_none() && bound.is_none() {
+            return Ok(());
+        }
+        let r = conn.send_request_bind(&name, &password).await?;
+        if r.res != ldap::RESULT_SUCCESS {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("pool bind as {} failed: {} {}", name, r.res, r.diag),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn healthy(&self, conn: &ClientConnection) -> bool {
+        let msg = Message::new(
+            conn.next_id(),
+            MessageParams::ExtendedRequest(ldap::MsgExtendedRequest {
+                name: ldap::WHO_AM_I_REQUEST.to_owned(),
+                value: None,
+            }),
+        );
+        matches!(
+            tokio::time::timeout(self.health_check_timeout, conn.send_request_w(msg)).await,
+            Ok(Ok(m)) if !m.is_empty()
+        )
+    }
+
+    fn take_idle(&self) -> Option<Idle> {
+        self.idle.lock().unwrap().pop()
+    }
+
+    // waits up to the checkout timeout for a free slot, then reuses an idle connection or opens one
+    pub async fn get(self: &Arc<Self>) -> Result<PooledConnection> {
+        let permit =
+            match tokio::time::timeout(self.checkout_timeout, self.permits.clone().acquire_owned())
+                .await
+            {
+                Ok(Ok(p)) => p,
+                Ok(Err(_)) => return Err(pool_error(std::io::ErrorKind::Other, "pool closed")),
+                Err(_) => {
+                    return Err(pool_error(
+                        std::io::ErrorKind::TimedOut,
+                        "no pooled connection available",
+                    ))
+                }
+            };
+        while let Some(idle) = self.take_idle() {
+            if idle.conn.is_closed() {
+                continue;
+            }
+            if idle.since.elapsed() >= self.health_check_after && !self.healthy(&idle.conn).await {
+                println!("pool: evicting broken connection to {}", self.address);
+                continue;
+            }
+            // the last user may have bound as someone else
+            if self.prepare(&idle.conn).await.is_err() {
+                continue;
+            }
+            return Ok(PooledConnection::new(self.clone(), idle.conn, permit));
+        }
+        let conn = self.open().await?;
+        Ok(PooledConnection::new(self.clone(), conn, permit))
+    }
+
+    // drops closed idle connections and opens new ones up to the minimum size
+    pub async fn maintain(&self) -> Result<()> {
+        self.idle.lock().unwrap().retain(|i| !i.conn.is_closed());
+        while self.idle_count() + self.in_use_count() < self.min_size {
+            let conn = self.open().await?;
+            self.give_back(conn);
+        }
+        Ok(())
+    }
+
+    // closes every idle connection, connections in use are closed when they come back
+    pub async fn close(&self) {
+        self.permits.close();
+        let idle = std::mem::take(&mut *self.idle.lock().unwrap());
+        for i in idle {
+            let _ = i.conn.close().await;
+        }
+    }
+
+    fn give_back(&self, conn: ClientConnection) {
+        if conn.is_closed() || self.permits.is_closed() {
+            return;
+        }
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_size {
+            idle.push(Idle {
+                conn,
+                since: Instant::now(),
+            });
+        }
+    }
+}
+
+// returned to its pool when dropped, broken connections are dropped instead
+pub struct PooledConnection {
+    pool: Arc<ConnectionPool>,
+    conn: Option<ClientConnection>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    fn new(
+        pool: Arc<ConnectionPool>,
+        conn: ClientConnection,
+        permit: OwnedSemaphorePermit,
+    ) -> Self {
+        Self {
+            pool,
+            conn: Some(conn),
+            _permit: permit,
+        }
+    }
+
+    // closes the connection instead of returning it, for callers that saw it misbehave
+    pub fn discard(mut self) {
+        self.conn = None;
+    }
+}
+
+impl std::ops::Deref for PooledConnection {
+    type Target = ClientConnection;
+    fn deref(&self) -> &ClientConnection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.give_back(conn);
+        }
+    }
+}
+
+// searches run on connections bound with the service credentials while user binds get their
+// own pool, so checking a password never changes the identity searches run as
+pub struct DirectoryPools {
+    pub search: Arc<ConnectionPool>,
+    pub auth: Arc<ConnectionPool>,
+}
+
+impl DirectoryPools {
+    pub fn new(search: ConnectionPool, auth: ConnectionPool) -> Self {
+        Self {
+            search: Arc::new(search),
+            auth: Arc::new(auth),
+        }
+    }
+
+    pub async fn search(&self, msg: Message) -> Result<Vec<Message>> {
+        let conn = self.search.get().await?;
+        let r = conn.send_request_w(msg).await;
+        if r.is_err() {
+            conn.discard();
+        }
+        r
+    }
+
+    // checks a user's password, the connection is rebound before its next checkout
+    pub async fn authenticate(&self, name: &str, password: &str) -> Result<MsgBindResponse> {
+        let conn = self.auth.get().await?;
+        let r = conn.send_request_bind(name, password).await;
+        if r.is_err() {
+            conn.discard();
+        }
+        r
+    }
+}
+
+#[tokio::test]
+async fn pool_test() {
+    let mut dir = crate::directory::Directory::new();
+    dir.set_root("cn=admin,dc=example", "secret").unwrap();
+    dir.apply_ldif("dn: uid=alice,dc=example\nobjectClass: account\nuserPassword: wonderland\n")
+        .unwrap();
+    let (addr, _) = crate::server::spawn_test_server(
+        crate::server::LdapServer::new("127.0.0.1:0".to_owned()),
+        Arc::new(dir),
+    )
+    .await;
+
+    let mut search = ConnectionPool::new(addr.clone(), 2, 2);
+    search.set_credentials("cn=admin,dc=example", "secret");
+    search.set_checkout_timeout(Duration::from_millis(100));
+    let search = Arc::new(search);
+    search.maintain().await.unwrap();
+    assert_eq!(search.idle_count(), 2);
+    let a = search.get().await.unwrap();
+    let b = search.get().await.unwrap();
+    assert_eq!(
+        search.get().await.err().unwrap().kind(),
+        std::io::ErrorKind::TimedOut
+    );
+    b.discard();
+    drop(a);
+    assert_eq!((search.idle_count(), search.in_use_count()), (1, 0));
+
+    let mut auth = ConnectionPool::new(addr, 0, 1);
+    auth.set_credentials("cn=admin,dc=example", "secret");
+    let pools = DirectoryPools::new(ConnectionPool::new(String::new(), 0, 1), auth);
+    let r = pools
+        .authenticate("uid=alice,dc=example", "wonderland")
+        .await
+        .unwrap();
+    assert_eq!(r.res, ldap::RESULT_SUCCESS);
+    let r = pools
+        .authenticate("uid=alice,dc=example", "wrong")
+        .await
+        .unwrap();
+    assert_eq!(r.res, ldap::RESULT_INVALID_CREDENTIALS);
+    // the connection went back bound as alice and is rebound on checkout
+    let conn = pools.auth.get().await.unwrap();
+    assert_eq!(conn.bound_name().as_deref(), Some("cn=admin,dc=example"));
+}
@@ -0,0 +1,261 @@
+use crate::client::ClientConnection;
+use crate::ldap::{self, Message, MessageParams, MsgBindResponse};
+use crate::servers::{random, ServerList, Strategy};
+use std::io::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_rustls::rustls::ClientConfig;
+
+// retried after a reconnect, everything else may already have been applied by the server
+fn idempotent(params: &MessageParams) -> bool {
+    match params {
+        MessageParams::Search(_) | MessageParams::Compare(_) => true,
+        MessageParams::ExtendedRequest(r) => r.name == ldap::WHO_AM_I_REQUEST,
+        _ => false,
+    }
+}
+
+// a client that reconnects when the server goes away, replaying the last bind on the new
+// connection. searches and compares that were cut off are sent again, other requests fail
+// with ConnectionAborted since the server may have applied them.
+pub struct ReconnectingClient {
+    servers: ServerList,
+    conn: tokio::sync::Mutex<Option<Arc<ClientConnection>>>,
+    bound: Mutex<Option<(String, String)>>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    // connection attempts per reconnect before the request fails, 0 keeps trying
+    max_attempts: u32,
+}
+
+impl ReconnectingClient {
+    // one address or several separated by whitespace, tried in order
+    pub fn new(address: String) -> Self {
+        let urls = address.split_whitespace().map(|u| u.to_owned()).collect();
+        Self::with_servers(ServerList::new(urls, Strategy::Failover))
+    }
+
+    pub fn with_servers(servers: ServerList) -> Self {
+        Self {
+            servers,
+            conn: tokio::sync::Mutex::new(None),
+            bound: Mutex::new(None),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_attempts: 5,
+        }
+    }
+
+    pub fn set_backoff(&mut self, initial: Duration, max: Duration) {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+    }
+
+    pub fn set_tls_config(&mut self, config: Arc<ClientConfig>) {
+        self.servers.set_tls_config(config);
+    }
+
+    pub fn set_max_attempts(&mut self, n: u32) {
+        self.max_attempts = n;
+    }
+
+    // the delay before attempt n, doubling each time with up to half of it taken off at random
+    fn backoff(&self, attempt: u32) -> Duration {
+        let d = self
+            .initial_backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff);
+        let jitter = d.as_millis() as u64 / 2;
+        if jitter == 0 {
+            return d;
+        }
+        d - Duration::from_millis(random() % (jitter + 1))
+    }
+
+    async fn open(&self) -> Result<ClientConnection> {
+        let conn = self.servers.connect().await?;
+        let bound = self.bound.lock().unwrap().clone();
+        if let Some((name, password)) = bound {
+            let r = conn.send_request_bind(&name, &password).await?;
+            if r.res != ldap::RESULT_SUCCESS {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!("rebind as {} failed: {} {}", name, r.res, r.diag),
+                ));
+            }
+        }
+        Ok(conn)
+    }
+
+    async fn reconnect(&self) -> Result<ClientConnection> {
+        let mut attempt = 0;
+        loop {
+            match self.open().await {
+                Ok(c) => return Ok(c),
+                // retrying will not make the credentials work or the addresses valid
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::InvalidInput
+                    ) =>
+                {
+                    return Err(e)
+                }
+                Err(e) => {
+                    attempt += 1;
+                    if self.max_attempts != 0 && attempt >= self.max_attempts {
+                        return Err(e);
+                    }
+                    let delay = self.backoff(attempt - 1);
+                    println!(
+                        "reconnect to {} failed: {}, retrying in {:?}",
+                        self.servers.urls().join(" "),
+                        e,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    // the open connection, reconnecting first if there is none or it was closed
+    pub async fn connection(&self) -> Result<Arc<ClientConnection>> {
+        let mut conn = self.conn.lock().await;
+        match &*conn {
+            Some(c) if !c.is_closed() => Ok(c.clone()),
+            _ => {
+                let c = Arc::new(self.reconnect().await?);
+                *conn = Some(c.clone());
+                Ok(c)
+            }
+        }
+    }
+
+    // forgets a connection that failed so the next request opens a new one
+    async fn drop_connection(&self, failed: &Arc<ClientConnection>) {
+        let mut conn = self.conn.lock().await;
+        if conn.as_ref().is_some_and(|c| Arc::ptr_eq(c, failed)) {
+            *conn = None;
+        }
+    }
+
+    // a successful bind is replayed on every new connection
+    pub async fn bind(&self, name: &str, password: &str) -> Result<MsgBindResponse> {
+        let mut retried = false;
+        loop {
+            let conn = self.connection().await?;
+            match conn.send_request_bind(name, password).await {
+                Ok(r) => {
+                    *self.bound.lock().unwrap() = (r.res == ldap::RESULT_SUCCESS)
+                        .then(|| (name.to_owned(), password.to_owned()));
+                    return Ok(r);
+                }
+                Err(e) if retried => return Err(e),
+                Err(_) => {
+                    self.drop_connection(&conn).await;
+                    retried = true;
+                }
+            }
+        }
+    }
+
+    // sends the request with a fresh message id, the id in msg is ignored
+    pub async fn send_request_w(&self, mut msg: Message) -> Result<Vec<Message>> {
+        let mut retried = false;
+        loop {
+            let conn = self.connection().await?;
+            msg.id = conn.next_id();
+            match conn.send_request_w(msg.clone()).await {
+                Ok(r) => return Ok(r),
+                Err(e) => {
+                    self.drop_connection(&conn).await;
+                    if retried {
+                        return Err(e);
+                    }
+                    if !idempotent(&msg.params) {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::ConnectionAborted,
+                            format!(
+                                "connection to {} lost, the request may or may not have been applied: {}",
+                                self.servers.urls().join(" "),
+                                e
+                            ),
+                        ));
+                    }
+                    retried = true;
+                }
+            }
+        }
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        match self.conn.lock().await.take() {
+            Some(c) => c.close().await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn reconnect_test() {
+    use crate::codec;
+    use tokio::io::AsyncWriteExt;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    // the nth connection answers binds and n searches, then drops on the next request
+    let server = tokio::spawn(async move {
+        let mut binds = Vec::new();
+        for searches in [0, 1, 2] {
+            let (mut s, _) = listener.accept().await.unwrap();
+            let mut dec = crate::tokiou::DecodeContext::new();
+            let mut answered = 0;
+            while let Ok(m) = dec.get_message(&mut s).await {
+                let resp = match m.params {
+                    MessageParams::Bind(b) => {
+                        binds.push(b.name);
+                        codec::ldap_write_bind_response(m.id, 0).unwrap()
+                    }
+                    MessageParams::Search(_) if answered < searches => {
+                        answered += 1;
+                        codec::ldap_write_search_res_done(m.id, 0).unwrap()
+                    }
+                    _ => break,
+                };
+                s.write_all(&resp).await.unwrap();
+            }
+        }
+        binds
+    });
+    let mut c = ReconnectingClient::new(addr);
+    c.set_backoff(Duration::from_millis(10), Duration::from_millis(50));
+    assert_eq!(c.bind("cn=app", "pw").await.unwrap().res, 0);
+    let search = crate::server::search_request(0, "", ldap::SearchScope::BaseObject, &[]);
+    // the first connection drops the search, it is sent again on the second
+    assert_eq!(c.send_request_w(search.clone()).await.unwrap().len(), 1);
+    let del = Message::new(
+        0,
+        MessageParams::Del(ldap::MsgDel {
+            entry: "cn=x".to_owned(),
+        }),
+    );
+    assert_eq!(
+        c.send_request_w(del).await.unwrap_err().kind(),
+        std::io::ErrorKind::ConnectionAborted
+    );
+    assert_eq!(c.send_request_w(search.clone()).await.unwrap().len(), 1);
+    c.close().await.unwrap();
+    assert_eq!(server.await.unwrap(), vec!["cn=app"; 3]);
+
+    // with the server gone requests fail once the attempts are used up
+    c.set_max_attempts(3);
+    assert_eq!(
+        c.send_request_w(search.clone()).await.unwrap_err().kind(),
+        std::io::ErrorKind::ConnectionRefused
+    );
+    let empty = ReconnectingClient::new(String::new());
+    assert_eq!(
+        empty.send_request_w(search).await.unwrap_err().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+}
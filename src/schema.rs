@@ -0,0 +1,1008 @@
+use std::collections::HashMap;
+use std::io::Result;
+
+use crate::ldap::PartialAttribute;
+
+pub type Extensions = Vec<(String, Vec<String>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttributeUsage {
+    UserApplications,
+    DirectoryOperation,
+    DistributedOperation,
+    DsaOperation,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeType {
+    pub oid: String,
+    pub names: Vec<String>,
+    pub desc: Option<String>,
+    pub obsolete: bool,
+    pub sup: Option<String>,
+    pub equality: Option<String>,
+    pub ordering: Option<String>,
+    pub substr: Option<String>,
+    pub syntax: Option<String>,
+    pub syntax_len: Option<u32>,
+    pub single_value: bool,
+    pub collective: bool,
+    pub no_user_modification: bool,
+    pub usage: AttributeUsage,
+    pub extensions: Extensions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectClassKind {
+    Abstract,
+    Structural,
+    Auxiliary,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectClass {
+    pub oid: String,
+    pub names: Vec<String>,
+    pub desc: Option<String>,
+    pub obsolete: bool,
+    pub sup: Vec<String>,
+    pub kind: ObjectClassKind,
+    pub must: Vec<String>,
+    pub may: Vec<String>,
+    pub extensions: Extensions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchingRule {
+    pub oid: String,
+    pub names: Vec<String>,
+    pub desc: Option<String>,
+    pub obsolete: bool,
+    pub syntax: String,
+    pub extensions: Extensions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Syntax {
+    pub oid: String,
+    pub desc: Option<String>,
+    pub extensions: Extensions,
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid schema description: {}", msg),
+    )
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Dollar,
+    Quoted(String),
+    Word(String),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut out = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => out.push(Token::Open),
+            ')' => out.push(Token::Close),
+            '$' => out.push(Token::Dollar),
+            '\'' => {
+                let mut q = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => {
+                            let hex: String = chars.by_ref().take(2).collect();
+                            match hex.to_ascii_lowercase().as_str() {
+                                "27" => q.push('\''),
+                                "5c" => q.push('\\'),
+                                _ => return Err(invalid("bad escape")),
+                            }
+                        }
+                        Some(c) => q.push(c),
+                        None => return Err(invalid("unterminated string")),
+                    }
+                }
+                out.push(Token::Quoted(q));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut w = String::from(c);
+                while let Some(n) = chars.peek() {
+                    if n.is_whitespace() || matches!(n, '(' | ')' | '$' | '\'') {
+                        break;
+                    }
+                    w.push(*n);
+                    chars.next();
+                }
+                out.push(Token::Word(w));
+            }
+        }
+    }
+    Ok(out)
+}
+
+const FLAGS: &[&str] = &[
+    "OBSOLETE",
+    "SINGLE-VALUE",
+    "COLLECTIVE",
+    "NO-USER-MODIFICATION",
+    "ABSTRACT",
+    "STRUCTURAL",
+    "AUXILIARY",
+];
+
+// a description split into its oid and keyword fields
+struct Description {
+    oid: String,
+    fields: Vec<(String, Vec<String>)>,
+}
+
+impl Description {
+    fn parse(s: &str) -> Result<Self> {
+        let mut tokens = tokenize(s)?.into_iter();
+        if tokens.next() != Some(Token::Open) {
+            return Err(invalid("missing '('"));
+        }
+        let oid = match tokens.next() {
+            Some(Token::Word(w)) => w,
+            _ => return Err(invalid("missing oid")),
+        };
+        let mut fields = Vec::new();
+        loop {
+            let kw = match tokens.next() {
+                Some(Token::Close) => break,
+                Some(Token::Word(w)) => w.to_ascii_uppercase(),
+                _ => return Err(invalid("expected keyword")),
+            };
+            if FLAGS.contains(&kw.as_str()) {
+                fields.push((kw, Vec::new()));
+                continue;
+            }
+            let values = match tokens.next() {
+                Some(Token::Quoted(q)) => vec![q],
+                Some(Token::Word(w)) => vec![w],
+                Some(Token::Open) => {
+                    let mut values = Vec::new();
+                    loop {
+                        match tokens.next() {
+                            Some(Token::Close) => break,
+                            Some(Token::Dollar) => {}
+                            Some(Token::Quoted(v)) | Some(Token::Word(v)) => values.push(v),
+                            _ => return Err(invalid("unterminated list")),
+                        }
+                    }
+                    values
+                }
+                _ => return Err(invalid("missing value")),
+            };
+            fields.push((kw, values));
+        }
+        if tokens.next().is_some() {
+            return Err(invalid("trailing data"));
+        }
+        Ok(Self { oid, fields })
+    }
+
+    fn take(&mut self, kw: &str) -> Vec<String> {
+        match self.fields.iter().position(|(k, _)| k == kw) {
+            Some(p) => self.fields.remove(p).1,
+            None => Vec::new(),
+        }
+    }
+
+    fn single(&mut self, kw: &str) -> Option<String> {
+        self.take(kw).into_iter().next()
+    }
+
+    fn flag(&mut self, kw: &str) -> bool {
+        match self.fields.iter().position(|(k, _)| k == kw) {
+            Some(p) => {
+                self.fields.remove(p);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn extensions(self) -> Extensions {
+        self.fields
+            .into_iter()
+            .filter(|(k, _)| k.starts_with("X-"))
+            .collect()
+    }
+}
+
+fn escape_qd(s: &str) -> String {
+    s.replace('\\', "\\5C").replace('\'', "\\27")
+}
+
+fn write_names(f: &mut std::fmt::Formatter<'_>, names: &[String]) -> std::fmt::Result {
+    match names.len() {
+        0 => Ok(()),
+        1 => write!(f, " NAME '{}'", escape_qd(&names[0])),
+        _ => {
+            let n: Vec<String> = names
+                .iter()
+                .map(|n| format!("'{}'", escape_qd(n)))
+                .collect();
+            write!(f, " NAME ( {} )", n.join(" "))
+        }
+    }
+}
+
+fn write_oids(f: &mut std::fmt::Formatter<'_>, kw: &str, oids: &[String]) -> std::fmt::Result {
+    match oids.len() {
+        0 => Ok(()),
+        1 => write!(f, " {} {}", kw, oids[0]),
+        _ => write!(f, " {} ( {} )", kw, oids.join(" $ ")),
+    }
+}
+
+fn write_desc(f: &mut std::fmt::Formatter<'_>, desc: &Option<String>) -> std::fmt::Result {
+    match desc {
+        Some(d) => write!(f, " DESC '{}'", escape_qd(d)),
+        None => Ok(()),
+    }
+}
+
+fn write_extensions(f: &mut std::fmt::Formatter<'_>, ext: &Extensions) -> std::fmt::Result {
+    for (k, values) in ext {
+        let v: Vec<String> = values
+            .iter()
+            .map(|v| format!("'{}'", escape_qd(v)))
+            .collect();
+        if v.len() == 1 {
+            write!(f, " {} {}", k, v[0])?;
+        } else {
+            write!(f, " {} ( {} )", k, v.join(" "))?;
+        }
+    }
+    Ok(())
+}
+
+impl AttributeType {
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut d = Description::parse(s)?;
+        let (syntax, syntax_len) = match d.single("SYNTAX") {
+            Some(s) => match s.split_once('{') {
+                Some((oid, len)) => (
+                    Some(oid.to_owned()),
+                    Some(
+                        len.trim_end_matches('}')
+                            .parse()
+                            .map_err(|_| invalid("bad syntax length"))?,
+                    ),
+                ),
+                None => (Some(s), None),
+            },
+            None => (None, None),
+        };
+        let usage = match d.single("USAGE").as_deref() {
+            None | Some("userApplications") => AttributeUsage::UserApplications,
+            Some("directoryOperation") => AttributeUsage::DirectoryOperation,
+            Some("distributedOperation") => AttributeUsage::DistributedOperation,
+            Some("dSAOperation") => AttributeUsage::DsaOperation,
+            Some(_) => return Err(invalid("unknown usage")),
+        };
+        Ok(Self {
+            oid: d.oid.clone(),
+            names: d.take("NAME"),
+            desc: d.single("DESC"),
+            obsolete: d.flag("OBSOLETE"),
+            sup: d.single("SUP"),
+            equality: d.single("EQUALITY"),
+            ordering: d.single("ORDERING"),
+            substr: d.single("SUBSTR"),
+            syntax,
+            syntax_len,
+            single_value: d.flag("SINGLE-VALUE"),
+            collective: d.flag("COLLECTIVE"),
+            no_user_modification: d.flag("NO-USER-MODIFICATION"),
+            usage,
+            extensions: d.extensions(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.names.first().unwrap_or(&self.oid)
+    }
+
+    pub fn has_name(&self, name: &str) -> bool {
+        self.oid == name || self.names.iter().any(|n| n.eq_ignore_ascii_case(name))
+    }
+
+    pub fn is_operational(&self) -> bool {
+        self.usage != AttributeUsage::UserApplications
+    }
+}
+
+impl std::fmt::Display for AttributeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "( {}", self.oid)?;
+        write_names(f, &self.names)?;
+        write_desc(f, &self.desc)?;
+        if self.obsolete {
+            f.write_str(" OBSOLETE")?;
+        }
+        for (kw, v) in [
+            ("SUP", &self.sup),
+            ("EQUALITY", &self.equality),
+            ("ORDERING", &self.ordering),
+            ("SUBSTR", &self.substr),
+        ] {
+            if let Some(v) = v {
+                write!(f, " {} {}", kw, v)?;
+            }
+        }
+        if let Some(syntax) = &self.syntax {
+            write!(f, " SYNTAX {}", syntax)?;
+            if let Some(len) = self.syntax_len {
+                write!(f, "{{{}}}", len)?;
+            }
+        }
+        if self.single_value {
+            f.write_str(" SINGLE-VALUE")?;
+        }
+        if self.collective {
+            f.write_str(" COLLECTIVE")?;
+        }
+        if self.no_user_modification {
+            f.write_str(" NO-USER-MODIFICATION")?;
+        }
+        match self.usage {
+            AttributeUsage::UserApplications => {}
+            AttributeUsage::DirectoryOperation => f.write_str(" USAGE directoryOperation")?,
+            AttributeUsage::DistributedOperation => f.write_str(" USAGE distributedOperation")?,
+            AttributeUsage::DsaOperation => f.write_str(" USAGE dSAOperation")?,
+        }
+        write_extensions(f, &self.extensions)?;
+        f.write_str(" )")
+    }
+}
+
+impl ObjectClass {
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut d = Description::parse(s)?;
+        let kind = if d.flag("ABSTRACT") {
+            ObjectClassKind::Abstract
+        } else if d.flag("AUXILIARY") {
+            ObjectClassKind::Auxiliary
+        } else {
+            d.flag("STRUCTURAL");
+            ObjectClassKind::Structural
+        };
+        Ok(Self {
+            oid: d.oid.clone(),
+            names: d.take("NAME"),
+            desc: d.single("DESC"),
+            obsolete: d.flag("OBSOLETE"),
+            sup: d.take("SUP"),
+            kind,
+            must: d.take("MUST"),
+            may: d.take("MAY"),
+            extensions: d.extensions(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.names.first().unwrap_or(&self.oid)
+    }
+
+    pub fn has_name(&self, name: &str) -> bool {
+        self.oid == name || self.names.iter().any(|n| n.eq_ignore_ascii_case(name))
+    }
+}
+
+impl std::fmt::Display for ObjectClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "( {}", self.oid)?;
+        write_names(f, &self.names)?;
+        write_desc(f, &self.desc)?;
+        if self.obsolete {
+            f.write_str(" OBSOLETE")?;
+        }
+        write_oids(f, "SUP", &self.sup)?;
+        match self.kind {
+            ObjectClassKind::Abstract => f.write_str(" ABSTRACT")?,
+            ObjectClassKind::Structural => f.write_str(" STRUCTURAL")?,
+            ObjectClassKind::Auxiliary => f.write_str(" AUXILIARY")?,
+        }
+        write_oids(f, "MUST", &self.must)?;
+        write_oids(f, "MAY", &self.may)?;
+        write_extensions(f, &self.extensions)?;
+        f.write_str(" )")
+    }
+}
+
+impl MatchingRule {
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut d = Description::parse(s)?;
+        Ok(Self {
+            oid: d.oid.clone(),
+            names: d.take("NAME"),
+            desc: d.single("DESC"),
+            obsolete: d.flag("OBSOLETE"),
+            syntax: d
+                .single("SYNTAX")
+                .ok_or_else(|| invalid("missing syntax"))?,
+            extensions: d.extensions(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.names.first().unwrap_or(&self.oid)
+    }
+}
+
+impl std::fmt::Display for MatchingRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "( {}", self.oid)?;
+        write_names(f, &self.names)?;
+        write_desc(f, &self.desc)?;
+        if self.obsolete {
+            f.write_str(" OBSOLETE")?;
+        }
+        write!(f, " SYNTAX {}", self.syntax)?;
+        write_extensions(f, &self.extensions)?;
+        f.write_str(" )")
+    }
+}
+
+impl Syntax {
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut d = Description::parse(s)?;
+        Ok(Self {
+            oid: d.oid.clone(),
+            desc: d.single("DESC"),
+            extensions: d.extensions(),
+        })
+    }
+}
+
+impl std::fmt::Display for Syntax {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "( {}", self.oid)?;
+        write_desc(f, &self.desc)?;
+        write_extensions(f, &self.extensions)?;
+        f.write_str(" )")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    attribute_types: Vec<AttributeType>,
+    object_classes: Vec<ObjectClass>,
+    matching_rules: Vec<MatchingRule>,
+    syntaxes: Vec<Syntax>,
+    // lowercase names and oids to positions in the lists above
+    attribute_index: HashMap<String, usize>,
+    object_class_index: HashMap<String, usize>,
+    matching_rule_index: HashMap<String, usize>,
+    syntax_index: HashMap<String, usize>,
+}
+
+fn index(map: &mut HashMap<String, usize>, oid: &str, names: &[String], pos: usize) {
+    map.insert(oid.to_ascii_lowercase(), pos);
+    for n in names {
+        map.insert(n.to_ascii_lowercase(), pos);
+    }
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // core, cosine and inetOrgPerson schema
+    pub fn core() -> Self {
+        let mut s = Self::new();
+        for d in CORE_SYNTAXES {
+            s.add_syntax(Syntax::parse(d).unwrap());
+        }
+        for d in CORE_MATCHING_RULES {
+            s.add_matching_rule(MatchingRule::parse(d).unwrap());
+        }
+        for d in CORE_ATTRIBUTE_TYPES {
+            s.add_attribute_type(AttributeType::parse(d).unwrap());
+        }
+        for d in CORE_OBJECT_CLASSES {
+            s.add_object_class(ObjectClass::parse(d).unwrap());
+        }
+        s
+    }
+
+    // builds a schema from the attributes of a subschema subentry
+    pub fn from_subschema(attrs: &[PartialAttribute]) -> Result<Self> {
+        let mut s = Self::new();
+        let values = |name: &'static str| {
+            attrs
+                .iter()
+                .filter(move |a| a.name.eq_ignore_ascii_case(name))
+                .flat_map(|a| a.values.iter())
+        };
+        for d in values("ldapSyntaxes") {
+            s.add_syntax(Syntax::parse(d)?);
+        }
+        for d in values("matchingRules") {
+            s.add_matching_rule(MatchingRule::parse(d)?);
+        }
+        for d in values("attributeTypes") {
+            s.add_attribute_type(AttributeType::parse(d)?);
+        }
+        for d in values("objectClasses") {
+            s.add_object_class(ObjectClass::parse(d)?);
+        }
+        Ok(s)
+    }
+
+    pub fn to_subschema_attributes(&self) -> Vec<PartialAttribute> {
+        vec![
+            PartialAttribute {
+                name: "ldapSyntaxes".to_owned(),
+                values: self.syntaxes.iter().map(|d| d.to_string()).collect(),
+            },
+            PartialAttribute {
+                name: "matchingRules".to_owned(),
+                values: self.matching_rules.iter().map(|d| d.to_string()).collect(),
+            },
+            PartialAttribute {
+                name: "attributeTypes".to_owned(),
+                values: self.attribute_types.iter().map(|d| d.to_string()).collect(),
+            },
+            PartialAttribute {
+                name: "objectClasses".to_owned(),
+                values: self.object_classes.iter().map(|d| d.to_string()).collect(),
+            },
+        ]
+    }
+
+    pub fn add_attribute_type(&mut self, at: AttributeType) {
+        let pos = self.attribute_types.len();
+        index(&mut self.attribute_index, &at.oid, &at.names, pos);
+        self.attribute_types.push(at);
+    }
+
+    pub fn add_object_class(&mut self, oc: ObjectClass) {
+        let pos = self.object_classes.len();
+        index(&mut self.object_class_index, &oc.oid, &oc.names, pos);
+        self.object_classes.push(oc);
+    }
+
+    pub fn add_matching_rule(&mut self, mr: MatchingRule) {
+        let pos = self.matching_rules.len();
+        index(&mut self.matching_rule_index, &mr.oid, &mr.names, pos);
+        self.matching_rules.push(mr);
+    }
+
+    pub fn add_syntax(&mut self, syntax: Syntax) {
+        let pos = self.syntaxes.len();
+        index(&mut self.syntax_index, &syntax.oid, &[], pos);
+        self.syntaxes.push(syntax);
+    }
+
+    pub fn attribute_types(&self) -> &[AttributeType] {
+        &self.attribute_types
+    }
+
+    pub fn object_classes(&self) -> &[ObjectClass] {
+        &self.object_classes
+    }
+
+    // attribute descriptions may carry options, "cn;lang-en" resolves to cn
+    pub fn attribute_type(&self, name: &str) -> Option<&AttributeType> {
+        let name = name.split(';').next().unwrap_or_default();
+        self.attribute_index
+            .get(&name.to_ascii_lowercase())
+            .map(|p| &self.attribute_types[*p])
+    }
+
+    pub fn object_class(&self, name: &str) -> Option<&ObjectClass> {
+        self.object_class_index
+            .get(&name.to_ascii_lowercase())
+            .map(|p| &self.object_classes[*p])
+    }
+
+    pub fn matching_rule(&self, name: &str) -> Option<&MatchingRule> {
+        self.matching_rule_index
+            .get(&name.to_ascii_lowercase())
+            .map(|p| &self.matching_rules[*p])
+    }
+
+    pub fn syntax(&self, oid: &str) -> Option<&Syntax> {
+        self.syntax_index
+            .get(&oid.to_ascii_lowercase())
+            .map(|p| &self.syntaxes[*p])
+    }
+
+    // first name of the attribute type, so aliases like commonName map to cn
+    pub fn canonical_name<'a>(&'a self, name: &'a str) -> &'a str {
+        match self.attribute_type(name) {
+            Some(at) => at.name(),
+            None => name,
+        }
+    }
+
+    // the attribute type followed by its superiors
+    pub fn attribute_type_chain(&self, name: &str) -> Vec<&AttributeType> {
+        let mut out: Vec<&AttributeType> = Vec::new();
+        let mut next = self.attribute_type(name);
+        while let Some(at) = next {
+            if out.iter().any(|o| o.oid == at.oid) {
+                break;
+            }
+            out.push(at);
+            next = at.sup.as_ref().and_then(|s| self.attribute_type(s));
+        }
+        out
+    }
+
+    pub fn equality_rule(&self, name: &str) -> Option<&MatchingRule> {
+        self.attribute_type_chain(name)
+            .iter()
+            .find_map(|at| at.equality.as_ref())
+            .and_then(|r| self.matching_rule(r))
+    }
+
+    pub fn ordering_rule(&self, name: &str) -> Option<&MatchingRule> {
+        self.attribute_type_chain(name)
+            .iter()
+            .find_map(|at| at.ordering.as_ref())
+            .and_then(|r| self.matching_rule(r))
+    }
+
+    pub fn substr_rule(&self, name: &str) -> Option<&MatchingRule> {
+        self.attribute_type_chain(name)
+            .iter()
+            .find_map(|at| at.substr.as_ref())
+            .and_then(|r| self.matching_rule(r))
+    }
+
+    pub fn attribute_syntax(&self, name: &str) -> Option<&str> {
+        self.attribute_type_chain(name)
+            .iter()
+            .find_map(|at| at.syntax.as_deref())
+    }
+
+    // true when name is the attribute type sup or one of its subtypes
+    pub fn is_subtype(&self, name: &str, sup: &str) -> bool {
+        match self.attribute_type(sup) {
+            Some(s) => self
+                .attribute_type_chain(name)
+                .iter()
+                .any(|at| at.oid == s.oid),
+            None => name.eq_ignore_ascii_case(sup),
+        }
+    }
+
+    // the object class followed by all its superiors
+    pub fn object_class_chain(&self, name: &str) -> Vec<&ObjectClass> {
+        let mut out: Vec<&ObjectClass> = Vec::new();
+        let mut pending: Vec<&ObjectClass> = self.object_class(name).into_iter().collect();
+        while let Some(oc) = pending.pop() {
+            if out.iter().any(|o| o.oid == oc.oid) {
+                continue;
+            }
+            out.push(oc);
+            for s in oc.sup.iter().rev() {
+                if let Some(s) = self.object_class(s) {
+                    pending.push(s);
+                }
+            }
+        }
+        out
+    }
+
+    pub fn must(&self, object_class: &str) -> Vec<&AttributeType> {
+        self.collect_attributes(object_class, |oc| &oc.must)
+    }
+
+    pub fn may(&self, object_class: &str) -> Vec<&AttributeType> {
+        self.collect_attributes(object_class, |oc| &oc.may)
+    }
+
+    fn collect_attributes<F: Fn(&ObjectClass) -> &Vec<String>>(
+        &self,
+        object_class: &str,
+        list: F,
+    ) -> Vec<&AttributeType> {
+        let mut out: Vec<&AttributeType> = Vec::new();
+        for oc in self.object_class_chain(object_class) {
+            for name in list(oc) {
+                if let Some(at) = self.attribute_type(name) {
+                    if !out.iter().any(|o| o.oid == at.oid) {
+                        out.push(at);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+pub const SYNTAX_BINARY: &str = "1.3.6.1.4.1.1466.115.121.1.5";
+pub const SYNTAX_BIT_STRING: &str = "1.3.6.1.4.1.1466.115.121.1.6";
+pub const SYNTAX_BOOLEAN: &str = "1.3.6.1.4.1.1466.115.121.1.7";
+pub const SYNTAX_COUNTRY_STRING: &str = "1.3.6.1.4.1.1466.115.121.1.11";
+pub const SYNTAX_DN: &str = "1.3.6.1.4.1.1466.115.121.1.12";
+pub const SYNTAX_DIRECTORY_STRING: &str = "1.3.6.1.4.1.1466.115.121.1.15";
+pub const SYNTAX_GENERALIZED_TIME: &str = "1.3.6.1.4.1.1466.115.121.1.24";
+pub const SYNTAX_IA5_STRING: &str = "1.3.6.1.4.1.1466.115.121.1.26";
+pub const SYNTAX_INTEGER: &str = "1.3.6.1.4.1.1466.115.121.1.27";
+pub const SYNTAX_NAME_AND_OPTIONAL_UID: &str = "1.3.6.1.4.1.1466.115.121.1.34";
+pub const SYNTAX_NUMERIC_STRING: &str = "1.3.6.1.4.1.1466.115.121.1.36";
+pub const SYNTAX_OID: &str = "1.3.6.1.4.1.1466.115.121.1.38";
+pub const SYNTAX_OCTET_STRING: &str = "1.3.6.1.4.1.1466.115.121.1.40";
+pub const SYNTAX_POSTAL_ADDRESS: &str = "1.3.6.1.4.1.1466.115.121.1.41";
+pub const SYNTAX_PRINTABLE_STRING: &str = "1.3.6.1.4.1.1466.115.121.1.44";
+pub const SYNTAX_TELEPHONE_NUMBER: &str = "1.3.6.1.4.1.1466.115.121.1.50";
+
+const CORE_SYNTAXES: &[&str] = &[
+    "( 1.3.6.1.4.1.1466.115.121.1.3 DESC 'Attribute Type Description' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.4 DESC 'Audio' X-NOT-HUMAN-READABLE 'TRUE' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.5 DESC 'Binary' X-NOT-HUMAN-READABLE 'TRUE' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.6 DESC 'Bit String' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.7 DESC 'Boolean' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.8 DESC 'Certificate' X-NOT-HUMAN-READABLE 'TRUE' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.11 DESC 'Country String' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.12 DESC 'DN' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.14 DESC 'Delivery Method' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.15 DESC 'Directory String' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.16 DESC 'DIT Content Rule Description' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.17 DESC 'DIT Structure Rule Description' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.22 DESC 'Facsimile Telephone Number' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.23 DESC 'Fax' X-NOT-HUMAN-READABLE 'TRUE' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.24 DESC 'Generalized Time' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.25 DESC 'Guide' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.26 DESC 'IA5 String' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.27 DESC 'INTEGER' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.28 DESC 'JPEG' X-NOT-HUMAN-READABLE 'TRUE' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.30 DESC 'Matching Rule Description' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.31 DESC 'Matching Rule Use Description' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.34 DESC 'Name And Optional UID' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.35 DESC 'Name Form Description' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.36 DESC 'Numeric String' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.37 DESC 'Object Class Description' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.38 DESC 'OID' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.40 DESC 'Octet String' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.41 DESC 'Postal Address' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.44 DESC 'Printable String' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.45 DESC 'SubtreeSpecification' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.50 DESC 'Telephone Number' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.51 DESC 'Teletex Terminal Identifier' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.52 DESC 'Telex Number' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.54 DESC 'LDAP Syntax Description' )",
+    "( 1.3.6.1.4.1.1466.115.121.1.58 DESC 'Substring Assertion' )",
+];
+
+const CORE_MATCHING_RULES: &[&str] = &[
+    "( 2.5.13.0 NAME 'objectIdentifierMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.38 )",
+    "( 2.5.13.1 NAME 'distinguishedNameMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 )",
+    "( 2.5.13.2 NAME 'caseIgnoreMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.13.3 NAME 'caseIgnoreOrderingMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.13.4 NAME 'caseIgnoreSubstringsMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.58 )",
+    "( 2.5.13.5 NAME 'caseExactMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.13.6 NAME 'caseExactOrderingMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.13.7 NAME 'caseExactSubstringsMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.58 )",
+    "( 2.5.13.8 NAME 'numericStringMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.36 )",
+    "( 2.5.13.9 NAME 'numericStringOrderingMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.36 )",
+    "( 2.5.13.10 NAME 'numericStringSubstringsMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.58 )",
+    "( 2.5.13.11 NAME 'caseIgnoreListMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.41 )",
+    "( 2.5.13.12 NAME 'caseIgnoreListSubstringsMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.58 )",
+    "( 2.5.13.13 NAME 'booleanMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.7 )",
+    "( 2.5.13.14 NAME 'integerMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.27 )",
+    "( 2.5.13.15 NAME 'integerOrderingMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.27 )",
+    "( 2.5.13.16 NAME 'bitStringMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.6 )",
+    "( 2.5.13.17 NAME 'octetStringMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.40 )",
+    "( 2.5.13.18 NAME 'octetStringOrderingMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.40 )",
+    "( 2.5.13.20 NAME 'telephoneNumberMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.50 )",
+    "( 2.5.13.21 NAME 'telephoneNumberSubstringsMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.58 )",
+    "( 2.5.13.23 NAME 'uniqueMemberMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.34 )",
+    "( 2.5.13.27 NAME 'generalizedTimeMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.24 )",
+    "( 2.5.13.28 NAME 'generalizedTimeOrderingMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.24 )",
+    "( 2.5.13.30 NAME 'objectIdentifierFirstComponentMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.38 )",
+    "( 1.3.6.1.4.1.1466.109.114.1 NAME 'caseExactIA5Match' SYNTAX 1.3.6.1.4.1.1466.115.121.1.26 )",
+    "( 1.3.6.1.4.1.1466.109.114.2 NAME 'caseIgnoreIA5Match' SYNTAX 1.3.6.1.4.1.1466.115.121.1.26 )",
+    "( 1.3.6.1.4.1.1466.109.114.3 NAME 'caseIgnoreIA5SubstringsMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.58 )",
+];
+
+const CORE_ATTRIBUTE_TYPES: &[&str] = &[
+    // rfc 4512
+    "( 2.5.4.0 NAME 'objectClass' EQUALITY objectIdentifierMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.38 )",
+    "( 2.5.4.1 NAME 'aliasedObjectName' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 SINGLE-VALUE )",
+    "( 2.5.18.1 NAME 'createTimestamp' EQUALITY generalizedTimeMatch ORDERING generalizedTimeOrderingMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.24 SINGLE-VALUE NO-USER-MODIFICATION USAGE directoryOperation )",
+    "( 2.5.18.2 NAME 'modifyTimestamp' EQUALITY generalizedTimeMatch ORDERING generalizedTimeOrderingMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.24 SINGLE-VALUE NO-USER-MODIFICATION USAGE directoryOperation )",
+    "( 2.5.18.3 NAME 'creatorsName' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 SINGLE-VALUE NO-USER-MODIFICATION USAGE directoryOperation )",
+    "( 2.5.18.4 NAME 'modifiersName' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 SINGLE-VALUE NO-USER-MODIFICATION USAGE directoryOperation )",
+    "( 2.5.18.6 NAME 'subtreeSpecification' SYNTAX 1.3.6.1.4.1.1466.115.121.1.45 SINGLE-VALUE USAGE directoryOperation )",
+    "( 2.5.18.10 NAME 'subschemaSubentry' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 SINGLE-VALUE NO-USER-MODIFICATION USAGE directoryOperation )",
+    "( 2.5.21.9 NAME 'structuralObjectClass' EQUALITY objectIdentifierMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.38 SINGLE-VALUE NO-USER-MODIFICATION USAGE directoryOperation )",
+    "( 2.5.21.1 NAME 'dITStructureRules' EQUALITY integerFirstComponentMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.17 USAGE directoryOperation )",
+    "( 2.5.21.2 NAME 'dITContentRules' EQUALITY objectIdentifierFirstComponentMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.16 USAGE directoryOperation )",
+    "( 2.5.21.4 NAME 'matchingRules' EQUALITY objectIdentifierFirstComponentMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.30 USAGE directoryOperation )",
+    "( 2.5.21.5 NAME 'attributeTypes' EQUALITY objectIdentifierFirstComponentMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.3 USAGE directoryOperation )",
+    "( 2.5.21.6 NAME 'objectClasses' EQUALITY objectIdentifierFirstComponentMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.37 USAGE directoryOperation )",
+    "( 2.5.21.7 NAME 'nameForms' EQUALITY objectIdentifierFirstComponentMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.35 USAGE directoryOperation )",
+    "( 2.5.21.8 NAME 'matchingRuleUse' EQUALITY objectIdentifierFirstComponentMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.31 USAGE directoryOperation )",
+    "( 1.3.6.1.4.1.1466.101.120.16 NAME 'ldapSyntaxes' EQUALITY objectIdentifierFirstComponentMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.54 USAGE directoryOperation )",
+    "( 1.3.6.1.4.1.1466.101.120.5 NAME 'namingContexts' SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 USAGE dSAOperation )",
+    "( 1.3.6.1.4.1.1466.101.120.6 NAME 'altServer' SYNTAX 1.3.6.1.4.1.1466.115.121.1.26 USAGE dSAOperation )",
+    "( 1.3.6.1.4.1.1466.101.120.7 NAME 'supportedExtension' SYNTAX 1.3.6.1.4.1.1466.115.121.1.38 USAGE dSAOperation )",
+    "( 1.3.6.1.4.1.1466.101.120.13 NAME 'supportedControl' SYNTAX 1.3.6.1.4.1.1466.115.121.1.38 USAGE dSAOperation )",
+    "( 1.3.6.1.4.1.1466.101.120.14 NAME 'supportedSASLMechanisms' SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 USAGE dSAOperation )",
+    "( 1.3.6.1.4.1.1466.101.120.15 NAME 'supportedLDAPVersion' SYNTAX 1.3.6.1.4.1.1466.115.121.1.27 USAGE dSAOperation )",
+    "( 1.3.6.1.4.1.4203.1.3.5 NAME 'supportedFeatures' EQUALITY objectIdentifierMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.38 USAGE dSAOperation )",
+    "( 1.3.6.1.1.20 NAME 'entryDN' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 SINGLE-VALUE NO-USER-MODIFICATION USAGE directoryOperation )",
+    // rfc 4519
+    "( 2.5.4.41 NAME 'name' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.4.49 NAME 'distinguishedName' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 )",
+    "( 2.5.4.3 NAME ( 'cn' 'commonName' ) SUP name )",
+    "( 2.5.4.4 NAME ( 'sn' 'surname' ) SUP name )",
+    "( 2.5.4.42 NAME ( 'givenName' 'gn' ) SUP name )",
+    "( 2.5.4.43 NAME 'initials' SUP name )",
+    "( 2.5.4.44 NAME 'generationQualifier' SUP name )",
+    "( 2.5.4.12 NAME 'title' SUP name )",
+    "( 2.5.4.7 NAME ( 'l' 'localityName' ) SUP name )",
+    "( 2.5.4.8 NAME ( 'st' 'stateOrProvinceName' ) SUP name )",
+    "( 2.5.4.10 NAME ( 'o' 'organizationName' ) SUP name )",
+    "( 2.5.4.11 NAME ( 'ou' 'organizationalUnitName' ) SUP name )",
+    "( 2.5.4.6 NAME ( 'c' 'countryName' ) SUP name SYNTAX 1.3.6.1.4.1.1466.115.121.1.11 SINGLE-VALUE )",
+    "( 2.5.4.5 NAME 'serialNumber' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.44 )",
+    "( 2.5.4.9 NAME ( 'street' 'streetAddress' ) EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.4.13 NAME 'description' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.4.14 NAME 'searchGuide' SYNTAX 1.3.6.1.4.1.1466.115.121.1.25 )",
+    "( 2.5.4.15 NAME 'businessCategory' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.4.16 NAME 'postalAddress' EQUALITY caseIgnoreListMatch SUBSTR caseIgnoreListSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.41 )",
+    "( 2.5.4.17 NAME 'postalCode' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.4.18 NAME 'postOfficeBox' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.4.19 NAME 'physicalDeliveryOfficeName' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.5.4.20 NAME 'telephoneNumber' EQUALITY telephoneNumberMatch SUBSTR telephoneNumberSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.50 )",
+    "( 2.5.4.21 NAME 'telexNumber' SYNTAX 1.3.6.1.4.1.1466.115.121.1.52 )",
+    "( 2.5.4.22 NAME 'teletexTerminalIdentifier' SYNTAX 1.3.6.1.4.1.1466.115.121.1.51 )",
+    "( 2.5.4.23 NAME 'facsimileTelephoneNumber' SYNTAX 1.3.6.1.4.1.1466.115.121.1.22 )",
+    "( 2.5.4.24 NAME 'x121Address' EQUALITY numericStringMatch SUBSTR numericStringSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.36 )",
+    "( 2.5.4.25 NAME 'internationalISDNNumber' EQUALITY numericStringMatch SUBSTR numericStringSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.36 )",
+    "( 2.5.4.26 NAME 'registeredAddress' SUP postalAddress SYNTAX 1.3.6.1.4.1.1466.115.121.1.41 )",
+    "( 2.5.4.27 NAME 'destinationIndicator' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.44 )",
+    "( 2.5.4.28 NAME 'preferredDeliveryMethod' SYNTAX 1.3.6.1.4.1.1466.115.121.1.14 SINGLE-VALUE )",
+    "( 2.5.4.31 NAME 'member' SUP distinguishedName )",
+    "( 2.5.4.32 NAME 'owner' SUP distinguishedName )",
+    "( 2.5.4.33 NAME 'roleOccupant' SUP distinguishedName )",
+    "( 2.5.4.34 NAME 'seeAlso' SUP distinguishedName )",
+    "( 2.5.4.35 NAME 'userPassword' EQUALITY octetStringMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.40 )",
+    "( 2.5.4.36 NAME 'userCertificate' DESC 'X.509 user certificate' EQUALITY octetStringMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.8 )",
+    "( 2.5.4.45 NAME 'x500UniqueIdentifier' EQUALITY bitStringMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.6 )",
+    "( 2.5.4.46 NAME 'dnQualifier' EQUALITY caseIgnoreMatch ORDERING caseIgnoreOrderingMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.44 )",
+    "( 2.5.4.50 NAME 'uniqueMember' EQUALITY uniqueMemberMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.34 )",
+    "( 0.9.2342.19200300.100.1.1 NAME ( 'uid' 'userid' ) EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 0.9.2342.19200300.100.1.25 NAME ( 'dc' 'domainComponent' ) EQUALITY caseIgnoreIA5Match SUBSTR caseIgnoreIA5SubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.26 SINGLE-VALUE )",
+    // rfc 4524
+    "( 0.9.2342.19200300.100.1.3 NAME ( 'mail' 'rfc822Mailbox' ) EQUALITY caseIgnoreIA5Match SUBSTR caseIgnoreIA5SubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.26{256} )",
+    "( 0.9.2342.19200300.100.1.4 NAME 'info' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15{2048} )",
+    "( 0.9.2342.19200300.100.1.6 NAME 'roomNumber' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15{256} )",
+    "( 0.9.2342.19200300.100.1.7 NAME 'photo' SYNTAX 1.3.6.1.4.1.1466.115.121.1.23{25000} )",
+    "( 0.9.2342.19200300.100.1.9 NAME 'host' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15{256} )",
+    "( 0.9.2342.19200300.100.1.10 NAME 'manager' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 )",
+    "( 0.9.2342.19200300.100.1.20 NAME ( 'homePhone' 'homeTelephoneNumber' ) EQUALITY telephoneNumberMatch SUBSTR telephoneNumberSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.50 )",
+    "( 0.9.2342.19200300.100.1.21 NAME 'secretary' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 )",
+    "( 0.9.2342.19200300.100.1.37 NAME 'associatedDomain' EQUALITY caseIgnoreIA5Match SUBSTR caseIgnoreIA5SubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.26 )",
+    "( 0.9.2342.19200300.100.1.38 NAME 'associatedName' EQUALITY distinguishedNameMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.12 )",
+    "( 0.9.2342.19200300.100.1.39 NAME 'homePostalAddress' EQUALITY caseIgnoreListMatch SUBSTR caseIgnoreListSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.41 )",
+    "( 0.9.2342.19200300.100.1.41 NAME ( 'mobile' 'mobileTelephoneNumber' ) EQUALITY telephoneNumberMatch SUBSTR telephoneNumberSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.50 )",
+    "( 0.9.2342.19200300.100.1.42 NAME ( 'pager' 'pagerTelephoneNumber' ) EQUALITY telephoneNumberMatch SUBSTR telephoneNumberSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.50 )",
+    "( 0.9.2342.19200300.100.1.43 NAME ( 'co' 'friendlyCountryName' ) EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 0.9.2342.19200300.100.1.44 NAME 'uniqueIdentifier' EQUALITY caseIgnoreMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15{256} )",
+    "( 0.9.2342.19200300.100.1.55 NAME 'audio' SYNTAX 1.3.6.1.4.1.1466.115.121.1.4{250000} )",
+    // rfc 2798
+    "( 0.9.2342.19200300.100.1.60 NAME 'jpegPhoto' SYNTAX 1.3.6.1.4.1.1466.115.121.1.28 )",
+    "( 1.3.6.1.4.1.250.1.57 NAME 'labeledURI' EQUALITY caseExactMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.16.840.1.113730.3.1.1 NAME 'carLicense' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.16.840.1.113730.3.1.2 NAME 'departmentNumber' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.16.840.1.113730.3.1.3 NAME 'employeeNumber' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 SINGLE-VALUE )",
+    "( 2.16.840.1.113730.3.1.4 NAME 'employeeType' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+    "( 2.16.840.1.113730.3.1.39 NAME 'preferredLanguage' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 SINGLE-VALUE )",
+    "( 2.16.840.1.113730.3.1.40 NAME 'userSMIMECertificate' SYNTAX 1.3.6.1.4.1.1466.115.121.1.5 )",
+    "( 2.16.840.1.113730.3.1.216 NAME 'userPKCS12' SYNTAX 1.3.6.1.4.1.1466.115.121.1.5 )",
+    "( 2.16.840.1.113730.3.1.241 NAME 'displayName' EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 SINGLE-VALUE )",
+];
+
+const CORE_OBJECT_CLASSES: &[&str] = &[
+    "( 2.5.6.0 NAME 'top' ABSTRACT MUST objectClass )",
+    "( 2.5.6.1 NAME 'alias' SUP top STRUCTURAL MUST aliasedObjectName )",
+    "( 1.3.6.1.4.1.1466.101.120.111 NAME 'extensibleObject' SUP top AUXILIARY )",
+    "( 2.5.20.1 NAME 'subschema' AUXILIARY MAY ( dITStructureRules $ nameForms $ dITContentRules $ objectClasses $ attributeTypes $ matchingRules $ matchingRuleUse ) )",
+    "( 2.5.17.0 NAME 'subentry' SUP top STRUCTURAL MUST ( cn $ subtreeSpecification ) )",
+    "( 2.5.6.2 NAME 'country' SUP top STRUCTURAL MUST c MAY ( searchGuide $ description ) )",
+    "( 2.5.6.3 NAME 'locality' SUP top STRUCTURAL MAY ( street $ seeAlso $ searchGuide $ st $ l $ description ) )",
+    "( 2.5.6.4 NAME 'organization' SUP top STRUCTURAL MUST o MAY ( userPassword $ searchGuide $ seeAlso $ businessCategory $ x121Address $ registeredAddress $ destinationIndicator $ preferredDeliveryMethod $ telexNumber $ teletexTerminalIdentifier $ telephoneNumber $ internationalISDNNumber $ facsimileTelephoneNumber $ street $ postOfficeBox $ postalCode $ postalAddress $ physicalDeliveryOfficeName $ st $ l $ description ) )",
+    "( 2.5.6.5 NAME 'organizationalUnit' SUP top STRUCTURAL MUST ou MAY ( businessCategory $ description $ destinationIndicator $ facsimileTelephoneNumber $ internationalISDNNumber $ l $ physicalDeliveryOfficeName $ postalAddress $ postalCode $ postOfficeBox $ preferredDeliveryMethod $ registeredAddress $ searchGuide $ seeAlso $ st $ street $ telephoneNumber $ teletexTerminalIdentifier $ telexNumber $ userPassword $ x121Address ) )",
+    "( 2.5.6.6 NAME 'person' SUP top STRUCTURAL MUST ( sn $ cn ) MAY ( userPassword $ telephoneNumber $ seeAlso $ description ) )",
+    "( 2.5.6.7 NAME 'organizationalPerson' SUP person STRUCTURAL MAY ( title $ x121Address $ registeredAddress $ destinationIndicator $ preferredDeliveryMethod $ telexNumber $ teletexTerminalIdentifier $ telephoneNumber $ internationalISDNNumber $ facsimileTelephoneNumber $ street $ postOfficeBox $ postalCode $ postalAddress $ physicalDeliveryOfficeName $ ou $ st $ l ) )",
+    "( 2.5.6.8 NAME 'organizationalRole' SUP top STRUCTURAL MUST cn MAY ( x121Address $ registeredAddress $ destinationIndicator $ preferredDeliveryMethod $ telexNumber $ teletexTerminalIdentifier $ telephoneNumber $ internationalISDNNumber $ facsimileTelephoneNumber $ seeAlso $ roleOccupant $ street $ postOfficeBox $ postalCode $ postalAddress $ physicalDeliveryOfficeName $ ou $ st $ l $ description ) )",
+    "( 2.5.6.9 NAME 'groupOfNames' SUP top STRUCTURAL MUST ( member $ cn ) MAY ( businessCategory $ seeAlso $ owner $ ou $ o $ description ) )",
+    "( 2.5.6.10 NAME 'residentialPerson' SUP person STRUCTURAL MUST l MAY ( businessCategory $ x121Address $ registeredAddress $ destinationIndicator $ preferredDeliveryMethod $ telexNumber $ teletexTerminalIdentifier $ telephoneNumber $ internationalISDNNumber $ facsimileTelephoneNumber $ street $ postOfficeBox $ postalCode $ postalAddress $ physicalDeliveryOfficeName $ st $ l ) )",
+    "( 2.5.6.11 NAME 'applicationProcess' SUP top STRUCTURAL MUST cn MAY ( seeAlso $ ou $ l $ description ) )",
+    "( 2.5.6.14 NAME 'device' SUP top STRUCTURAL MUST cn MAY ( serialNumber $ seeAlso $ owner $ ou $ o $ l $ description ) )",
+    "( 2.5.6.17 NAME 'groupOfUniqueNames' SUP top STRUCTURAL MUST ( uniqueMember $ cn ) MAY ( businessCategory $ seeAlso $ owner $ ou $ o $ description ) )",
+    "( 1.3.6.1.4.1.1466.344 NAME 'dcObject' SUP top AUXILIARY MUST dc )",
+    "( 1.3.6.1.1.3.1 NAME 'uidObject' SUP top AUXILIARY MUST uid )",
+    // rfc 4524
+    "( 0.9.2342.19200300.100.4.5 NAME 'account' SUP top STRUCTURAL MUST uid MAY ( description $ seeAlso $ l $ o $ ou $ host ) )",
+    "( 0.9.2342.19200300.100.4.13 NAME 'domain' SUP top STRUCTURAL MUST dc MAY ( userPassword $ searchGuide $ seeAlso $ businessCategory $ x121Address $ registeredAddress $ destinationIndicator $ preferredDeliveryMethod $ telexNumber $ teletexTerminalIdentifier $ telephoneNumber $ internationalISDNNumber $ facsimileTelephoneNumber $ street $ postOfficeBox $ postalCode $ postalAddress $ physicalDeliveryOfficeName $ st $ l $ description $ o $ associatedName ) )",
+    "( 0.9.2342.19200300.100.4.17 NAME 'domainRelatedObject' SUP top AUXILIARY MUST associatedDomain )",
+    "( 0.9.2342.19200300.100.4.18 NAME 'friendlyCountry' SUP country STRUCTURAL MUST co )",
+    "( 0.9.2342.19200300.100.4.19 NAME 'simpleSecurityObject' SUP top AUXILIARY MUST userPassword )",
+    // rfc 2798
+    "( 2.16.840.1.113730.3.2.2 NAME 'inetOrgPerson' SUP organizationalPerson STRUCTURAL MAY ( audio $ businessCategory $ carLicense $ departmentNumber $ displayName $ employeeNumber $ employeeType $ givenName $ homePhone $ homePostalAddress $ initials $ jpegPhoto $ labeledURI $ mail $ manager $ mobile $ o $ pager $ photo $ roomNumber $ secretary $ uid $ userCertificate $ x500UniqueIdentifier $ preferredLanguage $ userSMIMECertificate $ userPKCS12 ) )",
+];
+
+#[test]
+fn schema_test() {
+    let d = "( 2.5.4.3 NAME ( 'cn' 'commonName' ) DESC '\\27x\\27' SUP name X-ORIGIN 'RFC 4519' )";
+    let at = AttributeType::parse(d).unwrap();
+    assert_eq!(at.names, vec!["cn", "commonName"]);
+    assert_eq!(at.desc.as_deref(), Some("'x'"));
+    assert_eq!(at.sup.as_deref(), Some("name"));
+    assert_eq!(at.to_string(), d);
+
+    let d = "( 1.2.3 NAME 'x' SYNTAX 1.3.6.1.4.1.1466.115.121.1.26{256} SINGLE-VALUE USAGE dSAOperation )";
+    let at = AttributeType::parse(d).unwrap();
+    assert_eq!(at.syntax_len, Some(256));
+    assert!(at.single_value && at.is_operational());
+    assert_eq!(at.to_string(), d);
+
+    let s = Schema::core();
+    assert_eq!(s.attribute_type("commonName").unwrap().oid, "2.5.4.3");
+    assert_eq!(s.attribute_type("2.5.4.3").unwrap().name(), "cn");
+    assert_eq!(s.canonical_name("SURNAME"), "sn");
+    assert_eq!(s.equality_rule("cn").unwrap().name(), "caseIgnoreMatch");
+    assert_eq!(s.attribute_syntax("cn"), Some(SYNTAX_DIRECTORY_STRING));
+    assert!(s.is_subtype("member", "distinguishedName"));
+    assert!(s.is_subtype("cn;lang-en", "name"));
+    let chain: Vec<&str> = s
+        .object_class_chain("inetOrgPerson")
+        .iter()
+        .map(|o| o.name())
+        .collect();
+    assert_eq!(
+        chain,
+        vec!["inetOrgPerson", "organizationalPerson", "person", "top"]
+    );
+    let must: Vec<&str> = s.must("inetOrgPerson").iter().map(|a| a.name()).collect();
+    assert_eq!(must, vec!["sn", "cn", "objectClass"]);
+    assert!(s.may("inetOrgPerson").iter().any(|a| a.name() == "mail"));
+
+    // every reference in the core schema resolves
+    for at in s.attribute_types() {
+        for r in [&at.equality, &at.ordering, &at.substr]
+            .into_iter()
+            .flatten()
+        {
+            assert!(
+                s.matching_rule(r).is_some() || r == "integerFirstComponentMatch",
+                "{}",
+                r
+            );
+        }
+        assert!(s
+            .attribute_syntax(at.name())
+            .and_then(|o| s.syntax(o))
+            .is_some());
+    }
+    for oc in s.object_classes() {
+        for a in oc.must.iter().chain(oc.may.iter()) {
+            assert!(s.attribute_type(a).is_some(), "{}", a);
+        }
+    }
+
+    let copy = Schema::from_subschema(&s.to_subschema_attributes()).unwrap();
+    assert_eq!(copy.attribute_types().len(), s.attribute_types().len());
+    assert_eq!(copy.object_class("person"), s.object_class("person"));
+}
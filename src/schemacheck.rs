@@ -0,0 +1,568 @@
+use crate::codec;
+use crate::directory::{apply_change, Entry};
+use crate::dn::{Ava, Dn};
+use crate::filter::generalized_time;
+use crate::ldap::*;
+use crate::schema::*;
+use crate::server::{BoxFuture2, Service};
+use std::io::Result;
+use std::sync::Arc;
+
+type Check = std::result::Result<(), MsgResult>;
+
+fn violation(res: u32, diag: String) -> MsgResult {
+    MsgResult {
+        res,
+        matched_dn: String::new(),
+        diag,
+    }
+}
+
+fn is_printable(v: &str) -> bool {
+    !v.is_empty()
+        && v.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b" '()+,-./:=?".contains(&b))
+}
+
+fn is_numeric(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_oid(v: &str) -> bool {
+    let descr = v.starts_with(|c: char| c.is_ascii_alphabetic())
+        && v.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+    descr
+        || v.split('.')
+            .all(|n| is_numeric(n) && (n == "0" || !n.starts_with('0')))
+}
+
+fn is_integer(v: &str) -> bool {
+    let d = v.strip_prefix('-').unwrap_or(v);
+    is_numeric(d) && (d == "0" || !d.starts_with('0')) && v != "-0"
+}
+
+fn is_bit_string(v: &str) -> bool {
+    match v.strip_prefix('\'').and_then(|v| v.strip_suffix("'B")) {
+        Some(bits) => bits.bytes().all(|b| b == b'0' || b == b'1'),
+        None => false,
+    }
+}
+
+// values of syntaxes without a check here are accepted as they are
+pub fn check_value(syntax: &str, value: &str) -> bool {
+    match syntax {
+        SYNTAX_BIT_STRING => is_bit_string(value),
+        SYNTAX_BOOLEAN => value == "TRUE" || value == "FALSE",
+        SYNTAX_COUNTRY_STRING => value.len() == 2 && is_printable(value),
+        SYNTAX_DN => Dn::parse(value).is_ok(),
+        SYNTAX_DIRECTORY_STRING => !value.is_empty(),
+        SYNTAX_GENERALIZED_TIME => generalized_time(value).is_some(),
+        SYNTAX_IA5_STRING => value.is_ascii(),
+        SYNTAX_INTEGER => is_integer(value),
+        SYNTAX_NAME_AND_OPTIONAL_UID => match value.rsplit_once('#') {
+            Some((dn, uid)) if is_bit_string(uid) => Dn::parse(dn).is_ok(),
+            _ => Dn::parse(value).is_ok(),
+        },
+        SYNTAX_NUMERIC_STRING => {
+            !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit() || b == b' ')
+        }
+        SYNTAX_OID => is_oid(value),
+        SYNTAX_PRINTABLE_STRING | SYNTAX_TELEPHONE_NUMBER => is_printable(value),
+        _ => true,
+    }
+}
+
+fn check_values(schema: &Schema, attr: &PartialAttribute) -> Check {
+    let syntax = schema.attribute_syntax(&attr.name).unwrap_or_default();
+    match attr.values.iter().find(|v| !check_value(syntax, v)) {
+        Some(v) => Err(violation(
+            RESULT_INVALID_ATTRIBUTE_SYNTAX,
+            format!("{}: value {:?} is invalid", attr.name, v),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn object_classes<'a>(
+    schema: &'a Schema,
+    attrs: &[PartialAttribute],
+) -> std::result::Result<Vec<&'a ObjectClass>, MsgResult> {
+    let values = attrs
+        .iter()
+        .filter(|a| schema.is_subtype(&a.name, "objectClass"))
+        .flat_map(|a| a.values.iter());
+    let mut out: Vec<&ObjectClass> = Vec::new();
+    for v in values {
+        if schema.object_class(v).is_none() {
+            return Err(violation(
+                RESULT_OBJECT_CLASS_VIOLATION,
+                format!("unknown objectClass {}", v),
+            ));
+        }
+        for oc in schema.object_class_chain(v) {
+            if !out.iter().any(|o| o.oid == oc.oid) {
+                out.push(oc);
+            }
+        }
+    }
+    if out.is_empty() {
+        return Err(violation(
+            RESULT_OBJECT_CLASS_VIOLATION,
+            "no objectClass".to_owned(),
+        ));
+    }
+    Ok(out)
+}
+
+// the most specific structural class, all other structural classes must be its superiors
+fn structural_class<'a>(
+    schema: &'a Schema,
+    classes: &[&'a ObjectClass],
+) -> Option<&'a ObjectClass> {
+    let structural: Vec<&ObjectClass> = classes
+        .iter()
+        .filter(|oc| oc.kind == ObjectClassKind::Structural)
+        .copied()
+        .collect();
+    structural.iter().copied().find(|s| {
+        let chain = schema.object_class_chain(&s.oid);
+        structural
+            .iter()
+            .all(|o| chain.iter().any(|c| c.oid == o.oid))
+    })
+}
+
+fn check_modifiable(at: &AttributeType) -> Check {
+    if at.no_user_modification {
+        return Err(violation(
+            RESULT_CONSTRAINT_VIOLATION,
+            format!("{}: no user modification allowed", at.name()),
+        ));
+    }
+    Ok(())
+}
+
+// checks a complete entry against the object classes it lists
+pub fn check_entry(schema: &Schema, dn: &Dn, attrs: &[PartialAttribute]) -> Check {
+    let classes = object_classes(schema, attrs)?;
+    if structural_class(schema, &classes).is_none() {
+        return Err(violation(
+            RESULT_OBJECT_CLASS_VIOLATION,
+            "no single structural objectClass".to_owned(),
+        ));
+    }
+    let extensible = classes.iter().any(|oc| oc.has_name("extensibleObject"));
+    let allowed = |at: &AttributeType| {
+        classes.iter().any(|oc| {
+            oc.must
+                .iter()
+                .chain(oc.may.iter())
+                .any(|name| at.has_name(name))
+        })
+    };
+    for attr in attrs {
+        let at = match schema.attribute_type(&attr.name) {
+            Some(at) => at,
+            None => {
+                return Err(violation(
+                    RESULT_UNDEFINED_ATTRIBUTE_TYPE,
+                    format!("{}: attribute type undefined", attr.name),
+                ))
+            }
+        };
+        if !at.is_operational() && !extensible && !allowed(at) {
+            return Err(violation(
+                RESULT_OBJECT_CLASS_VIOLATION,
+                format!("{}: attribute not allowed", attr.name),
+            ));
+        }
+        if at.single_value && attr.values.len() > 1 {
+            return Err(violation(
+                RESULT_CONSTRAINT_VIOLATION,
+                format!("{}: single-valued attribute has multiple values", attr.name),
+            ));
+        }
+        check_values(schema, attr)?;
+    }
+    for oc in &classes {
+        for name in &oc.must {
+            let present = attrs.iter().any(|a| {
+                schema
+                    .attribute_type(&a.name)
+                    .is_some_and(|at| at.has_name(name))
+            });
+            if !present {
+                return Err(violation(
+                    RESULT_OBJECT_CLASS_VIOLATION,
+                    format!("{}: required attribute missing", name),
+                ));
+            }
+        }
+    }
+    for ava in dn.rdn().map(|r| r.avas.as_slice()).unwrap_or_default() {
+        if !has_value(schema, attrs, &ava.attr, &ava.value) {
+            return Err(violation(
+                RESULT_NAMING_VIOLATION,
+                format!("{}: naming attribute value missing from entry", ava.attr),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn has_value(schema: &Schema, attrs: &[PartialAttribute], name: &str, value: &str) -> bool {
+    let at = match schema.attribute_type(name) {
+        Some(at) => at,
+        None => return false,
+    };
+    attrs
+        .iter()
+        .filter(|a| {
+            schema
+                .attribute_type(&a.name)
+                .is_some_and(|o| o.oid == at.oid)
+        })
+        .flat_map(|a| a.values.iter())
+        .any(|v| v.eq_ignore_ascii_case(value))
+}
+
+// checks the changes of a modify on their own, without the entry they apply to
+pub fn check_changes(schema: &Schema, changes: &[Change]) -> Check {
+    for change in changes {
+        let attr = &change.modification;
+        let at = match schema.attribute_type(&attr.name) {
+            Some(at) => at,
+            None => {
+                return Err(violation(
+                    RESULT_UNDEFINED_ATTRIBUTE_TYPE,
+                    format!("{}: attribute type undefined", attr.name),
+                ))
+            }
+        };
+        check_modifiable(at)?;
+        if change.operation != ModifyOperation::Delete {
+            check_values(schema, attr)?;
+        }
+    }
+    Ok(())
+}
+
+// checks the entry a modify would produce, old holds the attributes before the changes
+fn check_modified(
+    schema: &Schema,
+    dn: &Dn,
+    old: Vec<PartialAttribute>,
+    changes: &[Change],
+) -> Check {
+    let mut entry = Entry {
+        dn: dn.to_string(),
+        attributes: old,
+    };
+    let old_classes = object_classes(schema, &entry.attributes)?;
+    let old_structural = structural_class(schema, &old_classes).map(|oc| oc.oid.clone());
+    for change in changes {
+        // errors like missing values are left to the service to report
+        if apply_change(&mut entry, change).is_err() {
+            return Ok(());
+        }
+    }
+    for ava in dn.rdn().map(|r| r.avas.as_slice()).unwrap_or_default() {
+        if !has_value(schema, &entry.attributes, &ava.attr, &ava.value) {
+            return Err(violation(
+                RESULT_NOT_ALLOWED_ON_RDN,
+                format!("{}: naming attribute value can not be removed", ava.attr),
+            ));
+        }
+    }
+    let classes = object_classes(schema, &entry.attributes)?;
+    let structural = structural_class(schema, &classes).map(|oc| oc.oid.clone());
+    if old_structural.is_some() && structural.is_some() && structural != old_structural {
+        return Err(violation(
+            RESULT_OBJECT_CLASS_MODS_PROHIBITED,
+            "structural objectClass can not be changed".to_owned(),
+        ));
+    }
+    check_entry(schema, dn, &entry.attributes)
+}
+
+// checks the entry a modify dn would produce, the values of the new rdn are added and those
+// of the old one removed when delete_old_rdn is set
+fn check_renamed(
+    schema: &Schema,
+    dn: &Dn,
+    new_dn: &Dn,
+    delete_old_rdn: bool,
+    old: Vec<PartialAttribute>,
+) -> Check {
+    let ava_change = |operation, ava: &Ava| Change {
+        operation,
+        modification: PartialAttribute {
+            name: ava.attr.clone(),
+            values: vec![ava.value.clone()],
+        },
+    };
+    let old_avas = dn.rdn().map(|r| r.avas.as_slice()).unwrap_or_default();
+    let new_avas = new_dn.rdn().map(|r| r.avas.as_slice()).unwrap_or_default();
+    let changes: Vec<Change> = old_avas
+        .iter()
+        .filter(|_| delete_old_rdn)
+        .map(|a| ava_change(ModifyOperation::Delete, a))
+        .chain(new_avas.iter().map(|a| ava_change(ModifyOperation::Add, a)))
+        .collect();
+    check_changes(schema, &changes)?;
+    let mut entry = Entry {
+        dn: new_dn.to_string(),
+        attributes: old,
+    };
+    for change in &changes {
+        // values already present or already gone are fine, like in the directory
+        let _ = apply_change(&mut entry, change);
+    }
+    check_entry(schema, new_dn, &entry.attributes)
+}
+
+// rejects adds, modifies and renames that violate the schema before they reach the wrapped service
+pub struct SchemaCheck<S> {
+    inner: S,
+    schema: Arc<Schema>,
+}
+
+impl<S: Service> SchemaCheck<S> {
+    pub fn new(inner: S, schema: Arc<Schema>) -> Self {
+        Self { inner, schema }
+    }
+
+    pub fn schema(&self) -> &Arc<Schema> {
+        &self.schema
+    }
+
+    fn check(&self, req: &Message) -> Option<Result<Vec<u8>>> {
+        let r = match &req.params {
+            MessageParams::Add(a) => {
+                let r = match Dn::parse(&a.entry) {
+                    Ok(dn) => a
+                        .attributes
+                        .iter()
+                        .filter_map(|a| self.schema.attribute_type(&a.name))
+                        .try_for_each(check_modifiable)
+                        .and_then(|_| check_entry(&self.schema, &dn, &a.attributes)),
+                    Err(_) => Err(violation(RESULT_INVALID_DN_SYNTAX, String::new())),
+                };
+                r.err().map(|r| codec::ldap_write_add_response(req.id, &r))
+            }
+            MessageParams::Modify(m) => {
+                let r = check_changes(&self.schema, &m.changes).and_then(|_| {
+                    match (Dn::parse(&m.object), self.inner.entry(&m.object)) {
+                        (Ok(dn), Some(old)) => check_modified(&self.schema, &dn, old, &m.changes),
+                        _ => Ok(()),
+                    }
+                });
+                r.err()
+                    .map(|r| codec::ldap_write_modify_response(req.id, &r))
+            }
+            MessageParams::ModDn(m) => {
+                let new_dn = |dn: &Dn| {
+                    let parent = match &m.new_superior {
+                        Some(s) => Dn::parse(s).ok()?,
+                        None => dn.parent()?,
+                    };
+                    let rdn = Dn::parse(&m.new_rdn).ok()?.rdns.pop()?;
+                    Some(parent.child(rdn))
+                };
+                // bad names and missing entries are left to the service to report
+                let r = match (m.dn(), self.inner.entry(&m.entry)) {
+                    (Ok(dn), Some(old)) => match new_dn(&dn) {
+                        Some(new_dn) => {
+                            check_renamed(&self.schema, &dn, &new_dn, m.delete_old_rdn, old)
+                        }
+                        None => Ok(()),
+                    },
+                    _ => Ok(()),
+                };
+                r.err()
+                    .map(|r| codec::ldap_write_moddn_response(req.id, &r))
+            }
+            _ => None,
+        };
+        if let Some(Ok(_)) = &r {
+            println!("schema check rejected request {}", req.id);
+        }
+        r
+    }
+}
+
+impl<S: Service> Service for SchemaCheck<S> {
+    type Future = BoxFuture2<Result<Vec<u8>>>;
+
+    fn call(&self, req: Message) -> Self::Future {
+        match self.check(&req) {
+            Some(r) => Box::pin(async move { r }),
+            None => Box::pin(self.inner.call(req)),
+        }
+    }
+
+    fn naming_contexts(&self) -> Vec<String> {
+        self.inner.naming_contexts()
+    }
+    fn supported_controls(&self) -> Vec<String> {
+        self.inner.supported_controls()
+    }
+    fn supported_extensions(&self) -> Vec<String> {
+        self.inner.supported_extensions()
+    }
+    fn supported_sasl_mechanisms(&self) -> Vec<String> {
+        self.inner.supported_sasl_mechanisms()
+    }
+    fn entry(&self, dn: &str) -> Option<Vec<PartialAttribute>> {
+        self.inner.entry(dn)
+    }
+    fn unbind(&self, bound_dn: &str) {
+        self.inner.unbind(bound_dn)
+    }
+}
+
+#[tokio::test]
+async fn schema_check_test() {
+    let dir = crate::directory::Directory::new();
+    dir.apply_ldif(
+        "dn: dc=example\nobjectClass: domain\ndc: example\n\n\
+         dn: uid=j,dc=example\nobjectClass: inetOrgPerson\nuid: j\ncn: J\nsn: S\n",
+    )
+    .unwrap();
+    let svc = SchemaCheck::new(dir, Arc::new(Schema::core()));
+    let attr = |name: &str, values: &[&str]| PartialAttribute {
+        name: name.to_owned(),
+        values: values.iter().map(|v| v.to_string()).collect(),
+    };
+    let add = |attributes: Vec<PartialAttribute>| Message {
+        id: 1,
+        params: MessageParams::Add(MsgAdd {
+            entry: "uid=k,dc=example".to_owned(),
+            attributes,
+        }),
+        controls: Vec::new(),
+    };
+    let modify = |changes: Vec<Change>| Message {
+        id: 2,
+        params: MessageParams::Modify(MsgModify {
+            object: "uid=j,dc=example".to_owned(),
+            changes,
+        }),
+        controls: Vec::new(),
+    };
+    let result = |out: Result<Vec<u8>>| match codec::parse_message(&out.unwrap()).unwrap().0.params
+    {
+        MessageParams::AddResponse(r)
+        | MessageParams::ModifyResponse(r)
+        | MessageParams::ModDnResponse(r) => r.res,
+        _ => unreachable!(),
+    };
+    let person = || {
+        vec![
+            attr("objectClass", &["top", "inetOrgPerson"]),
+            attr("uid", &["k"]),
+            attr("cn", &["K"]),
+        ]
+    };
+
+    assert_eq!(
+        result(svc.call(add(person())).await),
+        RESULT_OBJECT_CLASS_VIOLATION
+    );
+    let mut a = person();
+    a.push(attr("surname", &["S"]));
+    a.push(attr("foo", &["x"]));
+    assert_eq!(
+        result(svc.call(add(a)).await),
+        RESULT_UNDEFINED_ATTRIBUTE_TYPE
+    );
+    let mut a = person();
+    a.push(attr("sn", &["S"]));
+    a.push(attr("dc", &["x"]));
+    assert_eq!(
+        result(svc.call(add(a)).await),
+        RESULT_OBJECT_CLASS_VIOLATION
+    );
+    let mut a = person();
+    a.push(attr("sn", &["S"]));
+    a.push(attr("displayName", &["a", "b"]));
+    assert_eq!(result(svc.call(add(a)).await), RESULT_CONSTRAINT_VIOLATION);
+    let mut a = person();
+    a[0].values.push("organizationalRole".to_owned());
+    a.push(attr("sn", &["S"]));
+    assert_eq!(
+        result(svc.call(add(a)).await),
+        RESULT_OBJECT_CLASS_VIOLATION
+    );
+    let mut a = person();
+    a[1].values[0] = "other".to_owned();
+    a.push(attr("sn", &["S"]));
+    assert_eq!(result(svc.call(add(a)).await), RESULT_NAMING_VIOLATION);
+    let mut a = person();
+    a.push(attr("sn", &["S"]));
+    assert_eq!(result(svc.call(add(a)).await), RESULT_SUCCESS);
+
+    let change = |operation, a| Change {
+        operation,
+        modification: a,
+    };
+    let r = svc.call(modify(vec![change(
+        ModifyOperation::Replace,
+        attr("manager", &["not a dn"]),
+    )]));
+    assert_eq!(result(r.await), RESULT_INVALID_ATTRIBUTE_SYNTAX);
+    let r = svc.call(modify(vec![change(
+        ModifyOperation::Delete,
+        attr("sn", &[]),
+    )]));
+    assert_eq!(result(r.await), RESULT_OBJECT_CLASS_VIOLATION);
+    let r = svc.call(modify(vec![change(
+        ModifyOperation::Delete,
+        attr("uid", &[]),
+    )]));
+    assert_eq!(result(r.await), RESULT_NOT_ALLOWED_ON_RDN);
+    let r = svc.call(modify(vec![change(
+        ModifyOperation::Replace,
+        attr("objectClass", &["account"]),
+    )]));
+    assert_eq!(result(r.await), RESULT_OBJECT_CLASS_MODS_PROHIBITED);
+    let r = svc.call(modify(vec![change(
+        ModifyOperation::Replace,
+        attr("createTimestamp", &["20240101000000Z"]),
+    )]));
+    assert_eq!(result(r.await), RESULT_CONSTRAINT_VIOLATION);
+    let r = svc.call(modify(vec![change(
+        ModifyOperation::Add,
+        attr("mail", &["j@example.com"]),
+    )]));
+    assert_eq!(result(r.await), RESULT_SUCCESS);
+
+    let moddn = |new_rdn: &str| Message {
+        id: 3,
+        params: MessageParams::ModDn(MsgModDn {
+            entry: "uid=j,dc=example".to_owned(),
+            new_rdn: new_rdn.to_owned(),
+            delete_old_rdn: true,
+            new_superior: None,
+        }),
+        controls: Vec::new(),
+    };
+    assert_eq!(
+        result(svc.call(moddn("dc=j")).await),
+        RESULT_OBJECT_CLASS_VIOLATION
+    );
+    assert_eq!(
+        result(svc.call(moddn("foo=j")).await),
+        RESULT_UNDEFINED_ATTRIBUTE_TYPE
+    );
+    assert_eq!(
+        result(svc.call(moddn("manager=j")).await),
+        RESULT_INVALID_ATTRIBUTE_SYNTAX
+    );
+    assert_eq!(result(svc.call(moddn("cn=K")).await), RESULT_SUCCESS);
+
+    assert!(check_value(SYNTAX_GENERALIZED_TIME, "199412161032.5-0500"));
+    assert!(!check_value(SYNTAX_GENERALIZED_TIME, "19941316103200Z"));
+    assert!(check_value(SYNTAX_INTEGER, "-12") && !check_value(SYNTAX_INTEGER, "012"));
+    assert!(check_value(SYNTAX_OID, "2.5.4.3") && check_value(SYNTAX_OID, "cn"));
+}
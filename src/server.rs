@@ -1,19 +1,444 @@
-use crate::{ldap, tokiou};
-use std::{future::Future, io::Result, pin::Pin, sync::Arc};
+use crate::acl::AccessControl;
+use crate::dn::Dn;
+use crate::ldap::{
+    Filter, MessageParams, MsgExtendedResponse, MsgResult, MsgSearch, PartialAttribute,
+};
+use crate::tokenbucket::{KeyedRateLimiter, TokenBucket};
+use crate::{codec, ldap, tokiou};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::{future::Future, io::Result, pin::Pin, sync::Arc, time::Duration};
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::Instant;
 
 pub trait Service {
     type Future: Future<Output = Result<Vec<u8>>> + Send + Sync + 'static;
+    // abandon and cancel drop the returned future, work done before call returns can not
+    // be interrupted
     fn call(&self, req: ldap::Message) -> Self::Future;
+
+    // what the service handles, published in the root dse
+    fn naming_contexts(&self) -> Vec<String> {
+        Vec::new()
+    }
+    fn supported_controls(&self) -> Vec<String> {
+        Vec::new()
+    }
+    fn supported_extensions(&self) -> Vec<String> {
+        Vec::new()
+    }
+    fn supported_sasl_mechanisms(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // current attributes of an entry, lets layers in front of the service see what a modify changes
+    fn entry(&self, _dn: &str) -> Option<Vec<PartialAttribute>> {
+        None
+    }
+
+    // the client sent an unbind, its outstanding operations are abandoned and the connection closed.
+    // bound_dn is who the connection was bound as, empty for anonymous.
+    fn unbind(&self, _bound_dn: &str) {}
+}
+
+// why a connection stopped reading requests
+enum Closed {
+    Shutdown,
+    Unbind,
+    Timeout(&'static str),
+}
+
+// counts a connection against its source address until dropped
+struct IpSlot {
+    ip: IpAddr,
+    per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut per_ip = self.per_ip.lock().unwrap();
+        if let Some(n) = per_ip.get_mut(&self.ip) {
+            *n -= 1;
+            if *n == 0 {
+                per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+const ROOT_DSE_OPERATIONAL: &[&str] = &[
+    "namingContexts",
+    "subschemaSubentry",
+    "supportedLDAPVersion",
+    "supportedControl",
+    "supportedExtension",
+    "supportedSASLMechanisms",
+];
+
+const SUBSCHEMA_OPERATIONAL: &[&str] = &[
+    "attributeTypes",
+    "objectClasses",
+    "ldapSyntaxes",
+    "matchingRules",
+    "matchingRuleUse",
+    "dITContentRules",
+    "dITStructureRules",
+    "nameForms",
+];
+
+struct Subschema {
+    dn: Dn,
+    attributes: Vec<PartialAttribute>,
+}
+
+// request kinds, used to answer a request whose handler failed and to pick rate limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Bind,
+    Search,
+    Add,
+    Modify,
+    Del,
+    ModDn,
+    Compare,
+    Unbind,
+    Abandon,
+    Extended,
+    Other,
+}
+
+impl Operation {
+    fn of(params: &MessageParams) -> Self {
+        match params {
+            MessageParams::Bind(_) => Operation::Bind,
+            MessageParams::Search(_) => Operation::Search,
+            MessageParams::Add(_) => Operation::Add,
+            MessageParams::Modify(_) => Operation::Modify,
+            MessageParams::Del(_) => Operation::Del,
+            MessageParams::ModDn(_) => Operation::ModDn,
+            MessageParams::Compare(_) => Operation::Compare,
+            MessageParams::Unbind(_) => Operation::Unbind,
+            MessageParams::Abandon(_) => Operation::Abandon,
+            MessageParams::ExtendedRequest(_) => Operation::Extended,
+            _ => Operation::Other,
+        }
+    }
+}
+
+// a password modify request without a user is about the bound dn, which the service cannot see
+fn fill_password_modify_user(r: &mut ldap::MsgExtendedRequest, bound: &str) {
+    if r.name != ldap::PASSWORD_MODIFY_REQUEST || bound.is_empty() {
+        return;
+    }
+    let fields = match r.value.as_deref() {
+        Some(v) => codec::ldap_read_password_modify(v),
+        None => Ok((None, None, None)),
+    };
+    if let Ok((None, old, new)) = fields {
+        if let Ok(v) =
+            codec::ldap_write_password_modify(Some(bound), old.as_deref(), new.as_deref())
+        {
+            r.value = Some(v);
+        }
+    }
+}
+
+fn error_response(id: u32, op: Operation, code: u32, diag: &str) -> Result<Vec<u8>> {
+    let res = MsgResult {
+        res: code,
+        matched_dn: String::new(),
+        diag: diag.to_owned(),
+    };
+    match op {
+        Operation::Bind => codec::ldap_write_bind_result(
+            id,
+            &ldap::MsgBindResponse {
+                res: res.res,
+                matched_dn: res.matched_dn,
+                diag: res.diag,
+            },
+        ),
+        Operation::Search => codec::ldap_write_search_res_done(id, res.res),
+        Operation::Add => codec::ldap_write_add_response(id, &res),
+        Operation::Modify => codec::ldap_write_modify_response(id, &res),
+        Operation::Del => codec::ldap_write_del_response(id, &res),
+        Operation::ModDn => codec::ldap_write_moddn_response(id, &res),
+        Operation::Compare => codec::ldap_write_compare_response(id, &res),
+        Operation::Extended => codec::ldap_write_extended_response(
+            id,
+            &MsgExtendedResponse {
+                result: res,
+                name: None,
+                value: None,
+            },
+        ),
+        Operation::Unbind | Operation::Abandon | Operation::Other => Ok(Vec::new()),
+    }
+}
+
+// who a rate limit is counted against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitScope {
+    Connection,
+    Ip,
+    BindDn,
+}
+
+// what happens to a request over the limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitAction {
+    // answered with busy right away
+    Busy,
+    // held back until a token is available, which also stops reading from the connection so
+    // abandon and unbind requests sent meanwhile wait as well. shutdown does not wait.
+    Delay,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    pub scope: RateLimitScope,
+    // operations counted against the limit, all of them when empty
+    pub operations: Vec<Operation>,
+    pub per_second: f64,
+    pub action: RateLimitAction,
+}
+
+impl RateLimit {
+    fn applies(&self, op: Operation) -> bool {
+        self.operations.is_empty() || self.operations.contains(&op)
+    }
+    // a second worth of requests may come in a burst, whatever the scope
+    fn capacity(&self) -> u64 {
+        (self.per_second as u64).max(1)
+    }
+    fn bucket(&self) -> TokenBucket {
+        let mut b = TokenBucket::with_tokens(self.per_second / 1000.0, self.capacity());
+        b.set_capacity(self.capacity());
+        b
+    }
+    fn keyed<K: Eq + std::hash::Hash + Clone>(&self) -> KeyedRateLimiter<K> {
+        KeyedRateLimiter::new(self.per_second / 1000.0, self.capacity(), RATE_LIMIT_TTL)
+    }
+}
+
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// rejected tls connections finishing their handshake to get the busy notice, more are
+// closed right away so rejecting can not cost more than the limits save
+const MAX_TLS_REJECTS: usize = 32;
+
+// per address and per dn buckets unused this long are dropped
+const RATE_LIMIT_TTL: Duration = Duration::from_secs(60);
+
+// operations of a connection that are still running, by message id. the flag is set by
+// whoever sends the final response, the operation itself or a cancel, so only one does
+type InFlight = HashMap<u32, (Operation, AbortHandle, Arc<AtomicBool>)>;
+
+// stops the operation a cancel request names, returns the cancel result and the
+// response the canceled operation gets instead of its own
+fn cancel(in_flight: &mut InFlight, value: Option<&[u8]>) -> (u32, Option<Result<Vec<u8>>>) {
+    let cancel_id = match value.map(codec::ldap_read_cancel_id) {
+        Some(Ok(id)) => id,
+        _ => return (ldap::RESULT_PROTOCOL_ERROR, None),
+    };
+    match in_flight.get(&cancel_id) {
+        None => (ldap::RESULT_NO_SUCH_OPERATION, None),
+        Some((_, _, answered)) if answered.load(Ordering::SeqCst) => (ldap::RESULT_TOO_LATE, None),
+        Some((Operation::Bind, _, _)) => (ldap::RESULT_CANNOT_CANCEL, None),
+        // the operation may have claimed its response since the check above
+        Some((_, _, answered)) if answered.swap(true, Ordering::SeqCst) => {
+            (ldap::RESULT_TOO_LATE, None)
+        }
+        Some((op, h, _)) => {
+            h.abort();
+            let resp = error_response(cancel_id, *op, ldap::RESULT_CANCELED, "canceled");
+            in_flight.remove(&cancel_id);
+            (ldap::RESULT_SUCCESS, Some(resp))
+        }
+    }
+}
+
+// the writer task stopped, the client no longer reads or the write timed out
+fn writer_closed() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "writer closed")
+}
+
+// malformed or unexpected pdus, as opposed to the connection going away
+fn is_protocol_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::InvalidInput
+            | std::io::ErrorKind::InvalidData
+            | std::io::ErrorKind::UnexpectedEof
+    )
+}
+
+fn notice_of_disconnection(res: u32, diag: &str) -> Result<Vec<u8>> {
+    codec::ldap_write_extended_response(
+        0,
+        &MsgExtendedResponse {
+            result: MsgResult {
+                res,
+                matched_dn: String::new(),
+                diag: diag.to_owned(),
+            },
+            name: Some(ldap::NOTICE_OF_DISCONNECTION.to_owned()),
+            value: None,
+        },
+    )
 }
 
 pub type BoxFuture2<T> = Pin<Box<dyn Future<Output = T> + Send + Sync>>;
 
+// an address to accept connections on, tls is negotiated right after connecting when set
+struct Listener {
+    address: String,
+    tls: Option<tokio_rustls::TlsAcceptor>,
+}
+
+// listening sockets returned by LdapServer::bind
+pub struct BoundListeners {
+    listeners: Vec<(TcpListener, Option<tokio_rustls::TlsAcceptor>)>,
+}
+
+impl BoundListeners {
+    pub fn local_addrs(&self) -> Result<Vec<std::net::SocketAddr>> {
+        self.listeners.iter().map(|(l, _)| l.local_addr()).collect()
+    }
+}
+
 pub struct LdapServer {
-    listen_address: String,
+    listeners: Vec<Listener>,
+    subschema: Option<Subschema>,
+    shutdown: Arc<watch::Sender<bool>>,
+    // how long in-flight operations may take once shutdown starts
+    shutdown_timeout: Duration,
+    max_message_size: usize,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    // closes connections without requests or running operations
+    idle_timeout: Option<Duration>,
+    // how long after connecting a client has to send its first bind
+    bind_timeout: Option<Duration>,
+    // gives up on clients that do not read their responses
+    write_timeout: Option<Duration>,
+    // with the buckets of the per ip and per bind dn limits
+    rate_limits: Vec<(RateLimit, KeyedRateLimiter<String>)>,
+    // new connections per second from one address
+    connection_rate: Option<KeyedRateLimiter<IpAddr>>,
+    // checked against the bound dn before requests reach the service
+    access_control: Option<Arc<AccessControl>>,
+}
+
+// stops a running server, cloneable so it can be moved into a signal handler
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+}
+
+// tells a connection over the limits that the server is busy and closes it
+async fn reject<S: tokio::io::AsyncWrite + Unpin>(
+    mut socket: S,
+    reason: &str,
+    write_timeout: Option<Duration>,
+) {
+    let notice = match notice_of_disconnection(ldap::RESULT_BUSY, reason) {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+    let t = write_timeout.unwrap_or(Duration::from_secs(1));
+    let _ = tokio::time::timeout(t, async {
+        socket.write_all(&notice).await?;
+        socket.shutdown().await
+    })
+    .await;
+}
+
+fn attribute(name: &str, values: Vec<String>) -> PartialAttribute {
+    PartialAttribute {
+        name: name.to_owned(),
+        values,
+    }
 }
 
 impl LdapServer {
+    fn root_dse(&self, svc: &impl Service) -> Vec<PartialAttribute> {
+        let mut attrs = vec![
+            attribute("objectClass", vec!["top".to_owned()]),
+            attribute("namingContexts", svc.naming_contexts()),
+            attribute("supportedLDAPVersion", vec!["3".to_owned()]),
+            attribute("supportedControl", svc.supported_controls()),
+            attribute(
+                "supportedExtension",
+                [ldap::CANCEL_REQUEST, ldap::WHO_AM_I_REQUEST]
+                    .into_iter()
+                    .map(|e| e.to_owned())
+                    .chain(svc.supported_extensions())
+                    .collect(),
+            ),
+            attribute("supportedSASLMechanisms", svc.supported_sasl_mechanisms()),
+        ];
+        if let Some(subschema) = &self.subschema {
+            attrs.push(attribute(
+                "subschemaSubentry",
+                vec![subschema.dn.to_string()],
+            ));
+        }
+        attrs.retain(|a| !a.values.is_empty());
+        attrs
+    }
+
+    // answers base searches for the root dse and the subschema subentry without the service
+    fn builtin_search(
+        &self,
+        id: u32,
+        req: &MsgSearch,
+        svc: &impl Service,
+    ) -> Option<Result<Vec<u8>>> {
+        if req.scope != ldap::SearchScope::BaseObject {
+            return None;
+        }
+        if !matches!(&req.filter, Filter::Present(p) if p.name.eq_ignore_ascii_case("objectClass"))
+        {
+            return None;
+        }
+        let base = req.base_dn().ok()?;
+        let (dn, attrs) = if base.is_root() {
+            (
+                String::new(),
+                req.select(&self.root_dse(svc), ROOT_DSE_OPERATIONAL),
+            )
+        } else {
+            let subschema = self.subschema.as_ref()?;
+            if subschema.dn != base {
+                return None;
+            }
+            (
+                subschema.dn.to_string(),
+                req.select(&subschema.attributes, SUBSCHEMA_OPERATIONAL),
+            )
+        };
+        let r = codec::ldap_write_search_res_entry(id, &dn, &attrs).and_then(|mut out| {
+            out.append(&mut codec::ldap_write_search_res_done(
+                id,
+                ldap::RESULT_SUCCESS,
+            )?);
+            Ok(out)
+        });
+        Some(r)
+    }
+
     async fn ldap_reader<
         R: tokio::io::AsyncReadExt + Unpin,
         W: tokio::io::AsyncWriteExt + Unpin + Send + 'static,
@@ -21,31 +446,262 @@ impl LdapServer {
         self: &std::sync::Arc<Self>,
         socket: &mut R,
         mut writer: W,
+        remote_ip: IpAddr,
         s: Arc<impl Service + std::marker::Send + std::marker::Sync + 'static>,
     ) -> Result<()> {
-        let mut dec = tokiou::DecodeContext::new();
+        let mut dec = tokiou::DecodeContext::with_max_size(self.max_message_size);
+        let mut shutdown = self.shutdown.subscribe();
+        let mut ops = JoinSet::new();
+        let mut in_flight = InFlight::new();
+        let remote_ip = remote_ip.to_string();
+        let mut connection_buckets = HashMap::new();
+        // set once a bind succeeds, keys the per bind dn rate limits
+        let bind_dn = Arc::new(Mutex::new(String::new()));
 
         let (writer_tx, mut writer_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(1024);
-        tokio::spawn(async move {
+        let write_timeout = self.write_timeout;
+        let writer_task = tokio::spawn(async move {
             while let Some(i) = writer_rx.recv().await {
-                tokio::io::AsyncWriteExt::write_all(&mut writer, i.as_ref())
-                    .await
-                    .unwrap();
+                let write = writer.write_all(i.as_ref());
+                let res = match write_timeout {
+                    Some(t) => tokio::time::timeout(t, write).await.unwrap_or_else(|_| {
+                        Err(std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            "write timed out",
+                        ))
+                    }),
+                    None => write.await,
+                };
+                if let Err(e) = res {
+                    println!("write failed {:?}", e);
+                    return;
+                }
             }
+            let _ = writer.shutdown().await;
         });
-        loop {
-            let parsed = dec.get_message(socket).await?;
+        let mut bind_deadline = self.bind_timeout.map(|t| Instant::now() + t);
+        let mut last_activity = Instant::now();
+        let res = loop {
+            in_flight.retain(|_, (_, h, _)| !h.is_finished());
+            let idle_deadline = match self.idle_timeout {
+                Some(t) if in_flight.is_empty() => Some(last_activity + t),
+                _ => None,
+            };
+            let deadline = match (bind_deadline, idle_deadline) {
+                (Some(b), Some(i)) if i < b => Some((i, "idle timeout")),
+                (Some(b), _) => Some((b, "bind timeout")),
+                (None, Some(i)) => Some((i, "idle timeout")),
+                (None, None) => None,
+            };
+            let mut parsed = tokio::select! {
+                r = dec.get_message(socket) => match r {
+                    Ok(m) => m,
+                    Err(e) => break Err(e),
+                },
+                _ = shutdown.wait_for(|s| *s) => break Ok(Closed::Shutdown),
+                _ = writer_tx.closed() => break Err(writer_closed()),
+                // wakes up to restart the idle timer once the last operation is done
+                Some(_) = ops.join_next(), if !ops.is_empty() => {
+                    last_activity = Instant::now();
+                    continue;
+                }
+                _ = tokio::time::sleep_until(deadline.map_or_else(Instant::now, |d| d.0)),
+                    if deadline.is_some() => {
+                    break Ok(Closed::Timeout(deadline.map_or("", |d| d.1)));
+                }
+            };
+            last_activity = Instant::now();
+            let id = parsed.id;
+            let op = Operation::of(&parsed.params);
+            if op == Operation::Bind {
+                bind_deadline = None;
+            }
+            if op == Operation::Other {
+                break Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "unexpected message from client",
+                ));
+            }
+            if op == Operation::Unbind {
+                s.unbind(&bind_dn.lock().unwrap());
+                ops.abort_all();
+                break Ok(Closed::Unbind);
+            }
+            if let MessageParams::Abandon(a) = &parsed.params {
+                if let Some((_, h, answered)) = in_flight.remove(&a.id) {
+                    answered.store(true, Ordering::SeqCst);
+                    h.abort();
+                }
+                continue;
+            }
+            if let MessageParams::ExtendedRequest(r) = &parsed.params {
+                if r.name == ldap::CANCEL_REQUEST {
+                    let (code, canceled) = cancel(&mut in_flight, r.value.as_deref());
+                    let mut resp = match canceled {
+                        Some(resp) => resp?,
+                        None => Vec::new(),
+                    };
+                    resp.append(&mut error_response(id, op, code, "")?);
+                    if writer_tx.send(resp).await.is_err() {
+                        break Err(writer_closed());
+                    }
+                    continue;
+                }
+                if r.name == ldap::WHO_AM_I_REQUEST {
+                    let bound = bind_dn.lock().unwrap().clone();
+                    let resp = codec::ldap_write_extended_response(
+                        id,
+                        &MsgExtendedResponse {
+                            result: MsgResult {
+                                res: ldap::RESULT_SUCCESS,
+                                matched_dn: String::new(),
+                                diag: String::new(),
+                            },
+                            name: None,
+                            // empty for anonymous
+                            value: Some(match bound.is_empty() {
+                                true => Vec::new(),
+                                false => format!("dn:{}", bound).into_bytes(),
+                            }),
+                        },
+                    )?;
+                    if writer_tx.send(resp).await.is_err() {
+                        break Err(writer_closed());
+                    }
+                    continue;
+                }
+            }
+            let identity = match &parsed.params {
+                MessageParams::Bind(b) => b.name.to_lowercase(),
+                _ => bind_dn.lock().unwrap().clone(),
+            };
+            let allowed = tokio::select! {
+                a = self.throttle(&mut connection_buckets, &remote_ip, &identity, op) => a,
+                _ = shutdown.wait_for(|s| *s) => break Ok(Closed::Shutdown),
+            };
+            if !allowed {
+                let resp = error_response(id, op, ldap::RESULT_BUSY, "rate limit exceeded")?;
+                if writer_tx.send(resp).await.is_err() {
+                    break Err(writer_closed());
+                }
+                continue;
+            }
+            if let MessageParams::Search(req) = &parsed.params {
+                if let Some(resp) = self.builtin_search(id, req, s.as_ref()) {
+                    let resp = resp
+                        .or_else(|e| error_response(id, op, ldap::RESULT_OTHER, &e.to_string()))?;
+                    if writer_tx.send(resp).await.is_err() {
+                        break Err(writer_closed());
+                    }
+                    continue;
+                }
+            }
+            if let MessageParams::ExtendedRequest(r) = &mut parsed.params {
+                fill_password_modify_user(r, &bind_dn.lock().unwrap());
+            }
+            let bound = Dn::parse(&bind_dn.lock().unwrap()).unwrap_or_default();
+            if let Some(ac) = &self.access_control {
+                if !ac.permits(&bound, &parsed.params) {
+                    // a bind refused by the rules looks like a wrong password and leaves the
+                    // connection anonymous, like any failed bind
+                    let code = match op {
+                        Operation::Bind => {
+                            bind_dn.lock().unwrap().clear();
+                            ldap::RESULT_INVALID_CREDENTIALS
+                        }
+                        _ => ldap::RESULT_INSUFFICIENT_ACCESS_RIGHTS,
+                    };
+                    let resp = error_response(id, op, code, "")?;
+                    if writer_tx.send(resp).await.is_err() {
+                        break Err(writer_closed());
+                    }
+                    continue;
+                }
+            }
+            // search results only list the entries and attributes the bound dn may read
+            let visible = match (&parsed.params, &self.access_control) {
+                (MessageParams::Search(req), Some(ac)) => {
+                    Some((ac.clone(), bound, req.filter.clone()))
+                }
+                _ => None,
+            };
+            // remember who the connection is bound as when the bind succeeds
+            let binding = match op {
+                Operation::Bind => Some((identity, bind_dn.clone())),
+                _ => None,
+            };
             let f = s.call(parsed);
             let wtx = writer_tx.clone();
-            tokio::spawn(async move {
-                let resp = f.await;
-                if let Ok(resp) = resp {
-                    if !resp.is_empty() {
-                        wtx.send(resp).await.unwrap();
+            let answered = Arc::new(AtomicBool::new(false));
+            let claim = answered.clone();
+            let h = ops.spawn(async move {
+                let resp = match f.await {
+                    Ok(resp) => resp,
+                    Err(e) => {
+                        println!("operation {} failed {:?}", id, e);
+                        match error_response(id, op, ldap::RESULT_OTHER, &e.to_string()) {
+                            Ok(resp) => resp,
+                            Err(_) => return,
+                        }
+                    }
+                };
+                let resp = match visible {
+                    Some((ac, bound, filter)) => match ac.filter_entries(&bound, &filter, resp) {
+                        Ok(resp) => resp,
+                        Err(e) => {
+                            match error_response(id, op, ldap::RESULT_OTHER, &e.to_string()) {
+                                Ok(resp) => resp,
+                                Err(_) => return,
+                            }
+                        }
+                    },
+                    None => resp,
+                };
+                if let Some((name, bind_dn)) = binding {
+                    if let Ok((m, _)) = ldap::Message::decode(&resp) {
+                        if let MessageParams::BindResponse(r) = m.params {
+                            *bind_dn.lock().unwrap() = if r.res == ldap::RESULT_SUCCESS {
+                                name
+                            } else {
+                                String::new()
+                            };
+                        }
                     }
                 }
+                // canceled or abandoned after the service answered
+                if claim.swap(true, Ordering::SeqCst) {
+                    return;
+                }
+                if !resp.is_empty() && wtx.send(resp).await.is_err() {
+                    println!("connection closed before response to {}", id);
+                }
             });
+            in_flight.insert(id, (op, h, answered));
+        };
+        let notice = match &res {
+            Ok(Closed::Shutdown) => {
+                Some((ldap::RESULT_UNAVAILABLE, "server shutting down".to_owned()))
+            }
+            Ok(Closed::Unbind) => None,
+            Ok(Closed::Timeout(reason)) => Some((ldap::RESULT_UNAVAILABLE, (*reason).to_owned())),
+            Err(e) if is_protocol_error(e) => Some((ldap::RESULT_PROTOCOL_ERROR, e.to_string())),
+            Err(_) => None,
+        };
+        if let Some((code, diag)) = notice {
+            let _ = writer_tx.send(notice_of_disconnection(code, &diag)?).await;
+        }
+        // the notice goes out first so the client gets it even if start_server gives up on
+        // this connection, in-flight operations may still answer until the shutdown timeout
+        if matches!(res, Ok(Closed::Shutdown)) {
+            let _ = tokio::time::timeout(self.shutdown_timeout, async {
+                while ops.join_next().await.is_some() {}
+            })
+            .await;
+            ops.abort_all();
         }
+        drop(writer_tx);
+        let _ = writer_task.await;
+        res.map(|_| ())
     }
 
     pub async fn start_server<S: Service + std::marker::Send + std::marker::Sync + 'static>(
@@ -56,22 +712,767 @@ impl LdapServer {
         <S as Service>::Future: std::marker::Sync,
         <S as Service>::Future: std::marker::Send,
     {
-        println!("ldap will listen on {:?}", self.listen_address);
-        let listener = TcpListener::bind(&self.listen_address).await?;
+        let bound = self.bind().await?;
+        self.serve(bound, svc).await
+    }
+
+    // binds all listeners without accepting yet, so the addresses of port 0 listeners are known
+    pub async fn bind(&self) -> Result<BoundListeners> {
+        let mut listeners = Vec::new();
+        for l in &self.listeners {
+            println!("ldap will listen on {:?}", l.address);
+            listeners.push((TcpListener::bind(&l.address).await?, l.tls.clone()));
+        }
+        Ok(BoundListeners { listeners })
+    }
+
+    // accepts connections on listeners from bind until shutdown
+    pub async fn serve<S: Service + std::marker::Send + std::marker::Sync + 'static>(
+        self: &std::sync::Arc<Self>,
+        bound: BoundListeners,
+        svc: Arc<S>,
+    ) -> Result<()>
+    where
+        <S as Service>::Future: std::marker::Sync,
+        <S as Service>::Future: std::marker::Send,
+    {
+        let listeners = bound.listeners;
+        let mut shutdown = self.shutdown.subscribe();
+        let mut connections = JoinSet::new();
+        let per_ip = Arc::new(Mutex::new(HashMap::new()));
+        let tls_rejects = Arc::new(tokio::sync::Semaphore::new(MAX_TLS_REJECTS));
         loop {
-            let (socket, remote_addr) = listener.accept().await?;
+            let accept = futures::future::select_all(
+                listeners
+                    .iter()
+                    .map(|(l, tls)| Box::pin(async move { (l.accept().await, tls) })),
+            );
+            let (accepted, tls) = tokio::select! {
+                ((r, tls), _, _) = accept => (r, tls.clone()),
+                _ = shutdown.wait_for(|s| *s) => break,
+            };
+            let (socket, remote_addr) = match accepted {
+                Ok(a) => a,
+                Err(e) => {
+                    // e.g. out of file descriptors, back off instead of spinning
+                    println!("accept failed {:?}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            while connections.try_join_next().is_some() {}
+            let ip = remote_addr.ip();
+            let rejected = if self
+                .max_connections
+                .is_some_and(|max| connections.len() >= max)
+            {
+                Some("too many connections")
+            } else if self
+                .connection_rate
+                .as_ref()
+                .is_some_and(|r| !r.try_acquire(&ip, 1))
+            {
+                Some("connecting too fast")
+            } else {
+                let mut per_ip = per_ip.lock().unwrap();
+                let n = per_ip.entry(ip).or_insert(0);
+                if self.max_connections_per_ip.is_some_and(|max| *n >= max) {
+                    Some("too many connections from this address")
+                } else {
+                    *n += 1;
+                    None
+                }
+            };
+            if let Some(reason) = rejected {
+                println!("rejected connection from {:?}: {}", remote_addr, reason);
+                let write_timeout = self.write_timeout;
+                match tls {
+                    None => {
+                        tokio::spawn(reject(socket, reason, write_timeout));
+                    }
+                    // a tls client can only read the notice once the handshake is done
+                    Some(acceptor) => {
+                        let permit = match tls_rejects.clone().try_acquire_owned() {
+                            Ok(p) => p,
+                            Err(_) => continue,
+                        };
+                        tokio::spawn(async move {
+                            let _permit = permit;
+                            let accept = acceptor.accept(socket);
+                            if let Ok(Ok(stream)) =
+                                tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, accept).await
+                            {
+                                reject(stream, reason, write_timeout).await;
+                            }
+                        });
+                    }
+                }
+                continue;
+            }
+            let slot = IpSlot {
+                ip,
+                per_ip: per_ip.clone(),
+            };
             let s = self.clone();
             let svc1 = svc.clone();
-            tokio::spawn(async move {
+            connections.spawn(async move {
+                let _slot = slot;
                 println!("incoming connection from: {:?}", remote_addr);
-                let (mut r, w) = socket.into_split();
-                let res = s.ldap_reader(&mut r, w, svc1).await;
+                let res = match tls {
+                    None => {
+                        let (mut r, w) = socket.into_split();
+                        s.ldap_reader(&mut r, w, ip, svc1).await
+                    }
+                    Some(acceptor) => {
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket))
+                            .await
+                        {
+                            Ok(Ok(stream)) => {
+                                let (mut r, w) = tokio::io::split(stream);
+                                s.ldap_reader(&mut r, w, ip, svc1).await
+                            }
+                            Ok(Err(e)) => Err(e),
+                            Err(_) => Err(std::io::Error::new(
+                                std::io::ErrorKind::TimedOut,
+                                "tls handshake timed out",
+                            )),
+                        }
+                    }
+                };
                 println!("reader done {:?}", res);
             });
         }
+        drop(listeners);
+        println!("ldap shutting down, {} connections open", connections.len());
+        let drained = tokio::time::timeout(self.shutdown_timeout, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            println!(
+                "shutdown timeout, closing {} connections",
+                connections.len()
+            );
+            connections.shutdown().await;
+        }
+        Ok(())
     }
 
     pub fn new(listen_address: String) -> Self {
-        Self { listen_address }
+        Self {
+            listeners: vec![Listener {
+                address: listen_address,
+                tls: None,
+            }],
+            subschema: None,
+            shutdown: Arc::new(watch::channel(false).0),
+            shutdown_timeout: Duration::from_secs(10),
+            max_message_size: tokiou::DEFAULT_MAX_MESSAGE_SIZE,
+            max_connections: None,
+            max_connections_per_ip: None,
+            idle_timeout: None,
+            bind_timeout: None,
+            write_timeout: None,
+            rate_limits: Vec::new(),
+            connection_rate: None,
+            access_control: None,
+        }
+    }
+
+    // listens on another address as well, e.g. ldaps next to plain ldap
+    pub fn add_listener(&mut self, address: String, tls: Option<tokio_rustls::TlsAcceptor>) {
+        self.listeners.push(Listener { address, tls });
+    }
+
+    // negotiates tls on the address given to new
+    pub fn set_tls(&mut self, tls: tokio_rustls::TlsAcceptor) {
+        self.listeners[0].tls = Some(tls);
+    }
+
+    pub fn set_access_control(&mut self, access_control: AccessControl) {
+        self.access_control = Some(Arc::new(access_control));
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            tx: self.shutdown.clone(),
+        }
+    }
+
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    // largest request pdu accepted from a client, larger ones close the connection
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    pub fn set_max_connections(&mut self, max: usize) {
+        self.max_connections = Some(max);
+    }
+
+    pub fn set_max_connections_per_ip(&mut self, max: usize) {
+        self.max_connections_per_ip = Some(max);
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = Some(timeout);
+    }
+
+    pub fn set_bind_timeout(&mut self, timeout: Duration) {
+        self.bind_timeout = Some(timeout);
+    }
+
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        self.write_timeout = Some(timeout);
+    }
+
+    pub fn add_rate_limit(&mut self, limit: RateLimit) {
+        let keyed = limit.keyed();
+        self.rate_limits.push((limit, keyed));
+    }
+
+    pub fn set_connection_rate_limit(&mut self, per_second: f64) {
+        let limit = RateLimit {
+            scope: RateLimitScope::Ip,
+            operations: Vec::new(),
+            per_second,
+            action: RateLimitAction::Busy,
+        };
+        self.connection_rate = Some(limit.keyed());
+    }
+
+    // takes a token from every limit that applies, false when a busy limit has none left
+    async fn throttle(
+        &self,
+        connection_buckets: &mut HashMap<usize, TokenBucket>,
+        ip: &str,
+        bind_dn: &str,
+        op: Operation,
+    ) -> bool {
+        for (i, (limit, keyed)) in self.rate_limits.iter().enumerate() {
+            if !limit.applies(op) {
+                continue;
+            }
+            let busy = limit.action == RateLimitAction::Busy;
+            match limit.scope {
+                RateLimitScope::Connection => {
+                    let b = connection_buckets
+                        .entry(i)
+                        .or_insert_with(|| limit.bucket());
+                    if busy {
+                        if !b.try_acquire(1) {
+                            return false;
+                        }
+                    } else if b.acquire(1).await.is_err() {
+                        return false;
+                    }
+                }
+                RateLimitScope::Ip | RateLimitScope::BindDn => {
+                    let key = if limit.scope == RateLimitScope::Ip {
+                        ip
+                    } else {
+                        bind_dn
+                    }
+                    .to_owned();
+                    if busy {
+                        if !keyed.try_acquire(&key, 1) {
+                            return false;
+                        }
+                    } else if keyed.acquire(&key, 1).await.is_err() {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    // publishes the subschema subentry at dn, objectClass and the rdn attribute are added here
+    pub fn set_subschema_subentry(
+        &mut self,
+        dn: &str,
+        attributes: Vec<PartialAttribute>,
+    ) -> Result<()> {
+        let dn = Dn::parse(dn)?;
+        let mut attrs = vec![attribute(
+            "objectClass",
+            vec![
+                "top".to_owned(),
+                "subentry".to_owned(),
+                "subschema".to_owned(),
+            ],
+        )];
+        if let Some(rdn) = dn.rdn() {
+            for ava in &rdn.avas {
+                attrs.push(attribute(&ava.attr, vec![ava.value.clone()]));
+            }
+        }
+        attrs.extend(attributes);
+        self.subschema = Some(Subschema {
+            dn,
+            attributes: attrs,
+        });
+        Ok(())
+    }
+}
+
+// starts server on its own port for a test and returns the address it listens on
+#[cfg(test)]
+pub(crate) async fn spawn_test_server<S: Service + Send + Sync + 'static>(
+    server: LdapServer,
+    svc: Arc<S>,
+) -> (String, tokio::task::JoinHandle<Result<()>>)
+where
+    <S as Service>::Future: Sync,
+    <S as Service>::Future: Send,
+{
+    let server = Arc::new(server);
+    let bound = server.bind().await.unwrap();
+    let addr = bound.local_addrs().unwrap()[0].to_string();
+    (
+        addr,
+        tokio::spawn(async move { server.serve(bound, svc).await }),
+    )
+}
+
+// a search for every entry with an object class
+#[cfg(test)]
+pub(crate) fn search_request(
+    id: u32,
+    base: &str,
+    scope: ldap::SearchScope,
+    attributes: &[&str],
+) -> ldap::Message {
+    ldap::Message {
+        id,
+        params: MessageParams::Search(MsgSearch {
+            base_object: base.to_owned(),
+            scope,
+            deref: ldap::DerefAliases::NeverDerefAliases,
+            filter: Filter::Present(ldap::FilterPresent {
+                name: "objectClass".to_owned(),
+            }),
+            size_limit: 0,
+            time_limit: 0,
+            types_only: false,
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+        }),
+        controls: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn root_dse_test() {
+    let dir = crate::directory::Directory::new();
+    dir.apply_ldif("dn: dc=example\ndc: example\n\ndn: ou=a,dc=example\nou: a\n")
+        .unwrap();
+    let mut server = LdapServer::new("127.0.0.1:0".to_owned());
+    server
+        .set_subschema_subentry(
+            "cn=Subschema",
+            vec![attribute(
+                "objectClasses",
+                vec!["( 2.5.6.0 NAME 'top' ABSTRACT MUST objectClass )".to_owned()],
+            )],
+        )
+        .unwrap();
+    let (addr, _) = spawn_test_server(server, Arc::new(dir)).await;
+
+    let c = crate::client::connect(&addr).await.unwrap();
+    let search = |base: &str, attributes: &[&str]| {
+        search_request(1, base, ldap::SearchScope::BaseObject, attributes)
+    };
+    let r = c.send_request_w(search("", &["+"])).await.unwrap();
+    assert_eq!(r.len(), 2);
+    if let MessageParams::SearchResult(e) = &r[0].params {
+        assert_eq!(e.name, "");
+        let nc = e
+            .values
+            .iter()
+            .find(|a| a.name == "namingContexts")
+            .unwrap();
+        assert_eq!(nc.values, vec!["dc=example"]);
+        assert!(e
+            .values
+            .iter()
+            .any(|a| a.name == "subschemaSubentry" && a.values[0] == "cn=Subschema"));
+        assert!(!e.values.iter().any(|a| a.name == "objectClass"));
+    } else {
+        unreachable!();
     }
+    let r = c
+        .send_request_w(search("CN=subschema", &["objectClasses"]))
+        .await
+        .unwrap();
+    if let MessageParams::SearchResult(e) = &r[0].params {
+        assert_eq!(e.values.len(), 1);
+        assert_eq!(e.values[0].name, "objectClasses");
+    } else {
+        unreachable!();
+    }
+}
+
+#[tokio::test]
+async fn shutdown_test() {
+    use tokio::io::AsyncWriteExt;
+
+    let server = LdapServer::new("127.0.0.1:0".to_owned());
+    let handle = server.shutdown_handle();
+    let dir = Arc::new(crate::directory::Directory::new());
+    let (addr, task) = spawn_test_server(server, dir).await;
+
+    let mut s = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    s.write_all(&codec::ldap_write_bind_request(1, "", "").unwrap())
+        .await
+        .unwrap();
+    let mut dec = tokiou::DecodeContext::new();
+    let m = dec.get_message(&mut s).await.unwrap();
+    assert!(matches!(m.params, MessageParams::BindResponse(_)));
+
+    handle.shutdown();
+    let m = dec.get_message(&mut s).await.unwrap();
+    assert_eq!(m.id, 0);
+    if let MessageParams::ExtendedResponse(r) = m.params {
+        assert_eq!(r.name.as_deref(), Some(ldap::NOTICE_OF_DISCONNECTION));
+        assert_eq!(r.result.res, ldap::RESULT_UNAVAILABLE);
+    } else {
+        unreachable!();
+    }
+    assert!(dec.get_message(&mut s).await.is_err());
+    task.await.unwrap().unwrap();
+    assert!(tokio::net::TcpStream::connect(&addr).await.is_err());
+}
+
+#[tokio::test]
+async fn misbehaving_client_test() {
+    use tokio::io::AsyncWriteExt;
+
+    struct Failing {}
+    impl Service for Failing {
+        type Future = BoxFuture2<Result<Vec<u8>>>;
+        fn call(&self, _req: ldap::Message) -> Self::Future {
+            Box::pin(async { Err(std::io::Error::other("backend down")) })
+        }
+    }
+    let (addr, _) = spawn_test_server(
+        LdapServer::new("127.0.0.1:0".to_owned()),
+        Arc::new(Failing {}),
+    )
+    .await;
+    let bind = codec::ldap_write_bind_request(1, "", "").unwrap();
+
+    // a client that goes away before its response is written
+    for _ in 0..10 {
+        let mut s = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        s.write_all(&bind).await.unwrap();
+    }
+
+    // handler errors turn into an error result
+    let mut s = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    s.write_all(&bind).await.unwrap();
+    let mut dec = tokiou::DecodeContext::new();
+    let m = dec.get_message(&mut s).await.unwrap();
+    assert!(matches!(m.params, MessageParams::BindResponse(r) if r.res == ldap::RESULT_OTHER));
+
+    // garbage closes just this connection with a protocol error notice
+    s.write_all(&[0x30, 0x05, 0x02, 0x01, 0x02, 0x7f, 0x00])
+        .await
+        .unwrap();
+    let m = dec.get_message(&mut s).await.unwrap();
+    if let MessageParams::ExtendedResponse(r) = m.params {
+        assert_eq!(r.name.as_deref(), Some(ldap::NOTICE_OF_DISCONNECTION));
+        assert_eq!(r.result.res, ldap::RESULT_PROTOCOL_ERROR);
+    } else {
+        unreachable!();
+    }
+    assert!(dec.get_message(&mut s).await.is_err());
+
+    let c = crate::client::connect(&addr).await.unwrap();
+    assert_eq!(
+        c.send_request_bind("", "").await.unwrap().res,
+        ldap::RESULT_OTHER
+    );
+}
+
+#[tokio::test]
+async fn unbind_test() {
+    use tokio::io::AsyncReadExt;
+
+    struct Slow {
+        unbinds: Mutex<Vec<String>>,
+    }
+    impl Service for Slow {
+        type Future = BoxFuture2<Result<Vec<u8>>>;
+        fn call(&self, req: ldap::Message) -> Self::Future {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                codec::ldap_write_search_res_done(req.id, ldap::RESULT_SUCCESS)
+            })
+        }
+        fn unbind(&self, bound_dn: &str) {
+            self.unbinds.lock().unwrap().push(bound_dn.to_owned());
+        }
+    }
+    let svc = Arc::new(Slow {
+        unbinds: Mutex::new(Vec::new()),
+    });
+    let (addr, _) = spawn_test_server(LdapServer::new("127.0.0.1:0".to_owned()), svc.clone()).await;
+
+    let mut s = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut data = search_request(1, "dc=example", ldap::SearchScope::WholeSubtree, &[])
+        .encode()
+        .unwrap();
+    data.extend(codec::ldap_write_unbind_request(2).unwrap());
+    tokio::io::AsyncWriteExt::write_all(&mut s, &data)
+        .await
+        .unwrap();
+    // the pending search is abandoned and the server closes without a notice
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), s.read_to_end(&mut rest))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(*svc.unbinds.lock().unwrap(), vec![String::new()]);
+}
+
+#[tokio::test]
+async fn abandon_test() {
+    struct Slow {}
+    impl Service for Slow {
+        type Future = BoxFuture2<Result<Vec<u8>>>;
+        fn call(&self, req: ldap::Message) -> Self::Future {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                codec::ldap_write_search_res_done(req.id, ldap::RESULT_SUCCESS)
+            })
+        }
+    }
+    let (addr, _) =
+        spawn_test_server(LdapServer::new("127.0.0.1:0".to_owned()), Arc::new(Slow {})).await;
+
+    let search = |id: u32| search_request(id, "dc=example", ldap::SearchScope::WholeSubtree, &[]);
+    let mut s = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut data = search(1).encode().unwrap();
+    data.extend(search(2).encode().unwrap());
+    data.extend(codec::ldap_write_abandon_request(3, &ldap::MsgAbandon { id: 1 }).unwrap());
+    data.extend(codec::ldap_write_cancel_request(4, 2).unwrap());
+    data.extend(codec::ldap_write_cancel_request(5, 9).unwrap());
+    data.extend(codec::ldap_write_unbind_request(6).unwrap());
+    tokio::io::AsyncWriteExt::write_all(&mut s, &data)
+        .await
+        .unwrap();
+
+    let mut dec = tokiou::DecodeContext::new();
+    let mut got = Vec::new();
+    while let Ok(m) = tokio::time::timeout(Duration::from_secs(5), dec.get_message(&mut s))
+        .await
+        .unwrap()
+    {
+        got.push(m);
+    }
+    // nothing for the abandoned search, the canceled one ends with canceled
+    assert_eq!(got.len(), 3);
+    assert_eq!(got[0].id, 2);
+    assert!(
+        matches!(&got[0].params, MessageParams::MsgSearchResultDone(r) if r.res == ldap::RESULT_CANCELED)
+    );
+    assert_eq!(got[1].id, 4);
+    assert!(
+        matches!(&got[1].params, MessageParams::ExtendedResponse(r) if r.result.res == ldap::RESULT_SUCCESS)
+    );
+    assert_eq!(got[2].id, 5);
+    assert!(
+        matches!(&got[2].params, MessageParams::ExtendedResponse(r) if r.result.res == ldap::RESULT_NO_SUCH_OPERATION)
+    );
+}
+
+#[tokio::test]
+async fn connection_limits_test() {
+    async fn notice(s: &mut tokio::net::TcpStream) -> (u32, String) {
+        let mut dec = tokiou::DecodeContext::new();
+        let m = tokio::time::timeout(Duration::from_secs(5), dec.get_message(s))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(dec.get_message(s).await.is_err());
+        match m.params {
+            MessageParams::ExtendedResponse(r) => (r.result.res, r.result.diag),
+            _ => unreachable!(),
+        }
+    }
+
+    let mut server = LdapServer::new("127.0.0.1:0".to_owned());
+    server.set_max_connections_per_ip(1);
+    server.set_bind_timeout(Duration::from_millis(300));
+    let (addr, _) = spawn_test_server(server, Arc::new(crate::directory::Directory::new())).await;
+    let mut first = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let mut second = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    assert_eq!(notice(&mut second).await.0, ldap::RESULT_BUSY);
+    // never binds
+    assert_eq!(
+        notice(&mut first).await,
+        (ldap::RESULT_UNAVAILABLE, "bind timeout".to_owned())
+    );
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let c = crate::client::connect(&addr).await.unwrap();
+    c.send_request_bind("", "").await.unwrap();
+    c.close().await.unwrap();
+
+    let mut server = LdapServer::new("127.0.0.1:0".to_owned());
+    server.set_max_connections(1);
+    server.set_idle_timeout(Duration::from_millis(300));
+    let (addr, _) = spawn_test_server(server, Arc::new(crate::directory::Directory::new())).await;
+    let mut first = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    tokio::io::AsyncWriteExt::write_all(
+        &mut first,
+        &codec::ldap_write_bind_request(1, "", "").unwrap(),
+    )
+    .await
+    .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let mut second = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    assert_eq!(notice(&mut second).await.0, ldap::RESULT_BUSY);
+    let mut dec = tokiou::DecodeContext::new();
+    let m = dec.get_message(&mut first).await.unwrap();
+    assert!(matches!(m.params, MessageParams::BindResponse(_)));
+    let m = tokio::time::timeout(Duration::from_secs(5), dec.get_message(&mut first))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        matches!(m.params, MessageParams::ExtendedResponse(r) if r.result.diag == "idle timeout")
+    );
+}
+
+#[tokio::test]
+async fn rate_limit_test() {
+    let mut server = LdapServer::new("127.0.0.1:0".to_owned());
+    server.add_rate_limit(RateLimit {
+        scope: RateLimitScope::Connection,
+        operations: vec![Operation::Search],
+        per_second: 1.0,
+        action: RateLimitAction::Busy,
+    });
+    server.add_rate_limit(RateLimit {
+        scope: RateLimitScope::BindDn,
+        operations: vec![Operation::Bind],
+        per_second: 1.0,
+        action: RateLimitAction::Busy,
+    });
+    let (addr, _) = spawn_test_server(server, Arc::new(crate::directory::Directory::new())).await;
+
+    let search = |id: u32| search_request(id, "", ldap::SearchScope::BaseObject, &[]);
+    let c = crate::client::connect(&addr).await.unwrap();
+    let r = c.send_request_w(search(1)).await.unwrap();
+    assert!(
+        matches!(&r.last().unwrap().params, MessageParams::MsgSearchResultDone(d) if d.res == ldap::RESULT_SUCCESS)
+    );
+    let r = c.send_request_w(search(2)).await.unwrap();
+    assert!(
+        matches!(&r[0].params, MessageParams::MsgSearchResultDone(d) if d.res == ldap::RESULT_BUSY)
+    );
+    // another connection has its own bucket
+    let c2 = crate::client::connect(&addr).await.unwrap();
+    let r = c2.send_request_w(search(1)).await.unwrap();
+    assert_eq!(r.len(), 2);
+
+    // binds as the same dn share a bucket across connections
+    assert_ne!(
+        c.send_request_bind("cn=a", "x").await.unwrap().res,
+        ldap::RESULT_BUSY
+    );
+    assert_eq!(
+        c2.send_request_bind("CN=A", "x").await.unwrap().res,
+        ldap::RESULT_BUSY
+    );
+    assert_ne!(
+        c2.send_request_bind("cn=b", "x").await.unwrap().res,
+        ldap::RESULT_BUSY
+    );
+
+    // a client paced at 20 requests per second
+    let mut c3 = crate::client::connect(&addr).await.unwrap();
+    c3.set_rate_limiter(crate::tokenbucket::SharedTokenBucket::new(
+        TokenBucket::new(0.02),
+    ));
+    let start = std::time::Instant::now();
+    for i in 0..5 {
+        c3.send_request_bind(&format!("cn={}", i), "x")
+            .await
+            .unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // connections per second from one address
+    let mut server = LdapServer::new("127.0.0.1:0".to_owned());
+    server.set_connection_rate_limit(1.0);
+    let (addr, _) = spawn_test_server(server, Arc::new(crate::directory::Directory::new())).await;
+    let c = crate::client::connect(&addr).await.unwrap();
+    c.send_request_bind("", "").await.unwrap();
+    let mut s = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let m = tokiou::DecodeContext::new()
+        .get_message(&mut s)
+        .await
+        .unwrap();
+    assert!(
+        matches!(m.params, MessageParams::ExtendedResponse(r) if r.result.res == ldap::RESULT_BUSY)
+    );
+}
+
+#[tokio::test]
+async fn rate_limit_delay_test() {
+    let mut server = LdapServer::new("127.0.0.1:0".to_owned());
+    server.add_rate_limit(RateLimit {
+        scope: RateLimitScope::Connection,
+        operations: vec![Operation::Search],
+        per_second: 2.0,
+        action: RateLimitAction::Busy,
+    });
+    server.add_rate_limit(RateLimit {
+        scope: RateLimitScope::Connection,
+        operations: vec![Operation::Bind],
+        per_second: 0.001,
+        action: RateLimitAction::Delay,
+    });
+    let handle = server.shutdown_handle();
+    let (addr, task) =
+        spawn_test_server(server, Arc::new(crate::directory::Directory::new())).await;
+
+    // a connection gets the same burst as the per ip and per bind dn buckets
+    let c = crate::client::connect(&addr).await.unwrap();
+    let search = |id: u32| search_request(id, "", ldap::SearchScope::BaseObject, &[]);
+    for id in 1..3 {
+        let r = c.send_request_w(search(id)).await.unwrap();
+        assert_eq!(r.len(), 2);
+    }
+    let r = c.send_request_w(search(3)).await.unwrap();
+    assert!(
+        matches!(&r[0].params, MessageParams::MsgSearchResultDone(d) if d.res == ldap::RESULT_BUSY)
+    );
+
+    // a delayed request does not hold up shutdown
+    let mut s = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut data = codec::ldap_write_bind_request(1, "", "").unwrap();
+    data.extend(codec::ldap_write_bind_request(2, "", "").unwrap());
+    s.write_all(&data).await.unwrap();
+    let mut dec = tokiou::DecodeContext::new();
+    let m = dec.get_message(&mut s).await.unwrap();
+    assert!(matches!(m.params, MessageParams::BindResponse(_)));
+    handle.shutdown();
+    let m = tokio::time::timeout(Duration::from_secs(5), dec.get_message(&mut s))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        matches!(m.params, MessageParams::ExtendedResponse(r) if r.result.res == ldap::RESULT_UNAVAILABLE)
+    );
+    tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
 }
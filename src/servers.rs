@@ -0,0 +1,200 @@
+use crate::client::{self, ClientConnection};
+use crate::tokiou;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Result;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_rustls::rustls::ClientConfig;
+
+// good enough for picking servers and jittering retries
+pub(crate) fn random() -> u64 {
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    // always the first server that is up
+    Failover,
+    // each connect starts at the server after the one the previous connect started at
+    RoundRobin,
+    Random,
+}
+
+struct State {
+    next: usize,
+    // servers that failed to connect are skipped until then
+    down_until: Vec<Option<Instant>>,
+}
+
+// the servers of one directory, connect picks one by strategy and falls over to the others.
+// a server that fails is marked down and only tried again, as a probe, once retry_after passed
+// or when no other server is left.
+pub struct ServerList {
+    urls: Vec<String>,
+    strategy: Strategy,
+    retry_after: Duration,
+    max_message_size: usize,
+    // the webpki roots when unset
+    tls_config: Option<Arc<ClientConfig>>,
+    state: Mutex<State>,
+}
+
+impl ServerList {
+    pub fn new(urls: Vec<String>, strategy: Strategy) -> Self {
+        let n = urls.len();
+        Self {
+            urls,
+            strategy,
+            retry_after: Duration::from_secs(60),
+            max_message_size: tokiou::DEFAULT_MAX_MESSAGE_SIZE,
+            tls_config: None,
+            state: Mutex::new(State {
+                next: 0,
+                down_until: vec![None; n],
+            }),
+        }
+    }
+
+    // urls separated by whitespace, like the -H option of the openldap tools
+    pub fn parse(s: &str, strategy: Strategy) -> Result<Self> {
+        let urls: Vec<String> = s.split_whitespace().map(|u| u.to_owned()).collect();
+        if urls.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no server given",
+            ));
+        }
+        Ok(Self::new(urls, strategy))
+    }
+
+    pub fn set_retry_after(&mut self, d: Duration) {
+        self.retry_after = d;
+    }
+
+    pub fn set_max_message_size(&mut self, n: usize) {
+        self.max_message_size = n;
+    }
+
+    pub fn set_tls_config(&mut self, config: Arc<ClientConfig>) {
+        self.tls_config = Some(config);
+    }
+
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    pub fn is_down(&self, url: &str) -> bool {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        self.urls
+            .iter()
+            .zip(&state.down_until)
+            .any(|(u, d)| u == url && d.is_some_and(|t| now < t))
+    }
+
+    // for callers that saw a server misbehave on an open connection
+    pub fn mark_down(&self, url: &str) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + self.retry_after;
+        for (u, d) in self.urls.iter().zip(state.down_until.iter_mut()) {
+            if u == url {
+                *d = Some(until);
+            }
+        }
+    }
+
+    fn mark_up(&self, i: usize) {
+        self.state.lock().unwrap().down_until[i] = None;
+    }
+
+    // the servers to try, up ones by strategy and then the ones still marked down
+    fn order(&self) -> Vec<usize> {
+        let n = self.urls.len();
+        let mut state = self.state.lock().unwrap();
+        let mut order: Vec<usize> = match self.strategy {
+            Strategy::Failover => (0..n).collect(),
+            Strategy::RoundRobin => {
+                let start = state.next % n.max(1);
+                state.next = state.next.wrapping_add(1);
+                (0..n).map(|i| (start + i) % n).collect()
+            }
+            Strategy::Random => {
+                let mut order: Vec<usize> = (0..n).collect();
+                for i in (1..n).rev() {
+                    order.swap(i, (random() % (i as u64 + 1)) as usize);
+                }
+                order
+            }
+        };
+        let now = Instant::now();
+        order.sort_by_key(|&i| state.down_until[i].is_some_and(|t| now < t));
+        order
+    }
+
+    // connects to the first server in strategy order that answers, and the url it connected to
+    pub async fn connect_url(&self) -> Result<(ClientConnection, &str)> {
+        let mut last_error = None;
+        let tls_config = self
+            .tls_config
+            .clone()
+            .unwrap_or_else(client::default_tls_config);
+        for i in self.order() {
+            let url = &self.urls[i];
+            match client::connect_one(url, self.max_message_size, tls_config.clone()).await {
+                Ok(c) => {
+                    self.mark_up(i);
+                    return Ok((c, url));
+                }
+                Err(e) => {
+                    println!("connect to {} failed: {}", url, e);
+                    self.mark_down(url);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "no server given")
+        }))
+    }
+
+    pub async fn connect(&self) -> Result<ClientConnection> {
+        Ok(self.connect_url().await?.0)
+    }
+}
+
+#[tokio::test]
+async fn server_list_test() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let up = listener.local_addr().unwrap().to_string();
+    // nothing listens on a port that was just released
+    let down = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let mut servers = ServerList::parse(&format!("{}  {}", down, up), Strategy::Failover).unwrap();
+    let (_c, url) = servers.connect_url().await.unwrap();
+    assert_eq!(url, up);
+    assert!(servers.is_down(&down));
+    assert_eq!(servers.order(), vec![1, 0]);
+    servers.set_retry_after(Duration::ZERO);
+    servers.mark_down(&down);
+    assert_eq!(servers.order(), vec![0, 1]);
+    assert!(client::connect(&format!("{} {}", down, up)).await.is_ok());
+
+    let urls: Vec<String> = ["a:1", "b:1", "c:1"].map(|u| u.to_owned()).to_vec();
+    let rr = ServerList::new(urls.clone(), Strategy::RoundRobin);
+    assert_eq!(rr.order(), vec![0, 1, 2]);
+    assert_eq!(rr.order(), vec![1, 2, 0]);
+    rr.mark_down("c:1");
+    // c would go first this time but is down
+    assert_eq!(rr.order(), vec![0, 1, 2]);
+    assert_eq!(rr.order(), vec![0, 1, 2]);
+    assert_eq!(rr.order(), vec![1, 0, 2]);
+    let mut order = ServerList::new(urls, Strategy::Random).order();
+    order.sort();
+    assert_eq!(order, vec![0, 1, 2]);
+}
@@ -1,44 +1,297 @@
-use std::time::Instant;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::Result;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+// acquiring more than the capacity, or anything at rate zero, would wait forever
+fn never_available(n: u64) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{} tokens can never become available", n),
+    )
+}
+
+// speed is in tokens per millisecond, tokens never exceed capacity
 pub struct TokenBucket {
-    tokens: u64,
+    tokens: f64,
+    capacity: f64,
     speed: f64,
-    remain: f64,
     last_time: Instant,
 }
 
 impl TokenBucket {
+    // starts empty and holds at most one second worth of tokens
     pub fn new(speed: f64) -> Self {
+        Self::with_capacity(speed, (speed * 1000.0).max(1.0) as u64)
+    }
+    pub fn with_capacity(speed: f64, capacity: u64) -> Self {
         Self {
-            tokens: 0,
+            tokens: 0.0,
+            capacity: capacity as f64,
             speed,
-            remain: 0.0,
             last_time: Instant::now(),
         }
     }
-    /*pub fn set_speed(&mut self, s: f64) {
-        self.speed = s;
-    }*/
-    pub async fn get(&mut self) {
+    // starts with tokens available instead of empty
+    pub fn with_tokens(speed: f64, tokens: u64) -> Self {
+        let mut b = Self::new(speed);
+        b.capacity = b.capacity.max(tokens as f64);
+        b.tokens = tokens as f64;
+        b
+    }
+    pub fn set_rate(&mut self, speed: f64) {
+        // tokens earned so far count at the old rate
+        self.refill();
+        self.speed = speed;
+    }
+    pub fn set_capacity(&mut self, capacity: u64) {
+        self.capacity = capacity as f64;
+        self.tokens = self.tokens.min(self.capacity);
+    }
+    pub fn tokens(&mut self) -> f64 {
+        self.refill();
+        self.tokens
+    }
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_time).as_secs_f64() * 1000.0;
+        self.tokens = (self.tokens + elapsed * self.speed).min(self.capacity);
+        self.last_time = now;
+    }
+    pub fn try_acquire(&mut self, n: u64) -> bool {
+        self.refill();
+        if self.tokens >= n as f64 {
+            self.tokens -= n as f64;
+            return true;
+        }
+        false
+    }
+    // how long until n tokens are available, zero if they are now
+    pub fn wait_time(&mut self, n: u64) -> Duration {
+        self.refill();
+        let missing = n as f64 - self.tokens;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        if self.speed <= 0.0 || n as f64 > self.capacity {
+            return Duration::MAX;
+        }
+        Duration::from_secs_f64(missing / self.speed / 1000.0)
+    }
+    pub async fn acquire(&mut self, n: u64) -> Result<()> {
+        while !self.try_acquire(n) {
+            let wait = self.wait_time(n);
+            if wait == Duration::MAX {
+                return Err(never_available(n));
+            }
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+    pub async fn get(&mut self) -> Result<()> {
+        self.acquire(1).await
+    }
+}
+
+// a bucket that several tasks or connections take tokens from
+#[derive(Clone)]
+pub struct SharedTokenBucket {
+    inner: Arc<Mutex<TokenBucket>>,
+}
+
+impl SharedTokenBucket {
+    pub fn new(bucket: TokenBucket) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(bucket)),
+        }
+    }
+    pub fn set_rate(&self, speed: f64) {
+        self.inner.lock().unwrap().set_rate(speed);
+    }
+    pub fn try_acquire(&self, n: u64) -> bool {
+        self.inner.lock().unwrap().try_acquire(n)
+    }
+    pub async fn acquire(&self, n: u64) -> Result<()> {
         loop {
-            if self.tokens > 0 {
-                self.tokens -= 1;
+            // others may take the tokens while we sleep, so check again after
+            let wait = {
+                let mut b = self.inner.lock().unwrap();
+                if b.try_acquire(n) {
+                    return Ok(());
+                }
+                b.wait_time(n)
+            };
+            if wait == Duration::MAX {
+                return Err(never_available(n));
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+struct KeyedBucket {
+    bucket: TokenBucket,
+    last_used: Instant,
+    rejections: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyStats {
+    pub tokens: f64,
+    pub rejections: u64,
+}
+
+// one bucket per key, e.g. per source address or bind dn. buckets start full and
+// are dropped after ttl without use, so ttl should be at least the time to refill one
+pub struct KeyedRateLimiter<K> {
+    speed: f64,
+    capacity: u64,
+    ttl: Duration,
+    buckets: Mutex<HashMap<K, KeyedBucket>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl<K: Eq + Hash + Clone> KeyedRateLimiter<K> {
+    pub fn new(speed: f64, capacity: u64, ttl: Duration) -> Self {
+        Self {
+            speed,
+            capacity,
+            ttl,
+            buckets: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+    fn with_bucket<T>(&self, key: &K, f: impl FnOnce(&mut KeyedBucket) -> T) -> T {
+        self.sweep();
+        let mut buckets = self.buckets.lock().unwrap();
+        let b = buckets.entry(key.clone()).or_insert_with(|| {
+            let mut bucket = TokenBucket::with_tokens(self.speed, self.capacity);
+            bucket.set_capacity(self.capacity);
+            KeyedBucket {
+                bucket,
+                last_used: Instant::now(),
+                rejections: 0,
+            }
+        });
+        b.last_used = Instant::now();
+        f(b)
+    }
+    fn sweep(&self) {
+        let now = Instant::now();
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if now.duration_since(*last_sweep) < self.ttl {
                 return;
             }
-            let now = Instant::now();
-            let time_diff = now.duration_since(self.last_time);
-            let give = (time_diff.as_micros() as f64) * self.speed / 1000.0 + self.remain;
-            //self.remain = 0.0;
-            if give >= 1.0 {
-                let whole = give as u64;
-                self.remain = give - (whole as f64);
-                self.tokens += whole;
-                self.last_time = now;
-                continue;
+            *last_sweep = now;
+        }
+        self.evict_idle();
+    }
+    pub fn evict_idle(&self) {
+        let now = Instant::now();
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, b| now.duration_since(b.last_used) < self.ttl);
+    }
+    pub fn try_acquire(&self, key: &K, n: u64) -> bool {
+        self.with_bucket(key, |b| {
+            let ok = b.bucket.try_acquire(n);
+            if !ok {
+                b.rejections += 1;
             }
-            if self.tokens < 1 {
-                tokio::time::sleep(core::time::Duration::from_millis(20)).await;
+            ok
+        })
+    }
+    pub async fn acquire(&self, key: &K, n: u64) -> Result<()> {
+        loop {
+            let wait = self.with_bucket(key, |b| {
+                if b.bucket.try_acquire(n) {
+                    None
+                } else {
+                    Some(b.bucket.wait_time(n))
+                }
+            });
+            match wait {
+                Some(Duration::MAX) => return Err(never_available(n)),
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return Ok(()),
             }
         }
     }
+    pub fn stats(&self, key: &K) -> Option<KeyStats> {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.get_mut(key).map(|b| KeyStats {
+            tokens: b.bucket.tokens(),
+            rejections: b.rejections,
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[tokio::test]
+async fn token_bucket_test() {
+    // 100 per second, burst of 5
+    let mut b = TokenBucket::with_tokens(0.1, 5);
+    b.set_capacity(5);
+    assert!(b.try_acquire(3));
+    assert!(b.try_acquire(2));
+    assert!(!b.try_acquire(1));
+    assert!(!b.try_acquire(6));
+    assert_eq!(b.wait_time(6), Duration::MAX);
+    let start = Instant::now();
+    b.acquire(2).await.unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(15) && elapsed < Duration::from_millis(200));
+
+    // idle time does not build up more than the capacity
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(b.try_acquire(5));
+    assert!(!b.try_acquire(1));
+
+    assert!(b.acquire(6).await.is_err());
+    b.set_rate(0.0);
+    assert_eq!(b.wait_time(1), Duration::MAX);
+    assert!(b.acquire(1).await.is_err());
+
+    let shared = SharedTokenBucket::new(TokenBucket::with_capacity(0.2, 1));
+    let start = Instant::now();
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..4 {
+        let s = shared.clone();
+        tasks.spawn(async move { s.acquire(1).await.unwrap() });
+    }
+    while tasks.join_next().await.is_some() {}
+    // 4 tokens at 200 per second
+    assert!(start.elapsed() >= Duration::from_millis(18));
+    assert!(!shared.try_acquire(1));
+}
+
+#[test]
+fn keyed_rate_limiter_test() {
+    let l = KeyedRateLimiter::new(0.001, 2, Duration::from_millis(50));
+    assert!(l.is_empty());
+    assert!(l.try_acquire(&"a", 1));
+    assert!(l.try_acquire(&"a", 1));
+    assert!(!l.try_acquire(&"a", 1));
+    assert!(!l.try_acquire(&"a", 1));
+    assert!(l.try_acquire(&"b", 2));
+    let stats = l.stats(&"a").unwrap();
+    assert_eq!(stats.rejections, 2);
+    assert!(stats.tokens < 1.0);
+    assert_eq!(l.stats(&"c"), None);
+    assert_eq!(l.len(), 2);
+
+    // idle keys go away on the next use after the ttl
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(l.try_acquire(&"c", 1));
+    assert_eq!(l.len(), 1);
+    // a key that comes back starts full
+    assert!(l.try_acquire(&"a", 2));
 }
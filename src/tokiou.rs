@@ -2,11 +2,30 @@ use std::io::Result;
 
 use crate::ldap::Message;
 
-const MAX_SIZE: usize = 1024 * 32;
+const INITIAL_SIZE: usize = 1024 * 32;
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024 * 8;
+
+#[derive(Debug)]
+struct MessageTooLarge {
+    limit: usize,
+}
+
+impl std::fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message too large, limit is {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+pub fn is_message_too_large(e: &std::io::Error) -> bool {
+    e.get_ref().is_some_and(|i| i.is::<MessageTooLarge>())
+}
 
 pub struct DecodeContext {
-    buffer: [u8; MAX_SIZE],
+    buffer: Vec<u8>,
     have: usize,
+    max_size: usize,
 }
 
 impl DecodeContext {
@@ -20,6 +39,18 @@ impl DecodeContext {
                 Ok(r) => r,
                 Err(e) => {
                     if e.kind() == std::io::ErrorKind::WouldBlock {
+                        if self.have == self.buffer.len() {
+                            if self.have >= self.max_size {
+                                return Err(std::io::Error::new(
+                                    std::io::ErrorKind::InvalidData,
+                                    MessageTooLarge {
+                                        limit: self.max_size,
+                                    },
+                                ));
+                            }
+                            let size = (self.buffer.len() * 2).min(self.max_size);
+                            self.buffer.resize(size, 0);
+                        }
                         let res =
                             tokio::io::AsyncReadExt::read(s, &mut self.buffer[self.have..]).await?;
                         if res == 0 {
@@ -36,13 +67,22 @@ impl DecodeContext {
                 self.buffer.copy_within(parsed_size..self.have, 0);
             }
             self.have -= parsed_size;
+            // give back memory after a large message
+            if self.buffer.len() > INITIAL_SIZE && self.have <= INITIAL_SIZE {
+                self.buffer.truncate(INITIAL_SIZE);
+                self.buffer.shrink_to_fit();
+            }
             return Ok(parsed);
         }
     }
     pub fn new() -> Self {
+        Self::with_max_size(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+    pub fn with_max_size(max_size: usize) -> Self {
         Self {
-            buffer: [0; MAX_SIZE],
+            buffer: vec![0; INITIAL_SIZE.min(max_size)],
             have: 0,
+            max_size,
         }
     }
 }
@@ -52,3 +92,158 @@ impl Default for DecodeContext {
         Self::new()
     }
 }
+
+// framing for tokio_util::codec::Framed, so any AsyncRead/AsyncWrite carries ldap messages
+pub struct LdapCodec {
+    max_size: usize,
+}
+
+impl LdapCodec {
+    pub fn new() -> Self {
+        Self::with_max_size(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+    pub fn with_max_size(max_size: usize) -> Self {
+        Self { max_size }
+    }
+}
+
+impl Default for LdapCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl tokio_util::codec::Decoder for LdapCodec {
+    type Item = Message;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Message>> {
+        let too_large = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                MessageTooLarge {
+                    limit: self.max_size,
+                },
+            )
+        };
+        match crate::codec::parse_message(src) {
+            Ok((_, size)) if size > self.max_size => Err(too_large()),
+            Ok((m, size)) => {
+                bytes::Buf::advance(src, size);
+                Ok(Some(m))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if src.len() >= self.max_size {
+                    return Err(too_large());
+                }
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl tokio_util::codec::Encoder<Message> for LdapCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Message, dst: &mut bytes::BytesMut) -> Result<()> {
+        dst.extend_from_slice(&crate::codec::ldap_write_message(&item)?);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn large_message_test() {
+    let add = |id: u32, size: usize| {
+        crate::codec::ldap_write_add_request(
+            id,
+            &crate::ldap::MsgAdd {
+                entry: "cn=big,dc=example".to_owned(),
+                attributes: vec![crate::ldap::PartialAttribute {
+                    name: "description".to_owned(),
+                    values: vec!["x".repeat(size)],
+                }],
+            },
+        )
+        .unwrap()
+    };
+    let mut data = add(1, 100 * 1024);
+    data.extend(add(2, 10));
+    let mut r = data.as_slice();
+    let mut dec = DecodeContext::new();
+    assert_eq!(dec.get_message(&mut r).await.unwrap().id, 1);
+    assert_eq!(dec.get_message(&mut r).await.unwrap().id, 2);
+    assert!(!is_message_too_large(
+        &dec.get_message(&mut r).await.unwrap_err()
+    ));
+
+    let data = add(1, 100 * 1024);
+    let mut r = data.as_slice();
+    let mut dec = DecodeContext::with_max_size(64 * 1024);
+    let e = dec.get_message(&mut r).await.unwrap_err();
+    assert!(is_message_too_large(&e));
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn ldap_codec_test() {
+    use futures::{SinkExt, StreamExt};
+
+    let (a, b) = tokio::io::duplex(4096);
+    let mut client = tokio_util::codec::Framed::new(a, LdapCodec::new());
+    let mut server = tokio_util::codec::Framed::new(b, LdapCodec::new());
+    let search = crate::ldap::MsgSearch {
+        base_object: "dc=example".to_owned(),
+        scope: crate::ldap::SearchScope::WholeSubtree,
+        deref: crate::ldap::DerefAliases::NeverDerefAliases,
+        filter: crate::filter::parse("(&(objectClass=person)(cn=a*))").unwrap(),
+        size_limit: 0,
+        time_limit: 0,
+        types_only: false,
+        attributes: vec!["cn".to_owned(), "mail".to_owned()],
+    };
+    client
+        .send(Message {
+            id: 7,
+            params: crate::ldap::MessageParams::Search(search),
+            controls: Vec::new(),
+        })
+        .await
+        .unwrap();
+    let m = server.next().await.unwrap().unwrap();
+    assert_eq!(m.id, 7);
+    if let crate::ldap::MessageParams::Search(s) = m.params {
+        assert_eq!(s.base_object, "dc=example");
+        assert_eq!(s.filter.to_string(), "(&(objectClass=person)(cn=a*))");
+        assert_eq!(s.attributes, vec!["cn", "mail"]);
+    } else {
+        unreachable!();
+    }
+
+    let mut res = tokio_util::codec::FramedWrite::new(Vec::new(), LdapCodec::new());
+    for id in 0..3 {
+        res.send(Message {
+            id,
+            params: crate::ldap::MessageParams::MsgSearchResultDone(
+                crate::ldap::MsgSearchResultDone {
+                    res: crate::ldap::RESULT_SUCCESS,
+                    matched_dn: String::new(),
+                    diag: String::new(),
+                },
+            ),
+            controls: Vec::new(),
+        })
+        .await
+        .unwrap();
+    }
+    let data = res.into_inner();
+    let ids: Vec<u32> = tokio_util::codec::FramedRead::new(data.as_slice(), LdapCodec::new())
+        .map(|m| m.unwrap().id)
+        .collect()
+        .await;
+    assert_eq!(ids, vec![0, 1, 2]);
+
+    let big = crate::codec::ldap_write_bind_request(1, &"x".repeat(4096), "").unwrap();
+    let mut r = tokio_util::codec::FramedRead::new(big.as_slice(), LdapCodec::with_max_size(1024));
+    assert!(is_message_too_large(&r.next().await.unwrap().unwrap_err()));
+}
@@ -0,0 +1,303 @@
+use std::io::Result;
+
+use crate::ldap::SearchScope;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    Ldap,
+    Ldaps,
+    Ldapi,
+}
+
+impl Scheme {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Scheme::Ldap => 389,
+            Scheme::Ldaps => 636,
+            Scheme::Ldapi => 0,
+        }
+    }
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Ldap => "ldap",
+            Scheme::Ldaps => "ldaps",
+            Scheme::Ldapi => "ldapi",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlExtension {
+    pub critical: bool,
+    pub name: String,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LdapUrl {
+    pub scheme: Scheme,
+    // for ldapi this is the decoded socket path
+    pub host: String,
+    pub port: Option<u16>,
+    pub dn: String,
+    pub attributes: Vec<String>,
+    pub scope: Option<SearchScope>,
+    pub filter: Option<String>,
+    pub extensions: Vec<UrlExtension>,
+}
+
+pub const DEFAULT_LDAPI_PATH: &str = "/var/run/ldapi";
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("invalid ldap url: {}", msg),
+    )
+}
+
+fn decode(s: &str) -> Result<String> {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or_else(|| invalid("bad escape"))?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| invalid("bad escape"))?);
+            i += 3;
+        } else {
+            out.push(b[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| invalid("bad utf8"))
+}
+
+// percent-encodes everything outside of the unreserved and sub-delims set plus the given chars
+fn encode(s: &str, extra: &[u8]) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        let keep = b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/".contains(&b);
+        if keep && !extra.contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+impl LdapUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let (scheme, rest) = match url.split_once("://") {
+            Some(s) => s,
+            None => return Err(invalid("missing scheme")),
+        };
+        let scheme = match scheme.to_ascii_lowercase().as_str() {
+            "ldap" => Scheme::Ldap,
+            "ldaps" => Scheme::Ldaps,
+            "ldapi" => Scheme::Ldapi,
+            _ => return Err(invalid("unknown scheme")),
+        };
+        let (hostport, rest) = match rest.find('/') {
+            Some(p) => (&rest[..p], Some(&rest[p + 1..])),
+            None => (rest, None),
+        };
+        let (host, port) = if scheme == Scheme::Ldapi {
+            (decode(hostport)?, None)
+        } else if let Some(h) = hostport.strip_prefix('[') {
+            // ipv6 literal
+            let (h, p) = h.split_once(']').ok_or_else(|| invalid("bad ipv6 host"))?;
+            let port = match p.strip_prefix(':') {
+                Some(p) if !p.is_empty() => Some(p.parse().map_err(|_| invalid("bad port"))?),
+                _ => None,
+            };
+            (h.to_owned(), port)
+        } else {
+            match hostport.rsplit_once(':') {
+                Some((h, p)) if !p.is_empty() => (
+                    decode(h)?,
+                    Some(p.parse().map_err(|_| invalid("bad port"))?),
+                ),
+                Some((h, _)) => (decode(h)?, None),
+                None => (decode(hostport)?, None),
+            }
+        };
+        let mut out = LdapUrl {
+            scheme,
+            host,
+            port,
+            dn: String::new(),
+            attributes: Vec::new(),
+            scope: None,
+            filter: None,
+            extensions: Vec::new(),
+        };
+        let rest = match rest {
+            Some(r) => r,
+            None => return Ok(out),
+        };
+        let mut parts = rest.split('?');
+        out.dn = decode(parts.next().unwrap_or_default())?;
+        if let Some(attrs) = parts.next() {
+            for a in attrs.split(',').filter(|a| !a.is_empty()) {
+                out.attributes.push(decode(a)?);
+            }
+        }
+        if let Some(scope) = parts.next() {
+            out.scope = match scope.to_ascii_lowercase().as_str() {
+                "" => None,
+                "base" => Some(SearchScope::BaseObject),
+                "one" => Some(SearchScope::SingleLevel),
+                "sub" => Some(SearchScope::WholeSubtree),
+                _ => return Err(invalid("unknown scope")),
+            };
+        }
+        if let Some(filter) = parts.next() {
+            if !filter.is_empty() {
+                out.filter = Some(decode(filter)?);
+            }
+        }
+        if let Some(exts) = parts.next() {
+            for e in exts.split(',').filter(|e| !e.is_empty()) {
+                let (critical, e) = match e.strip_prefix('!') {
+                    Some(e) => (true, e),
+                    None => (false, e),
+                };
+                let (name, value) = match e.split_once('=') {
+                    Some((n, v)) => (decode(n)?, Some(decode(v)?)),
+                    None => (decode(e)?, None),
+                };
+                out.extensions.push(UrlExtension {
+                    critical,
+                    name,
+                    value,
+                });
+            }
+        }
+        if parts.next().is_some() {
+            return Err(invalid("too many '?'"));
+        }
+        Ok(out)
+    }
+
+    pub fn port_or_default(&self) -> u16 {
+        self.port.unwrap_or(self.scheme.default_port())
+    }
+
+    // host:port suitable for TcpStream::connect, an empty host means localhost
+    pub fn socket_address(&self) -> String {
+        let host = if self.host.is_empty() {
+            "localhost"
+        } else {
+            &self.host
+        };
+        if host.contains(':') {
+            format!("[{}]:{}", host, self.port_or_default())
+        } else {
+            format!("{}:{}", host, self.port_or_default())
+        }
+    }
+
+    pub fn socket_path(&self) -> &str {
+        if self.host.is_empty() {
+            DEFAULT_LDAPI_PATH
+        } else {
+            &self.host
+        }
+    }
+}
+
+impl std::fmt::Display for LdapUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://", self.scheme.as_str())?;
+        if self.scheme == Scheme::Ldapi {
+            f.write_str(&encode(&self.host, b"/:"))?;
+        } else if self.host.contains(':') {
+            write!(f, "[{}]", self.host)?;
+        } else {
+            f.write_str(&encode(&self.host, b"/:@"))?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        let scope = self.scope.map(|s| match s {
+            SearchScope::BaseObject => "base",
+            SearchScope::SingleLevel => "one",
+            SearchScope::WholeSubtree => "sub",
+        });
+        let attrs: Vec<String> = self.attributes.iter().map(|a| encode(a, b",")).collect();
+        let exts: Vec<String> = self
+            .extensions
+            .iter()
+            .map(|e| {
+                let mut s = String::new();
+                if e.critical {
+                    s.push('!');
+                }
+                s.push_str(&encode(&e.name, b",="));
+                if let Some(v) = &e.value {
+                    s.push('=');
+                    s.push_str(&encode(v, b","));
+                }
+                s
+            })
+            .collect();
+        let parts = [
+            encode(&self.dn, b""),
+            attrs.join(","),
+            scope.unwrap_or_default().to_owned(),
+            encode(self.filter.as_deref().unwrap_or_default(), b""),
+            exts.join(","),
+        ];
+        // trailing empty parts are left out
+        let used = parts.iter().rposition(|p| !p.is_empty());
+        if let Some(used) = used {
+            f.write_str("/")?;
+            f.write_str(&parts[..=used].join("?"))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for LdapUrl {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        LdapUrl::parse(s)
+    }
+}
+
+#[test]
+fn url_test() {
+    let u = LdapUrl::parse("ldap://host:389/ou=people,dc=x?cn,mail?sub?(uid=j*)").unwrap();
+    assert_eq!(u.scheme, Scheme::Ldap);
+    assert_eq!(u.host, "host");
+    assert_eq!(u.port, Some(389));
+    assert_eq!(u.dn, "ou=people,dc=x");
+    assert_eq!(u.attributes, vec!["cn", "mail"]);
+    assert_eq!(u.scope, Some(SearchScope::WholeSubtree));
+    assert_eq!(u.filter.as_deref(), Some("(uid=j*)"));
+    assert_eq!(
+        u.to_string(),
+        "ldap://host:389/ou=people,dc=x?cn,mail?sub?(uid=j*)"
+    );
+
+    let u = LdapUrl::parse("ldaps://[::1]/cn=a%3Fb??one??!x-ext=a%2Cb,e2").unwrap();
+    assert_eq!(u.host, "::1");
+    assert_eq!(u.socket_address(), "[::1]:636");
+    assert_eq!(u.dn, "cn=a?b");
+    assert_eq!(u.extensions.len(), 2);
+    assert!(u.extensions[0].critical);
+    assert_eq!(u.extensions[0].value.as_deref(), Some("a,b"));
+    assert_eq!(LdapUrl::parse(&u.to_string()).unwrap(), u);
+
+    let u = LdapUrl::parse("ldapi://%2Fvar%2Frun%2Fslapd%2Fldapi").unwrap();
+    assert_eq!(u.socket_path(), "/var/run/slapd/ldapi");
+    assert_eq!(u.to_string(), "ldapi://%2Fvar%2Frun%2Fslapd%2Fldapi");
+    assert_eq!(
+        LdapUrl::parse("ldap://").unwrap().socket_address(),
+        "localhost:389"
+    );
+    assert!(LdapUrl::parse("http://x").is_err());
+    assert!(LdapUrl::parse("ldap://x/??bad").is_err());
+}